@@ -8,6 +8,8 @@
 //! |-------|--------------|---------|
 //! | `forward` | one chunkwise pass over `[batch, sequence]` token ids | `run_parallel` |
 //! | `step`    | one recurrent decode step from the previous step's cache | `run_sequential` |
+//! | `import`  | [`load_mamba`] of the model exported to a `model.safetensors` | a cold start |
+//! | `record`  | the same load through a warm [`RecordCache`] | a `MAMBA_RECORD_CACHE` start |
 //!
 //! Cases are the [`ModelSpec::id`]s of [`hf::MODELS`] — `mamba1`, `mamba2`,
 //! `mamba3-siso`, `mamba3-mimo` — so a build decides what gets measured by its
@@ -105,6 +107,10 @@
 //! The defaults are the app's own regime — a single stream (`batch=1`) over a
 //! short prompt.
 //!
+//! `MAMBA_PRECISION` is read as the binary reads it, so `MAMBA_PRECISION=bf16`
+//! measures the cases in bf16, weights, activations and caches alike, and
+//! `bf16-mixed` with the recurrence parameters back in f32.
+//!
 //! ```bash
 //! BENCH_SEQ=1024 cargo bench --bench model --no-default-features \
 //!   --features "backend-cuda,mamba2" -- forward
@@ -119,8 +125,10 @@
 
 use burn::prelude::*;
 use burn_mamba::prelude::*;
+use burn_mamba_example::record_cache::RecordCache;
 use burn_mamba_example::{
-    ModelSpec, PRECISION_FLOAT_D_TYPE, PRECISION_INT_D_TYPE, hf, tie_lm_head,
+    Checkpoint, ModelSpec, RuntimePrecision, cast_mamba, hf, load_mamba, save_checkpoint,
+    tie_lm_head, vocab_size,
 };
 use criterion::measurement::WallTime;
 use criterion::{
@@ -185,8 +193,10 @@ impl Shape {
             let backend =
                 <burn::backend::Dispatch as burn::backend::Backend>::name(device.as_dispatch());
             eprintln!(
-                "bench-config: batch={batch} sequence={sequence} warmup_iters={} \
-                 budget={:.0?} samples={} simd={} backend={backend} models={:?}",
+                "bench-config: batch={batch} sequence={sequence} precision={} \
+                 warmup_iters={} budget={:.0?} samples={} simd={} backend={backend} \
+                 models={:?}",
+                precision(),
                 warmup_iters(),
                 budget(),
                 samples(),
@@ -234,9 +244,9 @@ fn env_usize(key: &str, default: usize) -> usize {
 // Devices and timing
 // ---------------------------------------------------------------------------
 
-/// The device every case runs on, with the crate's f32/i32 defaults installed —
-/// the same two lines every entry point starts with, so the bench measures the
-/// precision the app actually uses.
+/// The device every case runs on, with the [`precision`]'s float and i32
+/// installed as its defaults — the same two lines every entry point starts
+/// with, so the bench measures the precision the app actually uses.
 ///
 /// `configure` writes process-global per-device defaults and refuses a second
 /// call (`DeviceError::AlreadyInitialized`), so the [`Once`] is load-bearing:
@@ -251,12 +261,18 @@ fn device() -> Device {
     let mut device: Device = Default::default();
     CONFIGURED.call_once(|| {
         device
-            .configure((PRECISION_FLOAT_D_TYPE, PRECISION_INT_D_TYPE))
-            .expect("failed to install fp32/i32 device defaults");
+            .configure(precision().device_dtypes())
+            .expect("failed to install the float/i32 device defaults");
     });
     device
 }
 
+/// `MAMBA_PRECISION`, as the binary reads it: f32 unless it names `f16`, `bf16`
+/// or one of their `-mixed` variants.
+fn precision() -> RuntimePrecision {
+    RuntimePrecision::from_env().unwrap_or_else(|e| panic!("{e}"))
+}
+
 /// Block until every queued operation has actually run.
 ///
 /// The GPU backends are asynchronous: without this a measured iteration would
@@ -471,7 +487,12 @@ fn configure(group: &mut BenchmarkGroup<'_, WallTime>, tokens: u64) {
 /// Builds `spec`'s model with random weights, in the shape a loaded checkpoint
 /// would have had — see the module header on why the head is tied here.
 fn random_model(spec: &ModelSpec, device: &Device) -> MambaVocabNet {
-    let mut mamba = (spec.config)().init(device);
+    let mut mamba = spec.config().init(device);
+    // the device defaults already make it `precision()`'s float throughout; a
+    // mixed one still needs its f32 parameters cast back up
+    if precision().is_mixed() {
+        cast_mamba(&mut mamba, precision()).expect("failed to cast the model");
+    }
     tie_lm_head(&mut mamba, device);
     mamba
 }
@@ -483,7 +504,7 @@ fn random_model(spec: &ModelSpec, device: &Device) -> MambaVocabNet {
 /// then never a difference in inputs. Ids stay under `vocab_size`, since the
 /// padded tail of the embedding table is never a real token.
 fn token_ids(count: usize, spec: &ModelSpec) -> Vec<i32> {
-    let vocab = vocab_size(&(spec.config)()) as u64;
+    let vocab = vocab_size(&spec.config()) as u64;
     let mut state = 0x2545_F491_4F6C_DD1Du64;
     (0..count)
         .map(|_| {
@@ -507,19 +528,6 @@ fn input_id(shape: Shape, spec: &ModelSpec, device: &Device) -> Tensor<1, Int> {
     Tensor::from_data(token_ids(shape.batch, spec).as_slice(), device)
 }
 
-/// The checkpoint's *unpadded* vocabulary — the range a real token id lives in.
-#[allow(irrefutable_let_patterns)]
-fn vocab_size(config: &MambaVocabNetConfig) -> usize {
-    match config {
-        #[cfg(feature = "mamba1")]
-        MambaVocabNetConfig::Mamba1 { vocab_size, .. } => *vocab_size,
-        #[cfg(feature = "mamba2")]
-        MambaVocabNetConfig::Mamba2 { vocab_size, .. } => *vocab_size,
-        #[cfg(feature = "mamba3")]
-        MambaVocabNetConfig::Mamba3 { vocab_size, .. } => *vocab_size,
-    }
-}
-
 // ---------------------------------------------------------------------------
 // Groups
 // ---------------------------------------------------------------------------
@@ -534,7 +542,7 @@ fn bench_forward(
     model: &mut Option<MambaVocabNet>,
 ) {
     let plan = Plan::new("forward", spec.id);
-    let ssd_path = (spec.ssd_path)();
+    let ssd_path = spec.ssd_path();
     let mut input = None;
 
     let mut group = c.benchmark_group("forward");
@@ -588,7 +596,7 @@ fn bench_step(
         if caches.is_none() {
             caches = Some(burn_mamba_example::empty_caches(
                 shape.batch,
-                &(spec.config)(),
+                &spec.config(),
                 device,
             ));
             // Untimed: warm the decode kernels, advancing the cache as a real
@@ -612,7 +620,61 @@ fn bench_step(
     group.finish();
 }
 
-/// Both execution modes, over one set of weights per checkpoint.
+/// The two ways a run gets its model: importing the checkpoint ([load_mamba]:
+/// remapping, transposes, casts, the tied head) and reading back the record a
+/// [RecordCache] kept of an earlier import. The checkpoint is the random model,
+/// exported to a temporary directory; the record is written by the first
+/// untimed load.
+fn bench_startup(
+    c: &mut Criterion,
+    spec: &'static ModelSpec,
+    device: &Device,
+    model: &mut Option<MambaVocabNet>,
+) {
+    let dir = std::env::temp_dir().join(format!("bench-startup-{}", std::process::id()));
+    let mut exported = false;
+    for (case, cached) in [("import", false), ("record", true)] {
+        let plan = Plan::new(case, spec.id);
+        let cache = RecordCache::new(dir.join("records"));
+        let load = || {
+            let checkpoint = Checkpoint::File(dir.join("model.safetensors"));
+            let loaded = match cached {
+                true => cache.load(checkpoint, spec.config(), precision(), device),
+                false => load_mamba(checkpoint, spec.config(), precision(), device),
+            };
+            loaded.expect("failed to load the exported checkpoint")
+        };
+        let mut warm = false;
+
+        let mut group = c.benchmark_group(case);
+        // one whole model per iteration
+        configure(&mut group, 1);
+        group.bench_function(spec.id, |b| {
+            if !exported {
+                let mamba = model.get_or_insert_with(|| random_model(spec, device));
+                save_checkpoint(mamba, &spec.config(), &dir).expect("failed to export the model");
+                exported = true;
+            }
+            if !warm {
+                // Untimed: the record's first load writes it, and the page
+                // cache then holds both files, as on any start after the first.
+                for _ in 0..warmup_iters().max(1) {
+                    drop(load());
+                    sync(device);
+                }
+                warm = true;
+            }
+            b.iter_custom(|iters| timed(device, &plan, iters, &load))
+        });
+        group.finish();
+    }
+    if exported {
+        std::fs::remove_dir_all(&dir).expect("failed to remove the exported checkpoint");
+    }
+}
+
+/// Both execution modes and both ways to start, over one set of weights per
+/// checkpoint.
 fn bench_model(c: &mut Criterion) {
     let shape = Shape::from_env();
     let device = device();
@@ -624,6 +686,7 @@ fn bench_model(c: &mut Criterion) {
         let mut model = None;
         bench_forward(c, spec, shape, &device, &mut model);
         bench_step(c, spec, shape, &device, &mut model);
+        bench_startup(c, spec, &device, &mut model);
     }
 }
 
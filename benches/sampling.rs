@@ -0,0 +1,108 @@
+//! # Sampler benchmarks (`cargo bench --bench sampling`)
+//!
+//! The host-side half of a decode step: what [`LogitsProcessor::sample`] and
+//! [`sampling::apply_repeat_penalty`] cost once the logits are read back, per
+//! sampling strategy, at both vocabulary sizes the checkpoints use:
+//!
+//! | Vocabulary | Checkpoints |
+//! |------------|-------------|
+//! | 50288  | Mamba-1 / Mamba-2 130m (GPT-NeoX, padded) |
+//! | 128256 | Mamba-3 187m (Llama-3.1) |
+//!
+//! No model is involved, so no backend or model feature is needed:
+//!
+//! ```bash
+//! cargo bench --bench sampling
+//! ```
+//!
+//! The logits are fixed pseudo-random values with roughly the spread of a real
+//! model's (a few units around zero), so the nucleus of `top-p` is a few
+//! thousand tokens wide — wider than a confident model's, which only makes the
+//! partial selection work harder. Every case reuses one processor across
+//! iterations, as generation does, so its scratch buffers are warm.
+
+use burn_mamba_example::sampling::{self, LogitsProcessor, Sampling};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use rand::{Rng, SeedableRng};
+use std::hint::black_box;
+
+/// The padded vocabularies of the compiled-in checkpoints.
+const VOCAB_SIZES: [usize; 2] = [50288, 128256];
+
+/// Tokens [sampling::apply_repeat_penalty] looks back over — the run modes'
+/// `repeat_last_n`.
+const REPEAT_LAST_N: usize = 1024;
+
+/// Deterministic logits: the sum of four uniforms, a cheap bell shape.
+fn logits(vocab: usize) -> Vec<f32> {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(vocab as u64);
+    (0..vocab)
+        .map(|_| (0..4).map(|_| rng.random_range(-2.0..2.0f32)).sum())
+        .collect()
+}
+
+fn strategies() -> [(&'static str, Sampling); 5] {
+    let temperature = 0.8;
+    [
+        ("argmax", Sampling::ArgMax),
+        ("all", Sampling::All { temperature }),
+        ("top-k", Sampling::TopK { k: 40, temperature }),
+        (
+            "top-p",
+            Sampling::TopP {
+                p: 0.9,
+                temperature,
+            },
+        ),
+        (
+            "top-k-top-p",
+            Sampling::TopKThenTopP {
+                k: 40,
+                p: 0.9,
+                temperature,
+            },
+        ),
+    ]
+}
+
+fn bench_sample(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sample");
+    group.throughput(Throughput::Elements(1));
+    for vocab in VOCAB_SIZES {
+        let logits = logits(vocab);
+        for (name, sampling) in strategies() {
+            let mut processor = LogitsProcessor::from_sampling(299792458, sampling);
+            group.bench_with_input(BenchmarkId::new(name, vocab), &logits, |b, logits| {
+                b.iter(|| processor.sample(black_box(logits)).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_repeat_penalty(c: &mut Criterion) {
+    let mut group = c.benchmark_group("repeat-penalty");
+    group.throughput(Throughput::Elements(1));
+    for vocab in VOCAB_SIZES {
+        // penalized in place, over and over: the timing doesn't depend on values
+        let mut logits = logits(vocab);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let context: Vec<u32> = (0..REPEAT_LAST_N)
+            .map(|_| rng.random_range(0..vocab as u32))
+            .collect();
+        let mut seen = Vec::new();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(vocab),
+            &context,
+            |b, context| {
+                b.iter(|| {
+                    sampling::apply_repeat_penalty(&mut logits, 1.1, black_box(context), &mut seen)
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_sample, bench_repeat_penalty);
+criterion_main!(benches);
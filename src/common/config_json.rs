@@ -0,0 +1,755 @@
+//! The `state-spaces` checkpoints' `config.json`, read into the
+//! [MambaVocabNetConfig] and [MambaSsdPath] it describes.
+//!
+//! Every [ModelSpec::config] is still written out by hand, so the model builds
+//! before anything is fetched; [check_spec] compares it to what the checkpoint
+//! itself declares, so a drift between the two fails at load time instead of
+//! as a shape mismatch (or, worse, as silently wrong logits).
+//!
+//! The `transformers` conversions (`state-spaces/mamba-*-hf` and the fine-tunes
+//! made from them) name the same fields differently — `hidden_size`,
+//! `num_hidden_layers`, a top-level `state_size`, ... — and read into the same
+//! [ConfigJson].
+
+use crate::ModelSpec;
+use burn_mamba::prelude::*;
+
+/// The fields of a `config.json` that shape the model. Anything else (`rms_norm`,
+/// `fused_add_norm`, `residual_in_fp32`, ...) only affects how the reference
+/// implementation runs, and is ignored.
+///
+/// Serializes in the `state-spaces` naming, as [ConfigJson::from_vocab_net_config]
+/// writes it for an exported checkpoint.
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "RawConfigJson")]
+pub struct ConfigJson {
+    pub d_model: usize,
+    pub n_layer: usize,
+    pub vocab_size: usize,
+    /// 8 when unset.
+    pub pad_vocab_size_multiple: usize,
+    /// `0` is a mixer-only layer; otherwise the width of its gated MLP.
+    pub d_intermediate: usize,
+    pub ssm_cfg: SsmCfg,
+    /// Layers replaced by attention, which this example does not model.
+    pub attn_layer_idx: Vec<usize>,
+    /// `true` when unset.
+    pub tie_embeddings: bool,
+    /// Some exports put these next to `ssm_cfg` rather than inside it; see
+    /// [SsmCfg::mimo_rank] and [SsmCfg::chunk_size].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mimo_rank: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>,
+}
+
+/// The mixer's arguments, named as the reference implementation names them. A
+/// missing one takes that implementation's default for the [Self::layer].
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct SsmCfg {
+    /// `Mamba1` (the default), `Mamba2` or `Mamba3`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub d_state: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub d_conv: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expand: Option<usize>,
+    /// Mamba-1's rank of Δ's projection; `"auto"` reads as unset, which the
+    /// block derives from `d_model`.
+    #[serde(
+        default,
+        deserialize_with = "count_or_auto",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dt_rank: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headdim: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ngroups: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub norm_before_gate: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bias: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conv_bias: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_mimo: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mimo_rank: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_outproj_norm: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rope_fraction: Option<f64>,
+    #[serde(
+        rename = "A_floor",
+        alias = "a_floor",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub a_floor: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dt_min: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dt_max: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dt_init_floor: Option<f64>,
+}
+
+/// A `config.json` in either naming, before [ConfigJson] settles on one.
+/// `transformers` writes some fields under both names, so these are not serde
+/// aliases: the `mamba_ssm` name wins when both are there.
+#[derive(serde::Deserialize)]
+struct RawConfigJson {
+    d_model: Option<usize>,
+    hidden_size: Option<usize>,
+    n_layer: Option<usize>,
+    num_hidden_layers: Option<usize>,
+    vocab_size: usize,
+    #[serde(default = "default_pad_vocab_size_multiple")]
+    pad_vocab_size_multiple: usize,
+    #[serde(default)]
+    d_intermediate: usize,
+    #[serde(default)]
+    ssm_cfg: SsmCfg,
+    #[serde(default)]
+    attn_layer_idx: Vec<usize>,
+    tie_embeddings: Option<bool>,
+    tie_word_embeddings: Option<bool>,
+    mimo_rank: Option<usize>,
+    chunk_size: Option<usize>,
+    /// `mamba` or `mamba2`.
+    model_type: Option<String>,
+    state_size: Option<usize>,
+    conv_kernel: Option<usize>,
+    expand: Option<usize>,
+    #[serde(default, deserialize_with = "count_or_auto")]
+    time_step_rank: Option<usize>,
+    head_dim: Option<usize>,
+    n_groups: Option<usize>,
+    norm_before_gate: Option<bool>,
+    use_bias: Option<bool>,
+    use_conv_bias: Option<bool>,
+}
+
+impl TryFrom<RawConfigJson> for ConfigJson {
+    type Error = String;
+
+    fn try_from(raw: RawConfigJson) -> Result<Self, Self::Error> {
+        let mut ssm_cfg = raw.ssm_cfg;
+        if ssm_cfg.layer.is_none() {
+            ssm_cfg.layer = match raw.model_type.as_deref() {
+                Some("mamba2") => Some("Mamba2".into()),
+                _ => None,
+            };
+        }
+        ssm_cfg.d_state = ssm_cfg.d_state.or(raw.state_size);
+        ssm_cfg.d_conv = ssm_cfg.d_conv.or(raw.conv_kernel);
+        ssm_cfg.expand = ssm_cfg.expand.or(raw.expand);
+        // `Mamba2Config` has a `time_step_rank` too, which it never uses
+        if raw.model_type.as_deref() != Some("mamba2") {
+            ssm_cfg.dt_rank = ssm_cfg.dt_rank.or(raw.time_step_rank);
+        }
+        ssm_cfg.headdim = ssm_cfg.headdim.or(raw.head_dim);
+        ssm_cfg.ngroups = ssm_cfg.ngroups.or(raw.n_groups);
+        ssm_cfg.norm_before_gate = ssm_cfg.norm_before_gate.or(raw.norm_before_gate);
+        ssm_cfg.bias = ssm_cfg.bias.or(raw.use_bias);
+        ssm_cfg.conv_bias = ssm_cfg.conv_bias.or(raw.use_conv_bias);
+        Ok(Self {
+            d_model: raw
+                .d_model
+                .or(raw.hidden_size)
+                .ok_or("missing field `d_model` (or `hidden_size`)")?,
+            n_layer: raw
+                .n_layer
+                .or(raw.num_hidden_layers)
+                .ok_or("missing field `n_layer` (or `num_hidden_layers`)")?,
+            vocab_size: raw.vocab_size,
+            pad_vocab_size_multiple: raw.pad_vocab_size_multiple,
+            d_intermediate: raw.d_intermediate,
+            ssm_cfg,
+            attn_layer_idx: raw.attn_layer_idx,
+            tie_embeddings: raw
+                .tie_embeddings
+                .or(raw.tie_word_embeddings)
+                .unwrap_or_else(default_tie_embeddings),
+            mimo_rank: raw.mimo_rank,
+            chunk_size: raw.chunk_size,
+        })
+    }
+}
+
+/// A count, or `"auto"` for `None`.
+fn count_or_auto<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<usize>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum CountOrAuto {
+        Count(usize),
+        Auto(String),
+    }
+    match <Option<CountOrAuto> as serde::Deserialize>::deserialize(deserializer)? {
+        Some(CountOrAuto::Count(count)) => Ok(Some(count)),
+        Some(CountOrAuto::Auto(auto)) if auto == "auto" => Ok(None),
+        Some(CountOrAuto::Auto(other)) => Err(serde::de::Error::custom(format!(
+            "{other:?} is neither a count nor \"auto\""
+        ))),
+        None => Ok(None),
+    }
+}
+
+fn default_pad_vocab_size_multiple() -> usize {
+    8
+}
+
+fn default_tie_embeddings() -> bool {
+    true
+}
+
+/// Which mixer a [ConfigJson] describes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Mamba1,
+    Mamba2,
+    Mamba3,
+}
+
+impl ConfigJson {
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        serde_json::from_slice(bytes)
+            .map_err(|e| anyhow::anyhow!("config.json is not a Mamba config: {e}"))
+    }
+
+    pub fn layer(&self) -> anyhow::Result<Layer> {
+        match self.ssm_cfg.layer.as_deref() {
+            None | Some("Mamba1") => Ok(Layer::Mamba1),
+            Some("Mamba2") => Ok(Layer::Mamba2),
+            Some("Mamba3") => Ok(Layer::Mamba3),
+            Some(layer) => anyhow::bail!("unknown ssm_cfg.layer {layer:?}"),
+        }
+    }
+
+    /// The Mamba-3 MIMO rank: `1` unless the mixer is MIMO, in which case the
+    /// reference default is 4.
+    pub fn mimo_rank(&self) -> usize {
+        let rank = self.ssm_cfg.mimo_rank.or(self.mimo_rank);
+        match (self.ssm_cfg.is_mimo, rank) {
+            (Some(false), _) => 1,
+            (_, Some(rank)) => rank,
+            (Some(true), None) => 4,
+            (None, None) => 1,
+        }
+    }
+
+    /// The SSD chunk length the checkpoint was trained with, if it says.
+    pub fn chunk_size(&self) -> Option<usize> {
+        self.ssm_cfg.chunk_size.or(self.chunk_size)
+    }
+
+    /// The topology, built the way the hardcoded [ModelSpec::config]s are.
+    pub fn vocab_net_config(&self) -> anyhow::Result<MambaVocabNetConfig> {
+        anyhow::ensure!(
+            self.attn_layer_idx.is_empty(),
+            "attention layers ({:?}) are not supported",
+            self.attn_layer_idx
+        );
+        let ssm = &self.ssm_cfg;
+        #[allow(unused_variables)]
+        let mlp = (self.d_intermediate != 0)
+            .then(|| burn_mamba::modules::GatedMlpConfig::new(self.d_model, self.d_intermediate));
+        match self.layer()? {
+            #[cfg(feature = "mamba1")]
+            Layer::Mamba1 => Ok(MambaVocabNetConfig::Mamba1 {
+                n_real_layers: self.n_layer,
+                n_virtual_layers: None,
+                vocab_size: self.vocab_size,
+                pad_vocab_size_multiple: self.pad_vocab_size_multiple,
+                missing_lm_head: self.tie_embeddings,
+                ignore_first_residual: false,
+                ignore_last_residual: false,
+                residuals: ResidualsConfig::Standard,
+                mlp,
+                mamba_block: burn_mamba::mamba1::prelude::Mamba1Config::new(self.d_model)
+                    .with_state_rank(ssm.d_state.unwrap_or(16))
+                    .with_conv_kernel(ssm.d_conv.unwrap_or(4))
+                    .with_expand(ssm.expand.unwrap_or(2))
+                    // the default spelled out reads as the default, so that it
+                    // matches a hardcoded config leaving it unset
+                    .with_dt_rank(
+                        ssm.dt_rank
+                            .filter(|rank| *rank != self.d_model.div_ceil(16)),
+                    )
+                    .with_has_proj_bias(ssm.bias.unwrap_or(false))
+                    .with_has_conv_bias(ssm.conv_bias.unwrap_or(true)),
+            }),
+            #[cfg(feature = "mamba2")]
+            Layer::Mamba2 => Ok(MambaVocabNetConfig::Mamba2 {
+                n_real_layers: self.n_layer,
+                n_virtual_layers: None,
+                vocab_size: self.vocab_size,
+                pad_vocab_size_multiple: self.pad_vocab_size_multiple,
+                missing_lm_head: self.tie_embeddings,
+                ignore_first_residual: false,
+                ignore_last_residual: false,
+                residuals: ResidualsConfig::Standard,
+                mlp,
+                mamba_block: burn_mamba::mamba2::prelude::Mamba2Config::new(self.d_model)
+                    .with_state_rank(ssm.d_state.unwrap_or(128))
+                    .with_conv_kernel(ssm.d_conv.unwrap_or(4))
+                    .with_expand(ssm.expand.unwrap_or(2))
+                    .with_per_head_dim(ssm.headdim.unwrap_or(64))
+                    .with_ngroups(ssm.ngroups.unwrap_or(1))
+                    .with_is_norm_before_gate(ssm.norm_before_gate.unwrap_or(false))
+                    .with_has_proj_bias(ssm.bias.unwrap_or(false))
+                    .with_has_conv_bias(ssm.conv_bias.unwrap_or(true)),
+            }),
+            #[cfg(feature = "mamba3")]
+            Layer::Mamba3 => Ok(MambaVocabNetConfig::Mamba3 {
+                n_real_layers: self.n_layer,
+                n_virtual_layers: None,
+                vocab_size: self.vocab_size,
+                pad_vocab_size_multiple: self.pad_vocab_size_multiple,
+                missing_lm_head: self.tie_embeddings,
+                ignore_first_residual: false,
+                ignore_last_residual: false,
+                residuals: ResidualsConfig::Standard,
+                mlp,
+                mamba_block: burn_mamba::mamba3::prelude::Mamba3Config::new(self.d_model)
+                    .with_state_rank(ssm.d_state.unwrap_or(128))
+                    .with_expand(ssm.expand.unwrap_or(2))
+                    .with_per_head_dim(ssm.headdim.unwrap_or(64))
+                    .with_ngroups(ssm.ngroups.unwrap_or(1))
+                    .with_mimo_rank(self.mimo_rank())
+                    .with_rope_fraction(ssm.rope_fraction.unwrap_or(0.5) as _)
+                    .with_a_floor(ssm.a_floor.unwrap_or(1e-4) as _)
+                    .with_dt_min(ssm.dt_min.unwrap_or(1e-3) as _)
+                    .with_dt_max(ssm.dt_max.unwrap_or(0.1) as _)
+                    .with_dt_init_floor(ssm.dt_init_floor.unwrap_or(1e-4) as _)
+                    .with_has_outproj_norm(ssm.is_outproj_norm.unwrap_or(false))
+                    .with_has_proj_bias(ssm.bias.unwrap_or(false))
+                    // not a property of the checkpoint; see the hardcoded specs
+                    .with_siso_specialization_decode(false),
+            }),
+            #[allow(unreachable_patterns)]
+            layer => anyhow::bail!("{layer:?} layers are not compiled into this build"),
+        }
+    }
+
+    /// The scan the parallel path takes: the Mamba-2 and Mamba-3 ones chunk by
+    /// [Self::chunk_size], which the Mamba-3 reference defaults to 64.
+    pub fn ssd_path(&self) -> anyhow::Result<MambaSsdPath> {
+        match self.layer()? {
+            #[cfg(feature = "mamba1")]
+            Layer::Mamba1 => Ok(MambaSsdPath::Mamba1),
+            #[cfg(feature = "mamba2")]
+            Layer::Mamba2 => Ok(MambaSsdPath::Mamba2(Mamba2SsdPath::SerialRecalculated(
+                self.chunk_size(),
+            ))),
+            #[cfg(feature = "mamba3")]
+            Layer::Mamba3 => Ok(MambaSsdPath::Mamba3(Mamba3SsdPath::SerialRecalculated(
+                Some(self.chunk_size().unwrap_or(64)),
+            ))),
+            #[allow(unreachable_patterns)]
+            layer => anyhow::bail!("{layer:?} layers are not compiled into this build"),
+        }
+    }
+
+    /// The inverse of [Self::vocab_net_config]: the `config.json` an exported
+    /// checkpoint of `config` ships with, every mixer argument written out.
+    ///
+    /// `d_intermediate` is the built MLP's width (`0` without one), which
+    /// `GatedMlpConfig` may have rounded up from what its own config was given.
+    /// What a `config.json` cannot say — virtual layers, skipped or
+    /// non-standard residuals — is an error rather than silently dropped. The
+    /// scan is not a property of the weights, so `chunk_size` is left out.
+    pub fn from_vocab_net_config(
+        config: &MambaVocabNetConfig,
+        d_intermediate: usize,
+    ) -> anyhow::Result<Self> {
+        /// `f32` or `f64`, whichever the block config stores.
+        fn widen(value: impl Into<f64>) -> f64 {
+            value.into()
+        }
+        let (n_layer, d_model, vocab_size, pad_vocab_size_multiple, tied, ssm_cfg) = match config {
+            #[cfg(feature = "mamba1")]
+            MambaVocabNetConfig::Mamba1 {
+                n_real_layers,
+                n_virtual_layers,
+                vocab_size,
+                pad_vocab_size_multiple,
+                missing_lm_head,
+                ignore_first_residual,
+                ignore_last_residual,
+                residuals,
+                mamba_block: block,
+                ..
+            } => {
+                representable(
+                    n_virtual_layers.is_some(),
+                    *ignore_first_residual,
+                    *ignore_last_residual,
+                    residuals,
+                )?;
+                let ssm_cfg = SsmCfg {
+                    layer: Some("Mamba1".into()),
+                    d_state: Some(block.state_rank),
+                    d_conv: Some(block.conv_kernel),
+                    expand: Some(block.expand),
+                    dt_rank: block.dt_rank,
+                    bias: Some(block.has_proj_bias),
+                    conv_bias: Some(block.has_conv_bias),
+                    ..Default::default()
+                };
+                (
+                    *n_real_layers,
+                    block.d_model,
+                    *vocab_size,
+                    *pad_vocab_size_multiple,
+                    *missing_lm_head,
+                    ssm_cfg,
+                )
+            }
+            #[cfg(feature = "mamba2")]
+            MambaVocabNetConfig::Mamba2 {
+                n_real_layers,
+                n_virtual_layers,
+                vocab_size,
+                pad_vocab_size_multiple,
+                missing_lm_head,
+                ignore_first_residual,
+                ignore_last_residual,
+                residuals,
+                mamba_block: block,
+                ..
+            } => {
+                representable(
+                    n_virtual_layers.is_some(),
+                    *ignore_first_residual,
+                    *ignore_last_residual,
+                    residuals,
+                )?;
+                let ssm_cfg = SsmCfg {
+                    layer: Some("Mamba2".into()),
+                    d_state: Some(block.state_rank),
+                    d_conv: Some(block.conv_kernel),
+                    expand: Some(block.expand),
+                    headdim: Some(block.per_head_dim),
+                    ngroups: Some(block.ngroups),
+                    norm_before_gate: Some(block.is_norm_before_gate),
+                    bias: Some(block.has_proj_bias),
+                    conv_bias: Some(block.has_conv_bias),
+                    ..Default::default()
+                };
+                (
+                    *n_real_layers,
+                    block.d_model,
+                    *vocab_size,
+                    *pad_vocab_size_multiple,
+                    *missing_lm_head,
+                    ssm_cfg,
+                )
+            }
+            #[cfg(feature = "mamba3")]
+            MambaVocabNetConfig::Mamba3 {
+                n_real_layers,
+                n_virtual_layers,
+                vocab_size,
+                pad_vocab_size_multiple,
+                missing_lm_head,
+                ignore_first_residual,
+                ignore_last_residual,
+                residuals,
+                mamba_block: block,
+                ..
+            } => {
+                representable(
+                    n_virtual_layers.is_some(),
+                    *ignore_first_residual,
+                    *ignore_last_residual,
+                    residuals,
+                )?;
+                let ssm_cfg = SsmCfg {
+                    layer: Some("Mamba3".into()),
+                    d_state: Some(block.state_rank),
+                    expand: Some(block.expand),
+                    headdim: Some(block.per_head_dim),
+                    ngroups: Some(block.ngroups),
+                    is_mimo: Some(block.mimo_rank > 1),
+                    mimo_rank: Some(block.mimo_rank),
+                    is_outproj_norm: Some(block.has_outproj_norm),
+                    bias: Some(block.has_proj_bias),
+                    rope_fraction: Some(widen(block.rope_fraction)),
+                    a_floor: Some(widen(block.a_floor)),
+                    dt_min: Some(widen(block.dt_min)),
+                    dt_max: Some(widen(block.dt_max)),
+                    dt_init_floor: Some(widen(block.dt_init_floor)),
+                    ..Default::default()
+                };
+                (
+                    *n_real_layers,
+                    block.d_model,
+                    *vocab_size,
+                    *pad_vocab_size_multiple,
+                    *missing_lm_head,
+                    ssm_cfg,
+                )
+            }
+        };
+        Ok(Self {
+            d_model,
+            n_layer,
+            vocab_size,
+            pad_vocab_size_multiple,
+            d_intermediate,
+            ssm_cfg,
+            attn_layer_idx: Vec::new(),
+            tie_embeddings: tied,
+            mimo_rank: None,
+            chunk_size: None,
+        })
+    }
+}
+
+/// Whether [ConfigJson] can describe a network with these settings.
+fn representable(
+    virtual_layers: bool,
+    ignore_first_residual: bool,
+    ignore_last_residual: bool,
+    residuals: &ResidualsConfig,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !virtual_layers
+            && !ignore_first_residual
+            && !ignore_last_residual
+            && matches!(residuals, ResidualsConfig::Standard),
+        "virtual layers, skipped residuals and non-standard residuals have no \
+         config.json equivalent"
+    );
+    Ok(())
+}
+
+/// Fails, naming every differing field, when `spec`'s hardcoded topology or
+/// scan is not what `config` derives to.
+pub fn check_spec(spec: &ModelSpec, config: &ConfigJson) -> anyhow::Result<()> {
+    let hardcoded = serde_json::to_value(spec.config())?;
+    let derived = serde_json::to_value(config.vocab_net_config()?)?;
+    let mut drift = Vec::new();
+    differences("", &hardcoded, &derived, &mut drift);
+
+    let (hardcoded, derived) = (spec.ssd_path(), config.ssd_path()?);
+    if format!("{hardcoded:?}") != format!("{derived:?}") {
+        drift.push(format!(
+            "ssd_path: hardcoded {hardcoded:?}, config.json {derived:?}"
+        ));
+    }
+
+    anyhow::ensure!(
+        drift.is_empty(),
+        "the hardcoded {} config drifted from its config.json:\n  {}",
+        spec.id,
+        drift.join("\n  ")
+    );
+    Ok(())
+}
+
+/// Collects `path: hardcoded …, config.json …` for every leaf where the two
+/// serialized configs differ.
+fn differences(
+    path: &str,
+    hardcoded: &serde_json::Value,
+    derived: &serde_json::Value,
+    drift: &mut Vec<String>,
+) {
+    use serde_json::Value;
+    let join = |key: &str| match path {
+        "" => key.to_string(),
+        _ => format!("{path}.{key}"),
+    };
+    match (hardcoded, derived) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys: std::collections::BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let (a, b) = (a.get(key), b.get(key));
+                let (a, b) = (a.unwrap_or(&Value::Null), b.unwrap_or(&Value::Null));
+                differences(&join(key), a, b, drift);
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                differences(&join(&i.to_string()), a, b, drift);
+            }
+        }
+        (a, b) if a != b => {
+            let path = if path.is_empty() { "(root)" } else { path };
+            drift.push(format!("{path}: hardcoded {a}, config.json {b}"));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `state-spaces/mamba-130m`, verbatim.
+    const MAMBA1_130M: &str = r#"{"d_model": 768, "n_layer": 24, "vocab_size": 50277,
+        "ssm_cfg": {}, "rms_norm": true, "residual_in_fp32": true, "fused_add_norm": true,
+        "pad_vocab_size_multiple": 8}"#;
+
+    /// `state-spaces/mamba2-130m`, verbatim.
+    const MAMBA2_130M: &str = r#"{"d_model": 768, "d_intermediate": 0, "n_layer": 24,
+        "vocab_size": 50277, "ssm_cfg": {"layer": "Mamba2"}, "attn_layer_idx": [],
+        "attn_cfg": {}, "rms_norm": true, "residual_in_fp32": true, "fused_add_norm": true,
+        "pad_vocab_size_multiple": 16, "tie_embeddings": true}"#;
+
+    #[test]
+    fn missing_fields_take_the_reference_defaults() {
+        let mamba1 = ConfigJson::from_bytes(MAMBA1_130M.as_bytes()).unwrap();
+        assert_eq!(mamba1.layer().unwrap(), Layer::Mamba1);
+        assert_eq!((mamba1.d_intermediate, mamba1.tie_embeddings), (0, true));
+        assert_eq!((mamba1.mimo_rank(), mamba1.chunk_size()), (1, None));
+
+        let mamba2 = ConfigJson::from_bytes(MAMBA2_130M.as_bytes()).unwrap();
+        assert_eq!(mamba2.layer().unwrap(), Layer::Mamba2);
+        assert_eq!(mamba2.pad_vocab_size_multiple, 16);
+
+        let mimo = r#"{"d_model": 768, "n_layer": 12, "vocab_size": 128256,
+            "ssm_cfg": {"layer": "Mamba3", "is_mimo": true, "chunk_size": 16}}"#;
+        let mimo = ConfigJson::from_bytes(mimo.as_bytes()).unwrap();
+        assert_eq!((mimo.mimo_rank(), mimo.chunk_size()), (4, Some(16)));
+
+        let unknown = r#"{"d_model": 1, "n_layer": 1, "vocab_size": 1,
+            "ssm_cfg": {"layer": "Hyena"}}"#;
+        assert!(
+            ConfigJson::from_bytes(unknown.as_bytes())
+                .unwrap()
+                .layer()
+                .is_err()
+        );
+    }
+
+    /// `state-spaces/mamba-130m-hf`, verbatim: the `transformers` naming, with
+    /// `n_layer` written under both names.
+    const MAMBA1_130M_HF: &str = r#"{"architectures": ["MambaForCausalLM"],
+        "bos_token_id": 0, "conv_kernel": 4, "eos_token_id": 0, "expand": 2,
+        "fused_add_norm": true, "hidden_act": "silu", "hidden_size": 768,
+        "initializer_range": 0.1, "intermediate_size": 1536, "layer_norm_epsilon": 1e-05,
+        "model_type": "mamba", "n_layer": 24, "num_hidden_layers": 24, "pad_token_id": 0,
+        "pad_vocab_size_multiple": 8, "rescale_prenorm_residual": false,
+        "residual_in_fp32": true, "rms_norm": true, "state_size": 16,
+        "time_step_floor": 0.0001, "time_step_init_scheme": "random", "time_step_max": 0.1,
+        "time_step_min": 0.001, "time_step_rank": 48, "time_step_scale": 1.0,
+        "torch_dtype": "float32", "transformers_version": "4.39.0.dev0", "use_bias": false,
+        "use_cache": true, "use_conv_bias": true, "vocab_size": 50280}"#;
+
+    #[test]
+    fn transformers_names_read_into_the_same_fields() {
+        let hf = ConfigJson::from_bytes(MAMBA1_130M_HF.as_bytes()).unwrap();
+        assert_eq!(hf.layer().unwrap(), Layer::Mamba1);
+        assert_eq!((hf.d_model, hf.n_layer, hf.vocab_size), (768, 24, 50280));
+        // `intermediate_size` is the mixer's inner width, not an MLP's.
+        assert_eq!((hf.d_intermediate, hf.tie_embeddings), (0, true));
+        assert_eq!((hf.ssm_cfg.d_state, hf.ssm_cfg.d_conv), (Some(16), Some(4)));
+        assert_eq!(
+            (hf.ssm_cfg.bias, hf.ssm_cfg.conv_bias),
+            (Some(false), Some(true))
+        );
+        assert_eq!(hf.ssm_cfg.dt_rank, Some(48));
+
+        let auto = r#"{"d_model": 768, "n_layer": 24, "vocab_size": 50277,
+            "ssm_cfg": {"dt_rank": "auto"}}"#;
+        assert_eq!(
+            ConfigJson::from_bytes(auto.as_bytes())
+                .unwrap()
+                .ssm_cfg
+                .dt_rank,
+            None
+        );
+        let bad = r#"{"d_model": 768, "n_layer": 24, "vocab_size": 50277,
+            "ssm_cfg": {"dt_rank": "half"}}"#;
+        assert!(ConfigJson::from_bytes(bad.as_bytes()).is_err());
+
+        let mamba2 = r#"{"model_type": "mamba2", "hidden_size": 768, "num_hidden_layers": 24,
+            "vocab_size": 50288, "head_dim": 64, "n_groups": 1, "state_size": 128,
+            "tie_word_embeddings": false}"#;
+        let mamba2 = ConfigJson::from_bytes(mamba2.as_bytes()).unwrap();
+        assert_eq!(mamba2.layer().unwrap(), Layer::Mamba2);
+        assert_eq!(
+            (mamba2.ssm_cfg.headdim, mamba2.tie_embeddings),
+            (Some(64), false)
+        );
+
+        assert!(ConfigJson::from_bytes(br#"{"n_layer": 1, "vocab_size": 1}"#).is_err());
+    }
+
+    #[test]
+    fn differences_name_the_drifted_fields() {
+        let hardcoded = serde_json::json!({"n": 24, "block": {"d_state": 16, "bias": false}});
+        let derived = serde_json::json!({"n": 24, "block": {"d_state": 32, "bias": false}});
+        let mut drift = Vec::new();
+        differences("", &hardcoded, &derived, &mut drift);
+        assert_eq!(drift, ["block.d_state: hardcoded 16, config.json 32"]);
+    }
+
+    #[cfg(feature = "mamba1")]
+    #[test]
+    fn mamba1_130m_matches_its_config_json() {
+        let config = ConfigJson::from_bytes(MAMBA1_130M.as_bytes()).unwrap();
+        check_spec(&crate::hf::mamba1_130m::SPEC, &config).unwrap();
+    }
+
+    #[cfg(feature = "mamba2")]
+    #[test]
+    fn mamba2_130m_matches_its_config_json() {
+        let config = ConfigJson::from_bytes(MAMBA2_130M.as_bytes()).unwrap();
+        check_spec(&crate::hf::mamba2_130m::SPEC, &config).unwrap();
+
+        let drifted = MAMBA2_130M.replace("\"n_layer\": 24", "\"n_layer\": 48");
+        let drifted = ConfigJson::from_bytes(drifted.as_bytes()).unwrap();
+        let error = check_spec(&crate::hf::mamba2_130m::SPEC, &drifted).unwrap_err();
+        assert!(error.to_string().contains("n_real_layers"), "{error}");
+    }
+
+    /// `state-spaces/mamba3-siso-187m`'s model fields, as its spec records them:
+    /// the training-only keys of the real file are left out.
+    #[cfg(feature = "mamba3")]
+    const MAMBA3_SISO_187M: &str = r#"{"d_model": 768, "d_intermediate": 1536, "n_layer": 12,
+        "vocab_size": 128256, "ssm_cfg": {"layer": "Mamba3", "d_state": 128, "expand": 2,
+        "headdim": 64, "ngroups": 1, "is_mimo": false, "is_outproj_norm": false},
+        "attn_layer_idx": [], "attn_cfg": {}, "rms_norm": true, "residual_in_fp32": true,
+        "fused_add_norm": true, "pad_vocab_size_multiple": 16, "tie_embeddings": true}"#;
+
+    /// `state-spaces/mamba3-mimo-187m`'s model fields, likewise: the same shape
+    /// but for rank-4 MIMO, a narrower MLP and the 16-long chunks it trained with.
+    #[cfg(feature = "mamba3")]
+    const MAMBA3_MIMO_187M: &str = r#"{"d_model": 768, "d_intermediate": 1264, "n_layer": 12,
+        "vocab_size": 128256, "ssm_cfg": {"layer": "Mamba3", "d_state": 128, "expand": 2,
+        "headdim": 64, "ngroups": 1, "is_mimo": true, "mimo_rank": 4, "chunk_size": 16,
+        "is_outproj_norm": false}, "attn_layer_idx": [], "attn_cfg": {}, "rms_norm": true,
+        "residual_in_fp32": true, "fused_add_norm": true, "pad_vocab_size_multiple": 16,
+        "tie_embeddings": true}"#;
+
+    #[cfg(feature = "mamba3")]
+    #[test]
+    fn mamba3_187m_match_their_config_json() {
+        let siso = ConfigJson::from_bytes(MAMBA3_SISO_187M.as_bytes()).unwrap();
+        assert_eq!((siso.mimo_rank(), siso.chunk_size()), (1, None));
+        check_spec(&crate::hf::mamba3_siso_187m::SPEC, &siso).unwrap();
+
+        let mimo = ConfigJson::from_bytes(MAMBA3_MIMO_187M.as_bytes()).unwrap();
+        assert_eq!((mimo.mimo_rank(), mimo.chunk_size()), (4, Some(16)));
+        check_spec(&crate::hf::mamba3_mimo_187m::SPEC, &mimo).unwrap();
+
+        // without its `chunk_size` the MIMO checkpoint reads as the reference's
+        // 64, which the spec's 16 must catch
+        let defaulted = MAMBA3_MIMO_187M.replace(r#" "chunk_size": 16,"#, "");
+        let defaulted = ConfigJson::from_bytes(defaulted.as_bytes()).unwrap();
+        assert_eq!(defaulted.chunk_size(), None);
+        let error = check_spec(&crate::hf::mamba3_mimo_187m::SPEC, &defaulted).unwrap_err();
+        assert!(error.to_string().contains("ssd_path"), "{error}");
+        assert!(error.to_string().contains("Some(64)"), "{error}");
+    }
+}
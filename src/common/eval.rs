@@ -0,0 +1,349 @@
+//! Scoring text with the model, rather than sampling from it.
+//!
+//! Everything here reads log-probabilities off `MambaVocabNet::forward`, on the
+//! device: a window's `[1, len, vocab]` log-softmax is reduced to the `len`
+//! log-probabilities of the tokens that actually follow before anything is read
+//! back.
+
+use crate::{MambaWrapper, PRECISION_FLOAT_D_TYPE, Precision};
+use burn::prelude::*;
+use burn::tensor::activation::log_softmax;
+use burn_mamba::prelude::*;
+
+/// The likelihood of a text under the model, accumulated window by window.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Perplexity {
+    /// How many tokens were predicted.
+    pub tokens: usize,
+    /// The UTF-8 length of the text they encode.
+    pub bytes: usize,
+    /// Summed negative log-likelihood of the predicted tokens, in nats.
+    pub nll: f64,
+}
+
+impl Perplexity {
+    /// Mean negative log-likelihood per token, in nats.
+    pub fn mean_nll(&self) -> f64 {
+        self.nll / self.tokens as f64
+    }
+
+    pub fn perplexity(&self) -> f64 {
+        self.mean_nll().exp()
+    }
+
+    /// Bits per UTF-8 byte — unlike the two above, comparable across tokenizers,
+    /// so across the GPT-NeoX and Llama-3 checkpoints.
+    pub fn bits_per_byte(&self) -> f64 {
+        self.nll / std::f64::consts::LN_2 / self.bytes as f64
+    }
+}
+
+/// How likely a continuation is after its context; see
+/// [MambaWrapper::log_likelihoods].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogLikelihood {
+    /// Summed log-probability of the continuation's tokens, in nats.
+    pub log_prob: f64,
+    /// Whether each token was the most likely one at its position.
+    pub is_greedy: bool,
+    /// How many tokens the continuation is.
+    pub tokens: usize,
+}
+
+/// One multiple-choice question, read from a JSONL line by [Self::from_json].
+#[derive(Clone, Debug, PartialEq)]
+pub struct MultipleChoice {
+    pub context: String,
+    /// Each appended to `context` as is, leading space included.
+    pub choices: Vec<String>,
+    /// The index of the right choice.
+    pub label: usize,
+}
+
+impl MultipleChoice {
+    /// Reads one of the layouts lm-eval-harness uses, told apart by their fields:
+    ///
+    /// - LAMBADA, `{"text"}`: the last word is the only choice, and it is right
+    ///   when greedy decoding produces it;
+    /// - HellaSwag, `{"ctx", "endings", "label"}`;
+    /// - PIQA, `{"goal", "sol1", "sol2", "label"}`, asked as
+    ///   `Question: {goal}\nAnswer:`.
+    ///
+    /// A `label` may be a number or a string holding one, as HellaSwag's is.
+    pub fn from_json(line: &str) -> anyhow::Result<Self> {
+        let json: serde_json::Value = serde_json::from_str(line)?;
+        let field = |name: &str| json.get(name).and_then(serde_json::Value::as_str);
+        let label = || -> anyhow::Result<usize> {
+            match json.get("label") {
+                Some(serde_json::Value::Number(label)) => label.as_u64().map(|l| l as usize),
+                Some(serde_json::Value::String(label)) => label.trim().parse().ok(),
+                _ => None,
+            }
+            .ok_or_else(|| anyhow::anyhow!("missing or invalid label in {line:?}"))
+        };
+
+        if let Some(text) = field("text") {
+            let (context, word) = text
+                .trim_end()
+                .rsplit_once(' ')
+                .ok_or_else(|| anyhow::anyhow!("no last word to predict in {text:?}"))?;
+            Ok(Self {
+                context: context.into(),
+                choices: vec![format!(" {word}")],
+                label: 0,
+            })
+        } else if let (Some(context), Some(endings)) = (field("ctx"), json.get("endings")) {
+            let choices = endings
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(serde_json::Value::as_str)
+                .map(|ending| format!(" {ending}"))
+                .collect();
+            Ok(Self {
+                context: context.into(),
+                choices,
+                label: label()?,
+            })
+        } else if let (Some(goal), Some(sol1), Some(sol2)) =
+            (field("goal"), field("sol1"), field("sol2"))
+        {
+            Ok(Self {
+                context: format!("Question: {goal}\nAnswer:"),
+                choices: vec![format!(" {sol1}"), format!(" {sol2}")],
+                label: label()?,
+            })
+        } else {
+            anyhow::bail!("not a LAMBADA, HellaSwag or PIQA line: {line:?}")
+        }
+    }
+}
+
+impl MambaWrapper {
+    /// Scores `text` with [Perplexity]: it is encoded whole, then fed through
+    /// `forward` `window` tokens at a time, each window starting from the caches
+    /// the previous one left, so every token is predicted from the whole text
+    /// before it at the cost of a single pass.
+    ///
+    /// The text is a document as [Self::document_tokens] makes it.
+    pub fn perplexity(&mut self, text: &str, window: usize) -> anyhow::Result<Perplexity> {
+        anyhow::ensure!(window > 0, "the window must hold at least one token");
+        let tokens = self.document_tokens(text)?;
+        anyhow::ensure!(tokens.len() > 1, "{text:?} is too short to predict a token");
+
+        let mut score = Perplexity {
+            tokens: tokens.len() - 1,
+            bytes: text.len(),
+            nll: 0.,
+        };
+        let mut caches = None;
+        for start in (0..tokens.len() - 1).step_by(window) {
+            let end = (start + window).min(tokens.len() - 1);
+            let (log_probs, new_caches) =
+                self.target_log_probs(&tokens[start..end], &tokens[start + 1..end + 1], caches)?;
+            caches = Some(new_caches);
+            score.nll -= log_probs.iter().map(|l| *l as f64).sum::<f64>();
+        }
+        Ok(score)
+    }
+
+    /// Scores each `(context, continuation)` pair: the summed log-probability of
+    /// the continuation's tokens after the context, whether greedy decoding would
+    /// have produced them, and how many there are — lm-eval-harness's
+    /// `loglikelihood` request.
+    ///
+    /// The context goes through `forward` once for a run of pairs that share it:
+    /// each continuation then starts from a clone of its caches. Contexts are
+    /// encoded like [Self::perplexity]'s text; continuations on their own, without
+    /// the tokenizer's prefix.
+    pub fn log_likelihoods(
+        &mut self,
+        pairs: &[(&str, &str)],
+    ) -> anyhow::Result<Vec<LogLikelihood>> {
+        // the last context run: its text, its caches and its last position's
+        // `[1, 1, vocab]` log-probabilities
+        let mut context_run: Option<(&str, MambaCaches, Tensor<3>)> = None;
+        let mut scores = Vec::with_capacity(pairs.len());
+        for (context, continuation) in pairs {
+            if context_run
+                .as_ref()
+                .is_none_or(|(text, _, _)| text != context)
+            {
+                let tokens = self.document_tokens(context)?;
+                let (log_probs, caches) = self.forward_log_probs(&tokens, None)?;
+                let last = log_probs.narrow(1, tokens.len() - 1, 1);
+                context_run = Some((context, caches, last));
+            }
+            let (_, caches, last) = context_run.as_ref().expect("the context just ran");
+
+            let prefix_len = self.tokenizer.tokenizer().encode("").len();
+            let targets = self.tokenizer.tokenizer().encode(continuation)[prefix_len..]
+                .iter()
+                .map(|t| *t as usize)
+                .collect::<Vec<_>>();
+            anyhow::ensure!(!targets.is_empty(), "empty continuation {continuation:?}");
+
+            // the first token follows the context; the others, the continuation
+            let log_probs = if targets.len() == 1 {
+                last.clone()
+            } else {
+                let inputs = &targets[..targets.len() - 1];
+                let (log_probs, _) = self.forward_log_probs(inputs, Some(caches.clone()))?;
+                Tensor::cat(vec![last.clone(), log_probs], 1)
+            };
+            let greedy = log_probs
+                .clone()
+                .argmax(2)
+                .into_data()
+                .convert::<i64>()
+                .into_vec::<i64>()
+                .map_err(|e| anyhow::anyhow!("failed to read the greedy tokens back: {e:?}"))?;
+            let log_probs = gather_targets(log_probs, &targets);
+            scores.push(LogLikelihood {
+                log_prob: log_probs.iter().map(|l| *l as f64).sum(),
+                is_greedy: greedy.iter().zip(&targets).all(|(g, t)| *g as usize == *t),
+                tokens: targets.len(),
+            });
+        }
+        Ok(scores)
+    }
+
+    /// `text`'s tokens as a document: a tokenizer that prepends nothing
+    /// (GPT-NeoX) gets the eos token prepended, the document separator the
+    /// checkpoints were trained with, so that the first token is predicted too.
+    fn document_tokens(&mut self, text: &str) -> anyhow::Result<Vec<usize>> {
+        let (mut tokens, eos_token) = self.reset_prompt(text)?;
+        if self.tokenizer.tokenizer().encode("").is_empty() {
+            tokens.insert(0, eos_token);
+        }
+        Ok(tokens)
+    }
+
+    /// Runs `inputs` through `forward` from `caches`, and returns the
+    /// log-probability of each of `targets` — the token that follows each input.
+    pub fn target_log_probs(
+        &self,
+        inputs: &[usize],
+        targets: &[usize],
+        caches: Option<MambaCaches>,
+    ) -> anyhow::Result<(Vec<Precision>, MambaCaches)> {
+        let (log_probs, caches) = self.forward_log_probs(inputs, caches)?;
+        Ok((gather_targets(log_probs, targets), caches))
+    }
+
+    /// Runs `inputs` through `forward` from `caches`, returning the
+    /// `[1, len, vocab]` log-softmax of every position, still on the device.
+    fn forward_log_probs(
+        &self,
+        inputs: &[usize],
+        caches: Option<MambaCaches>,
+    ) -> anyhow::Result<(Tensor<3>, MambaCaches)> {
+        anyhow::ensure!(!inputs.is_empty(), "cannot run an empty window");
+        let device = crate::device(&self.mamba);
+        let input: Tensor<1, Int> = Tensor::from_data(inputs, &device);
+        let ssd_path = self.spec.ssd_path();
+        let (logits, caches) = self
+            .mamba
+            .forward(input.unsqueeze(), caches, ssd_path, None);
+        let log_probs = log_softmax(logits.cast(PRECISION_FLOAT_D_TYPE), 2);
+        Ok((log_probs, caches))
+    }
+}
+
+/// The log-probability, at each position of `log_probs`, `[1, len, vocab]`, of
+/// that position's token in `targets`, `[len]`.
+fn gather_targets(log_probs: Tensor<3>, targets: &[usize]) -> Vec<Precision> {
+    let device = log_probs.device();
+    let targets: Tensor<1, Int> = Tensor::from_data(targets, &device);
+    let targets = targets.unsqueeze::<2>().unsqueeze_dim::<3>(2);
+    log_probs
+        .gather(2, targets)
+        .into_data()
+        .into_vec::<Precision>()
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perplexity_metrics_follow_the_summed_nll() {
+        // four tokens at probability 1/2, over two bytes each
+        let score = Perplexity {
+            tokens: 4,
+            bytes: 8,
+            nll: 4. * std::f64::consts::LN_2,
+        };
+        assert!((score.mean_nll() - std::f64::consts::LN_2).abs() < 1e-12);
+        assert!((score.perplexity() - 2.).abs() < 1e-12);
+        assert!((score.bits_per_byte() - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn multiple_choice_layouts_are_told_apart() {
+        let lambada = MultipleChoice::from_json(r#"{"text": "the cat sat on the mat"}"#);
+        assert_eq!(
+            lambada.unwrap(),
+            MultipleChoice {
+                context: "the cat sat on the".into(),
+                choices: vec![" mat".into()],
+                label: 0,
+            }
+        );
+
+        let hellaswag =
+            r#"{"ctx": "He opens the jar", "endings": ["and eats.", "and flies."], "label": "1"}"#;
+        let hellaswag = MultipleChoice::from_json(hellaswag).unwrap();
+        assert_eq!(hellaswag.choices, vec![" and eats.", " and flies."]);
+        assert_eq!(hellaswag.label, 1);
+
+        let piqa = r#"{"goal": "Dry a towel", "sol1": "Hang it.", "sol2": "Wet it.", "label": 0}"#;
+        let piqa = MultipleChoice::from_json(piqa).unwrap();
+        assert_eq!(piqa.context, "Question: Dry a towel\nAnswer:");
+        assert_eq!(piqa.choices.len(), 2);
+
+        assert!(MultipleChoice::from_json(r#"{"question": "?"}"#).is_err());
+    }
+
+    /// Scoring pairs off a shared context run sums what one `forward` over
+    /// the whole document gives the continuation's tokens.
+    #[test]
+    fn log_likelihoods_sum_the_continuation_log_probs() {
+        use crate::verify::{byte_tokenizer, random_model, tiny_specs};
+        let device: Device = Default::default();
+        let pairs = [
+            ("the cat sat on the", " mat"),
+            ("the cat sat on the", " hat"),
+        ];
+        for spec in tiny_specs() {
+            let mamba = random_model(spec, &device);
+            let mut wrapper = MambaWrapper::new(spec, byte_tokenizer(), mamba).unwrap();
+            let scores = wrapper.log_likelihoods(&pairs).unwrap();
+
+            for ((context, continuation), score) in pairs.iter().zip(&scores) {
+                let encode = |text: &str| wrapper.tokenizer.tokenizer().encode(text);
+                let continuation = encode(continuation);
+                let mut document = vec![256];
+                document.extend(encode(context).into_iter().chain(continuation.clone()));
+                let document: Vec<usize> = document.into_iter().map(|t| t as usize).collect();
+                let (log_probs, _) = wrapper
+                    .target_log_probs(&document[..document.len() - 1], &document[1..], None)
+                    .unwrap();
+                let expected: f64 = log_probs[log_probs.len() - continuation.len()..]
+                    .iter()
+                    .map(|l| *l as f64)
+                    .sum();
+
+                assert_eq!(score.tokens, continuation.len(), "{}", spec.id);
+                assert!(score.log_prob < 0., "{}: {score:?}", spec.id);
+                assert!(
+                    (score.log_prob - expected).abs() < 1e-3 * expected.abs(),
+                    "{}: {} against {expected}",
+                    spec.id,
+                    score.log_prob
+                );
+            }
+        }
+    }
+}
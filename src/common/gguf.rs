@@ -0,0 +1,896 @@
+//! llama.cpp's GGUF checkpoints of Mamba and Mamba-2, often quantized.
+//!
+//! A [Gguf] describes its own topology in its metadata, which reads into the
+//! same [ConfigJson] a `config.json` does. Its tensors are renamed to the
+//! `state-spaces` naming and dequantized, one at a time, to safetensors bytes
+//! of the [crate::RuntimePrecision]'s float, so the load itself is the
+//! [crate::load_mamba] every other checkpoint goes through, with its remapping
+//! and its cast.
+//!
+//! Only the tensor types llama.cpp quantizes Mamba checkpoints to in practice
+//! are read: F32, F16, BF16, Q8_0, Q4_0 and Q4_K. Anything else is refused by
+//! name rather than loaded wrong.
+//!
+//! The llama.cpp converter does not store every tensor as it found it:
+//! - `A_log` is stored as `A = -exp(A_log)`, and is taken back by `ln(-A)`;
+//! - `conv1d.weight` is stored squeezed, `[d_inner, d_conv]`;
+//! - Mamba-2's `A_log` and `D` are stored as `[n_heads, 1]`, and its gated
+//!   norm as `[n_groups, d_inner / n_groups]`.
+
+use crate::config_json::{ConfigJson, Layer, SsmCfg};
+use burn::tensor::FloatDType;
+use std::collections::BTreeMap;
+
+/// A parsed GGUF file: its metadata, its tensor directory and the raw bytes
+/// the directory points into.
+#[derive(Clone, Debug)]
+pub struct Gguf {
+    metadata: BTreeMap<String, Value>,
+    tensors: Vec<TensorInfo>,
+    bytes: Vec<u8>,
+    /// Where the tensor data starts; every [TensorInfo::offset] is relative to
+    /// it.
+    data_start: usize,
+}
+
+/// A metadata value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Int(i128),
+    F32(f32),
+    F64(f64),
+    Bool(bool),
+    String(String),
+    Array(Vec<Value>),
+}
+
+#[derive(Clone, Debug)]
+struct TensorInfo {
+    name: String,
+    /// Innermost first, the reverse of a PyTorch shape.
+    dims: Vec<usize>,
+    ggml_type: GgmlType,
+    offset: usize,
+}
+
+/// The `ggml_type` of a tensor.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GgmlType {
+    F32,
+    F16,
+    BF16,
+    Q8_0,
+    Q4_0,
+    Q4K,
+    Other(u32),
+}
+
+impl GgmlType {
+    fn from_id(id: u32) -> Self {
+        match id {
+            0 => GgmlType::F32,
+            1 => GgmlType::F16,
+            2 => GgmlType::Q4_0,
+            8 => GgmlType::Q8_0,
+            12 => GgmlType::Q4K,
+            30 => GgmlType::BF16,
+            id => GgmlType::Other(id),
+        }
+    }
+
+    /// `(elements per block, bytes per block)`.
+    fn block(self) -> anyhow::Result<(usize, usize)> {
+        match self {
+            GgmlType::F32 => Ok((1, 4)),
+            GgmlType::F16 | GgmlType::BF16 => Ok((1, 2)),
+            // an f16 scale, then 32 i8
+            GgmlType::Q8_0 => Ok((32, 34)),
+            // an f16 scale, then 32 nibbles
+            GgmlType::Q4_0 => Ok((32, 18)),
+            // f16 scale and min, 12 bytes of 6-bit sub-block scales and mins,
+            // then 256 nibbles
+            GgmlType::Q4K => Ok((256, 144)),
+            GgmlType::Other(id) => anyhow::bail!("ggml tensor type {id} is not supported"),
+        }
+    }
+}
+
+/// The little-endian cursor the header is read with.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> anyhow::Result<&'a [u8]> {
+        let taken = self
+            .position
+            .checked_add(n)
+            .and_then(|end| self.bytes.get(self.position..end))
+            .ok_or_else(|| anyhow::anyhow!("the GGUF header is truncated"))?;
+        self.position += n;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> anyhow::Result<usize> {
+        let len = self.u64()?;
+        // every element takes at least one byte, so a larger count is garbage
+        anyhow::ensure!(
+            len <= (self.bytes.len() - self.position) as u64,
+            "a GGUF length of {len} overruns the file"
+        );
+        Ok(len as usize)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|e| anyhow::anyhow!("a GGUF string is not utf-8: {e}"))
+    }
+
+    fn value(&mut self, value_type: u32) -> anyhow::Result<Value> {
+        Ok(match value_type {
+            0 => Value::Int(self.array::<1>()?[0] as i128),
+            1 => Value::Int(i8::from_le_bytes(self.array()?) as i128),
+            2 => Value::Int(u16::from_le_bytes(self.array()?) as i128),
+            3 => Value::Int(i16::from_le_bytes(self.array()?) as i128),
+            4 => Value::Int(self.u32()? as i128),
+            5 => Value::Int(i32::from_le_bytes(self.array()?) as i128),
+            6 => Value::F32(f32::from_le_bytes(self.array()?)),
+            7 => Value::Bool(self.array::<1>()?[0] != 0),
+            8 => Value::String(self.string()?),
+            9 => {
+                let item_type = self.u32()?;
+                let len = self.len()?;
+                Value::Array(
+                    (0..len)
+                        .map(|_| self.value(item_type))
+                        .collect::<anyhow::Result<_>>()?,
+                )
+            }
+            10 => Value::Int(self.u64()? as i128),
+            11 => Value::Int(i64::from_le_bytes(self.array()?) as i128),
+            12 => Value::F64(f64::from_le_bytes(self.array()?)),
+            other => anyhow::bail!("unknown GGUF metadata type {other}"),
+        })
+    }
+}
+
+impl Gguf {
+    /// Parses the header; the tensor data is only read by
+    /// [Self::to_safetensors].
+    pub fn from_bytes(bytes: Vec<u8>) -> anyhow::Result<Self> {
+        let mut reader = Reader {
+            bytes: &bytes,
+            position: 0,
+        };
+        anyhow::ensure!(reader.take(4)? == b"GGUF", "not a GGUF file");
+        let version = reader.u32()?;
+        anyhow::ensure!(
+            matches!(version, 2 | 3),
+            "GGUF version {version} is not supported (only 2 and 3 are)"
+        );
+        let tensor_count = reader.len()?;
+        let metadata_count = reader.len()?;
+
+        let mut metadata = BTreeMap::new();
+        for _ in 0..metadata_count {
+            let key = reader.string()?;
+            let value_type = reader.u32()?;
+            metadata.insert(key, reader.value(value_type)?);
+        }
+
+        let mut tensors = Vec::with_capacity(tensor_count);
+        for _ in 0..tensor_count {
+            let name = reader.string()?;
+            let n_dims = reader.u32()?;
+            let dims = (0..n_dims)
+                .map(|_| reader.u64().map(|dim| dim as usize))
+                .collect::<anyhow::Result<_>>()?;
+            let ggml_type = GgmlType::from_id(reader.u32()?);
+            let offset = reader.u64()? as usize;
+            tensors.push(TensorInfo {
+                name,
+                dims,
+                ggml_type,
+                offset,
+            });
+        }
+
+        let alignment = match metadata.get("general.alignment") {
+            Some(Value::Int(alignment)) if *alignment > 0 => *alignment as usize,
+            Some(other) => anyhow::bail!("general.alignment is {other:?}"),
+            None => 32,
+        };
+        let data_start = reader.position.next_multiple_of(alignment);
+        let gguf = Self {
+            metadata,
+            tensors,
+            bytes,
+            data_start,
+        };
+        for tensor in &gguf.tensors {
+            gguf.data(tensor)?;
+        }
+        Ok(gguf)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        let bytes =
+            std::fs::read(path).map_err(|e| anyhow::anyhow!("failed to read {path:?}: {e}"))?;
+        Self::from_bytes(bytes).map_err(|e| anyhow::anyhow!("{path:?}: {e}"))
+    }
+
+    pub fn metadata(&self, key: &str) -> Option<&Value> {
+        self.metadata.get(key)
+    }
+
+    /// `general.architecture`: `mamba` or `mamba2`.
+    pub fn layer(&self) -> anyhow::Result<Layer> {
+        match self.metadata("general.architecture") {
+            Some(Value::String(arch)) if arch == "mamba" => Ok(Layer::Mamba1),
+            Some(Value::String(arch)) if arch == "mamba2" => Ok(Layer::Mamba2),
+            Some(Value::String(arch)) => anyhow::bail!("a {arch:?} GGUF is not a Mamba model"),
+            _ => anyhow::bail!("the GGUF has no general.architecture"),
+        }
+    }
+
+    /// The `{architecture}.{key}` integer.
+    fn usize(&self, key: &str) -> anyhow::Result<Option<usize>> {
+        let arch = match self.layer()? {
+            Layer::Mamba2 => "mamba2",
+            _ => "mamba",
+        };
+        match self.metadata(&format!("{arch}.{key}")) {
+            None => Ok(None),
+            Some(Value::Int(value)) if *value >= 0 => Ok(Some(*value as usize)),
+            Some(other) => anyhow::bail!("{arch}.{key} is {other:?}, not a count"),
+        }
+    }
+
+    fn required(&self, key: &str) -> anyhow::Result<usize> {
+        self.usize(key)?
+            .ok_or_else(|| anyhow::anyhow!("the GGUF metadata has no {key}"))
+    }
+
+    fn tensor(&self, name: &str) -> Option<&TensorInfo> {
+        self.tensors.iter().find(|tensor| tensor.name == name)
+    }
+
+    /// The topology the metadata and the tensor directory describe.
+    ///
+    /// llama.cpp pads the vocabulary into the token list itself, so
+    /// `vocab_size` is the embedding's row count and needs no further padding.
+    pub fn config_json(&self) -> anyhow::Result<ConfigJson> {
+        let layer = self.layer()?;
+        let d_model = self.required("embedding_length")?;
+        let d_inner = self.required("ssm.inner_size")?;
+        anyhow::ensure!(
+            d_model > 0 && d_inner % d_model == 0,
+            "ssm.inner_size {d_inner} is not a multiple of embedding_length {d_model}"
+        );
+        let embedding = self
+            .tensor("token_embd.weight")
+            .ok_or_else(|| anyhow::anyhow!("the GGUF has no token_embd.weight"))?;
+        let vocab_size = *embedding
+            .dims
+            .last()
+            .ok_or_else(|| anyhow::anyhow!("token_embd.weight has no dimensions"))?;
+        let headdim = match layer {
+            Layer::Mamba2 => {
+                let n_heads = self.required("ssm.time_step_rank")?;
+                anyhow::ensure!(
+                    n_heads > 0 && d_inner % n_heads == 0,
+                    "ssm.inner_size {d_inner} does not split into {n_heads} heads"
+                );
+                Some(d_inner / n_heads)
+            }
+            _ => None,
+        };
+        Ok(ConfigJson {
+            d_model,
+            n_layer: self.required("block_count")?,
+            vocab_size,
+            pad_vocab_size_multiple: 1,
+            d_intermediate: self.usize("feed_forward_length")?.unwrap_or(0),
+            ssm_cfg: SsmCfg {
+                layer: Some(format!("{layer:?}")),
+                d_state: Some(self.required("ssm.state_size")?),
+                d_conv: Some(self.required("ssm.conv_kernel")?),
+                expand: Some(d_inner / d_model),
+                // Mamba-2 stores its head count under the same key
+                dt_rank: match layer {
+                    Layer::Mamba1 => self.usize("ssm.time_step_rank")?,
+                    _ => None,
+                },
+                headdim,
+                ngroups: self.usize("ssm.group_count")?,
+                bias: Some(self.tensor("blk.0.ssm_in.bias").is_some()),
+                conv_bias: Some(self.tensor("blk.0.ssm_conv1d.bias").is_some()),
+                ..Default::default()
+            },
+            attn_layer_idx: Vec::new(),
+            tie_embeddings: self.tensor("output.weight").is_none(),
+            mimo_rank: None,
+            chunk_size: None,
+        })
+    }
+
+    /// Every tensor's name, in the `state-spaces` naming.
+    pub fn tensor_names(&self) -> anyhow::Result<Vec<String>> {
+        let layer = self.layer()?;
+        self.tensors
+            .iter()
+            .map(|tensor| Ok(rename(&tensor.name, layer)?.0))
+            .collect()
+    }
+
+    fn data(&self, tensor: &TensorInfo) -> anyhow::Result<&[u8]> {
+        let (block_len, block_size) = tensor.ggml_type.block()?;
+        let len = tensor
+            .dims
+            .iter()
+            .try_fold(1usize, |len, dim| len.checked_mul(*dim))
+            .ok_or_else(|| anyhow::anyhow!("{}: {:?} overflows", tensor.name, tensor.dims))?;
+        // ggml quantizes row by row, so no block spans two rows
+        let row_len = tensor.dims.first().copied().unwrap_or(1);
+        anyhow::ensure!(
+            row_len % block_len == 0,
+            "{}: rows of {row_len} elements are not whole {:?} blocks",
+            tensor.name,
+            tensor.ggml_type
+        );
+        let start = self.data_start.checked_add(tensor.offset);
+        start
+            .zip(start.and_then(|start| start.checked_add(len / block_len * block_size)))
+            .and_then(|(start, end)| self.bytes.get(start..end))
+            .ok_or_else(|| anyhow::anyhow!("{}: the tensor data is truncated", tensor.name))
+    }
+
+    /// The whole checkpoint as a safetensors file, in the `state-spaces`
+    /// naming. Each tensor is dequantized on its own, straight into `dtype`
+    /// bytes, so the file is all that is held: two bytes a parameter at half
+    /// precision.
+    ///
+    /// The vectors — norms, biases, `D` — and `A_log` stay f32 whatever
+    /// `dtype` is, for a mixed [crate::RuntimePrecision] to keep them exact.
+    /// They are a sliver of the model.
+    pub fn to_safetensors(&self, dtype: FloatDType) -> anyhow::Result<Vec<u8>> {
+        let layer = self.layer()?;
+        let mut renamed = Vec::with_capacity(self.tensors.len());
+        for tensor in &self.tensors {
+            let (name, fixup) = rename(&tensor.name, layer)?;
+            let mut shape: Vec<usize> = tensor.dims.iter().rev().copied().collect();
+            match fixup {
+                Fixup::None => {}
+                Fixup::Conv1d => {
+                    anyhow::ensure!(shape.len() == 2, "{}: {shape:?}", tensor.name);
+                    shape.insert(1, 1);
+                }
+                Fixup::Flatten | Fixup::ALog { flatten: true } => {
+                    shape = vec![shape.iter().product()];
+                }
+                Fixup::ALog { flatten: false } => {}
+            }
+            let dtype = match shape.len() < 2 || matches!(fixup, Fixup::ALog { .. }) {
+                true => FloatDType::F32,
+                false => dtype,
+            };
+            renamed.push((name, shape, dtype, tensor, fixup));
+        }
+
+        let mut bytes = crate::store_load::safetensors_header(
+            renamed
+                .iter()
+                .map(|(name, shape, dtype, _, _)| (name.clone(), shape.clone(), *dtype)),
+        );
+        for (_, _, dtype, tensor, fixup) in renamed {
+            let a_log = matches!(fixup, Fixup::ALog { .. });
+            dequantize(tensor.ggml_type, self.data(tensor)?, |value| {
+                let value = if a_log { (-value).ln() } else { value };
+                match dtype {
+                    FloatDType::F16 => bytes.extend(half::f16::from_f32(value).to_le_bytes()),
+                    FloatDType::BF16 => bytes.extend(half::bf16::from_f32(value).to_le_bytes()),
+                    _ => bytes.extend(value.to_le_bytes()),
+                }
+            })?;
+        }
+        Ok(bytes)
+    }
+}
+
+/// How a tensor's data differs from the `state-spaces` checkpoint's.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Fixup {
+    None,
+    /// Squeezed: the channel dimension goes back in.
+    Conv1d,
+    /// Reshaped for llama.cpp's broadcasting: back to one dimension.
+    Flatten,
+    /// `-exp(A_log)`, and (Mamba-2) with a trailing dimension of 1.
+    ALog {
+        flatten: bool,
+    },
+}
+
+/// The llama.cpp tensor name to the `state-spaces` one.
+fn rename(name: &str, layer: Layer) -> anyhow::Result<(String, Fixup)> {
+    let model_level = match name {
+        "token_embd.weight" => Some("backbone.embedding.weight"),
+        "output_norm.weight" => Some("backbone.norm_f.weight"),
+        "output.weight" => Some("lm_head.weight"),
+        _ => None,
+    };
+    if let Some(renamed) = model_level {
+        return Ok((renamed.into(), Fixup::None));
+    }
+
+    let unknown = || anyhow::anyhow!("GGUF tensor {name:?} has no place in a {layer:?} model");
+    let (i, rest) = name
+        .strip_prefix("blk.")
+        .and_then(|rest| rest.split_once('.'))
+        .ok_or_else(unknown)?;
+    let i: usize = i.parse().map_err(|_| unknown())?;
+    let mamba2 = layer == Layer::Mamba2;
+    let (renamed, fixup) = match rest {
+        "attn_norm.weight" => ("norm.weight", Fixup::None),
+        "ssm_in.weight" => ("mixer.in_proj.weight", Fixup::None),
+        "ssm_in.bias" => ("mixer.in_proj.bias", Fixup::None),
+        "ssm_conv1d.weight" => ("mixer.conv1d.weight", Fixup::Conv1d),
+        "ssm_conv1d.bias" => ("mixer.conv1d.bias", Fixup::None),
+        "ssm_x.weight" if !mamba2 => ("mixer.x_proj.weight", Fixup::None),
+        "ssm_dt.weight" if !mamba2 => ("mixer.dt_proj.weight", Fixup::None),
+        "ssm_dt.bias" if !mamba2 => ("mixer.dt_proj.bias", Fixup::None),
+        "ssm_dt.bias" => ("mixer.dt_bias", Fixup::None),
+        "ssm_a" => ("mixer.A_log", Fixup::ALog { flatten: mamba2 }),
+        "ssm_d" => ("mixer.D", Fixup::Flatten),
+        "ssm_norm.weight" if mamba2 => ("mixer.norm.weight", Fixup::Flatten),
+        "ssm_out.weight" => ("mixer.out_proj.weight", Fixup::None),
+        "ssm_out.bias" => ("mixer.out_proj.bias", Fixup::None),
+        _ => return Err(unknown()),
+    };
+    Ok((format!("backbone.layers.{i}.{renamed}"), fixup))
+}
+
+/// Each value of `data`, as f32 and in order, to `push`; block by block as
+/// `ggml` lays it out.
+fn dequantize(ggml_type: GgmlType, data: &[u8], mut push: impl FnMut(f32)) -> anyhow::Result<()> {
+    let f16 = |b: &[u8]| half::f16::from_le_bytes([b[0], b[1]]).to_f32();
+    let (_, block_size) = ggml_type.block()?;
+    for block in data.chunks_exact(block_size) {
+        match ggml_type {
+            GgmlType::F32 => push(f32::from_le_bytes(block.try_into().unwrap())),
+            GgmlType::F16 => push(f16(block)),
+            GgmlType::BF16 => push(half::bf16::from_le_bytes([block[0], block[1]]).to_f32()),
+            GgmlType::Q8_0 => {
+                let d = f16(block);
+                block[2..].iter().for_each(|&q| push(d * q as i8 as f32));
+            }
+            GgmlType::Q4_0 => {
+                // the low nibbles are the first half of the block, the high
+                // nibbles the second
+                let d = f16(block);
+                let qs = &block[2..];
+                qs.iter()
+                    .for_each(|&q| push(d * ((q & 0xf) as i32 - 8) as f32));
+                qs.iter()
+                    .for_each(|&q| push(d * ((q >> 4) as i32 - 8) as f32));
+            }
+            GgmlType::Q4K => {
+                let (d, min) = (f16(&block[0..2]), f16(&block[2..4]));
+                let scales = &block[4..16];
+                // eight sub-blocks of 32, in pairs sharing 32 bytes of nibbles
+                for (pair, qs) in block[16..].chunks_exact(32).enumerate() {
+                    let (scale, m) = scale_min_k4(2 * pair, scales);
+                    let (d1, m1) = (d * scale as f32, min * m as f32);
+                    qs.iter().for_each(|&q| push(d1 * (q & 0xf) as f32 - m1));
+                    let (scale, m) = scale_min_k4(2 * pair + 1, scales);
+                    let (d2, m2) = (d * scale as f32, min * m as f32);
+                    qs.iter().for_each(|&q| push(d2 * (q >> 4) as f32 - m2));
+                }
+            }
+            GgmlType::Other(_) => unreachable!("refused by block()"),
+        }
+    }
+    Ok(())
+}
+
+/// The 6-bit scale and min of Q4_K sub-block `j`, packed into 12 bytes.
+fn scale_min_k4(j: usize, q: &[u8]) -> (u8, u8) {
+    if j < 4 {
+        (q[j] & 63, q[j + 4] & 63)
+    } else {
+        (
+            (q[j + 4] & 0xf) | ((q[j - 4] >> 6) << 4),
+            (q[j + 4] >> 4) | ((q[j] >> 6) << 4),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A GGUF v3 file, built the way llama.cpp's writer lays one out.
+    struct Writer {
+        metadata: Vec<u8>,
+        metadata_count: u64,
+        tensors: Vec<(String, Vec<u64>, u32, Vec<u8>)>,
+    }
+
+    impl Writer {
+        fn new(architecture: &str) -> Self {
+            let mut writer = Self {
+                metadata: Vec::new(),
+                metadata_count: 0,
+                tensors: Vec::new(),
+            };
+            writer.key("general.architecture", 8);
+            writer.string(architecture);
+            writer
+        }
+
+        fn string(&mut self, s: &str) {
+            self.metadata
+                .extend_from_slice(&(s.len() as u64).to_le_bytes());
+            self.metadata.extend_from_slice(s.as_bytes());
+        }
+
+        fn key(&mut self, key: &str, value_type: u32) {
+            self.metadata_count += 1;
+            self.string(key);
+            self.metadata.extend_from_slice(&value_type.to_le_bytes());
+        }
+
+        fn u32(mut self, key: &str, value: u32) -> Self {
+            self.key(key, 4);
+            self.metadata.extend_from_slice(&value.to_le_bytes());
+            self
+        }
+
+        fn tensor(mut self, name: &str, dims: &[u64], ggml_type: u32, data: Vec<u8>) -> Self {
+            self.tensors
+                .push((name.into(), dims.to_vec(), ggml_type, data));
+            self
+        }
+
+        fn f32(self, name: &str, dims: &[u64], values: &[f32]) -> Self {
+            let data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            self.tensor(name, dims, 0, data)
+        }
+
+        fn finish(self) -> Vec<u8> {
+            let mut bytes = b"GGUF".to_vec();
+            bytes.extend_from_slice(&3u32.to_le_bytes());
+            bytes.extend_from_slice(&(self.tensors.len() as u64).to_le_bytes());
+            bytes.extend_from_slice(&self.metadata_count.to_le_bytes());
+            bytes.extend_from_slice(&self.metadata);
+            let mut offset = 0u64;
+            for (name, dims, ggml_type, data) in &self.tensors {
+                bytes.extend_from_slice(&(name.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(name.as_bytes());
+                bytes.extend_from_slice(&(dims.len() as u32).to_le_bytes());
+                dims.iter()
+                    .for_each(|dim| bytes.extend_from_slice(&dim.to_le_bytes()));
+                bytes.extend_from_slice(&ggml_type.to_le_bytes());
+                bytes.extend_from_slice(&offset.to_le_bytes());
+                offset += (data.len() as u64).next_multiple_of(32);
+            }
+            for (_, _, _, data) in &self.tensors {
+                bytes.resize(bytes.len().next_multiple_of(32), 0);
+                bytes.extend_from_slice(data);
+            }
+            bytes
+        }
+    }
+
+    /// [dequantize] into a vector.
+    fn dequantized(ggml_type: GgmlType, data: &[u8]) -> anyhow::Result<Vec<f32>> {
+        let mut values = Vec::new();
+        dequantize(ggml_type, data, |value| values.push(value))?;
+        Ok(values)
+    }
+
+    /// Reads an f32 safetensors file back into `name → (shape, values)`.
+    fn read_safetensors(bytes: &[u8]) -> BTreeMap<String, (Vec<usize>, Vec<f32>)> {
+        let len = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+        let header: BTreeMap<String, serde_json::Value> =
+            serde_json::from_slice(&bytes[8..8 + len]).unwrap();
+        let data = &bytes[8 + len..];
+        header
+            .into_iter()
+            .map(|(name, entry)| {
+                let shape = serde_json::from_value(entry["shape"].clone()).unwrap();
+                let [start, end]: [usize; 2] =
+                    serde_json::from_value(entry["data_offsets"].clone()).unwrap();
+                let values = data[start..end]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                    .collect();
+                (name, (shape, values))
+            })
+            .collect()
+    }
+
+    /// A one-layer Mamba-2 with `d_model = 4`, 2 heads of 4 and one group.
+    fn tiny_mamba2() -> Vec<u8> {
+        Writer::new("mamba2")
+            .u32("mamba2.embedding_length", 4)
+            .u32("mamba2.block_count", 1)
+            .u32("mamba2.ssm.inner_size", 8)
+            .u32("mamba2.ssm.state_size", 16)
+            .u32("mamba2.ssm.conv_kernel", 4)
+            .u32("mamba2.ssm.time_step_rank", 2)
+            .u32("mamba2.ssm.group_count", 1)
+            .f32("token_embd.weight", &[4, 8], &[0.5; 32])
+            .f32("output_norm.weight", &[4], &[1.; 4])
+            .f32("blk.0.attn_norm.weight", &[4], &[1.; 4])
+            .f32("blk.0.ssm_a", &[1, 2], &[-1., -2.])
+            .f32("blk.0.ssm_d", &[1, 2], &[1.; 2])
+            .f32("blk.0.ssm_dt.bias", &[2], &[0.; 2])
+            .f32("blk.0.ssm_conv1d.weight", &[4, 40], &[0.; 160])
+            .f32("blk.0.ssm_conv1d.bias", &[40], &[0.; 40])
+            .f32("blk.0.ssm_norm.weight", &[8, 1], &[1.; 8])
+            .finish()
+    }
+
+    #[test]
+    fn metadata_reads_into_a_config() {
+        let gguf = Gguf::from_bytes(tiny_mamba2()).unwrap();
+        let config = gguf.config_json().unwrap();
+        assert_eq!(config.layer().unwrap(), Layer::Mamba2);
+        assert_eq!(
+            (config.d_model, config.n_layer, config.vocab_size),
+            (4, 1, 8)
+        );
+        assert_eq!(config.ssm_cfg.expand, Some(2));
+        assert_eq!(config.ssm_cfg.headdim, Some(4));
+        assert_eq!(config.ssm_cfg.conv_bias, Some(true));
+        assert_eq!(config.ssm_cfg.bias, Some(false));
+        assert!(config.tie_embeddings);
+
+        let mamba1 = Writer::new("mamba")
+            .u32("mamba.embedding_length", 32)
+            .u32("mamba.block_count", 1)
+            .u32("mamba.ssm.inner_size", 64)
+            .u32("mamba.ssm.state_size", 16)
+            .u32("mamba.ssm.conv_kernel", 4)
+            .u32("mamba.ssm.time_step_rank", 3)
+            .f32("token_embd.weight", &[32, 8], &[0.; 256])
+            .finish();
+        let config = Gguf::from_bytes(mamba1).unwrap().config_json().unwrap();
+        assert_eq!(config.layer().unwrap(), Layer::Mamba1);
+        assert_eq!(config.ssm_cfg.dt_rank, Some(3));
+        assert_eq!(config.ssm_cfg.headdim, None);
+
+        let llama = Writer::new("llama").finish();
+        assert!(Gguf::from_bytes(llama).unwrap().config_json().is_err());
+        assert!(Gguf::from_bytes(b"GGML".to_vec()).is_err());
+    }
+
+    /// `mamba` as llama.cpp's converter would write it: [export_checkpoint]'s
+    /// tensors under their GGUF names and in their GGUF form.
+    ///
+    /// [export_checkpoint]: crate::export_checkpoint
+    fn gguf_of(mamba: &burn_mamba::prelude::MambaVocabNet, config: &ConfigJson) -> Vec<u8> {
+        let layer = config.layer().unwrap();
+        let mamba_config = config.vocab_net_config().unwrap();
+        let (bytes, _) = crate::export_checkpoint(mamba, &mamba_config).unwrap();
+        let ssm = &config.ssm_cfg;
+        let d_inner = ssm.expand.unwrap() * config.d_model;
+        let (arch, rank) = match layer {
+            Layer::Mamba2 => ("mamba2", d_inner / ssm.headdim.unwrap()),
+            _ => ("mamba", config.d_model.div_ceil(16)),
+        };
+        let mut writer = Writer::new(arch)
+            .u32(&format!("{arch}.embedding_length"), config.d_model as u32)
+            .u32(&format!("{arch}.block_count"), config.n_layer as u32)
+            .u32(&format!("{arch}.ssm.inner_size"), d_inner as u32)
+            .u32(
+                &format!("{arch}.ssm.state_size"),
+                ssm.d_state.unwrap() as u32,
+            )
+            .u32(
+                &format!("{arch}.ssm.conv_kernel"),
+                ssm.d_conv.unwrap() as u32,
+            )
+            .u32(&format!("{arch}.ssm.time_step_rank"), rank as u32);
+        if let Some(ngroups) = ssm.ngroups {
+            writer = writer.u32(&format!("{arch}.ssm.group_count"), ngroups as u32);
+        }
+        for (name, (mut shape, mut values)) in read_safetensors(&bytes) {
+            let ggml_name = match name.as_str() {
+                "backbone.embedding.weight" => "token_embd.weight".to_string(),
+                "backbone.norm_f.weight" => "output_norm.weight".to_string(),
+                "lm_head.weight" => "output.weight".to_string(),
+                name => {
+                    let rest = name.strip_prefix("backbone.layers.").unwrap();
+                    let (i, rest) = rest.split_once('.').unwrap();
+                    let renamed = match rest {
+                        "norm.weight" => "attn_norm.weight",
+                        "mixer.in_proj.weight" => "ssm_in.weight",
+                        "mixer.in_proj.bias" => "ssm_in.bias",
+                        "mixer.conv1d.weight" => {
+                            shape.remove(1);
+                            "ssm_conv1d.weight"
+                        }
+                        "mixer.conv1d.bias" => "ssm_conv1d.bias",
+                        "mixer.x_proj.weight" => "ssm_x.weight",
+                        "mixer.dt_proj.weight" => "ssm_dt.weight",
+                        "mixer.dt_proj.bias" | "mixer.dt_bias" => "ssm_dt.bias",
+                        "mixer.A_log" => {
+                            values.iter_mut().for_each(|a| *a = -a.exp());
+                            "ssm_a"
+                        }
+                        "mixer.D" => "ssm_d",
+                        "mixer.norm.weight" => "ssm_norm.weight",
+                        "mixer.out_proj.weight" => "ssm_out.weight",
+                        other => panic!("no GGUF name for {other}"),
+                    };
+                    // Mamba-2's per-head vectors are `[n_heads, 1]` and its
+                    // gated norm `[n_groups, d_inner / n_groups]`
+                    match renamed {
+                        _ if layer != Layer::Mamba2 => {}
+                        "ssm_a" | "ssm_d" => shape.push(1),
+                        "ssm_norm.weight" => shape.insert(0, 1),
+                        _ => {}
+                    }
+                    format!("blk.{i}.{renamed}")
+                }
+            };
+            let dims = shape
+                .iter()
+                .rev()
+                .map(|dim| *dim as u64)
+                .collect::<Vec<_>>();
+            writer = writer.f32(&ggml_name, &dims, &values);
+        }
+        writer.finish()
+    }
+
+    /// A random model, written as a GGUF, loads back through
+    /// [crate::load_mamba] to the same parameters; `A_log` only up to the
+    /// rounding of its `-exp` and `ln` round trip.
+    #[test]
+    fn gguf_of_a_model_loads_back_to_it() {
+        use burn::prelude::*;
+        use burn::store::ModuleSnapshot;
+        let device: Device = Default::default();
+        let configs: &[&str] = &[
+            #[cfg(feature = "mamba1")]
+            r#"{"d_model": 32, "n_layer": 2, "vocab_size": 64,
+                "ssm_cfg": {"d_state": 8, "d_conv": 4, "expand": 2}}"#,
+            #[cfg(feature = "mamba2")]
+            r#"{"d_model": 32, "n_layer": 2, "vocab_size": 64,
+                "ssm_cfg": {"layer": "Mamba2", "d_state": 16, "d_conv": 4, "expand": 2,
+                            "headdim": 16, "ngroups": 1}}"#,
+        ];
+        for config in configs {
+            let config = ConfigJson::from_bytes(config.as_bytes()).unwrap();
+            let mut mamba = config.vocab_net_config().unwrap().init(&device);
+            crate::tie_lm_head(&mut mamba, &device);
+
+            let gguf = Gguf::from_bytes(gguf_of(&mamba, &config)).unwrap();
+            let loaded_config = gguf.config_json().unwrap().vocab_net_config().unwrap();
+            let reloaded = crate::load_mamba(
+                crate::Checkpoint::Gguf(gguf),
+                loaded_config,
+                crate::RuntimePrecision::F32,
+                &device,
+            )
+            .unwrap();
+
+            let params = crate::store_load::fixtures::params;
+            let (expected, actual) = (params(&mamba), params(&reloaded));
+            assert!(expected.keys().eq(actual.keys()), "{:?}", config.layer());
+            for (path, want) in &expected {
+                let want = want.to_vec::<f32>().unwrap();
+                let got = actual[path].to_vec::<f32>().unwrap();
+                let exact = !path.ends_with("a_log") && !path.ends_with("a_log_h");
+                for (want, got) in want.iter().zip(&got) {
+                    match exact {
+                        true => assert_eq!(want, got, "{path}"),
+                        false => assert!((want - got).abs() <= 1e-5 * want.abs().max(1.), "{path}"),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tensors_take_back_their_state_spaces_form() {
+        let gguf = Gguf::from_bytes(tiny_mamba2()).unwrap();
+        let tensors = read_safetensors(&gguf.to_safetensors(FloatDType::F32).unwrap());
+        assert_eq!(tensors.len(), gguf.tensor_names().unwrap().len());
+
+        let (shape, a_log) = &tensors["backbone.layers.0.mixer.A_log"];
+        assert_eq!(shape, &[2]);
+        assert_eq!(a_log, &[0., 2f32.ln()]);
+        assert_eq!(tensors["backbone.embedding.weight"].0, [8, 4]);
+        assert_eq!(
+            tensors["backbone.layers.0.mixer.conv1d.weight"].0,
+            [40, 1, 4]
+        );
+        assert_eq!(tensors["backbone.layers.0.mixer.norm.weight"].0, [8]);
+        assert_eq!(tensors["backbone.layers.0.mixer.D"].0, [2]);
+        assert!(tensors.contains_key("backbone.layers.0.mixer.dt_bias"));
+
+        // at half precision, only the matrices are
+        let half = gguf.to_safetensors(FloatDType::BF16).unwrap();
+        let len = u64::from_le_bytes(half[..8].try_into().unwrap()) as usize;
+        let header: BTreeMap<String, serde_json::Value> =
+            serde_json::from_slice(&half[8..8 + len]).unwrap();
+        for (name, dtype) in [
+            ("backbone.embedding.weight", "BF16"),
+            ("backbone.layers.0.mixer.conv1d.weight", "BF16"),
+            ("backbone.layers.0.mixer.A_log", "F32"),
+            ("backbone.layers.0.mixer.D", "F32"),
+            ("backbone.layers.0.mixer.norm.weight", "F32"),
+        ] {
+            assert_eq!(header[name]["dtype"], dtype, "{name}");
+        }
+        assert_eq!(
+            half.len(),
+            8 + len + 2 * (32 + 160) + 4 * (4 + 4 + 2 + 2 + 2 + 40 + 8)
+        );
+
+        let stray = Writer::new("mamba").f32("blk.0.attn_q.weight", &[1], &[0.]);
+        let stray = Gguf::from_bytes(stray.finish()).unwrap();
+        assert!(stray.to_safetensors(FloatDType::F32).is_err());
+    }
+
+    #[test]
+    fn quantized_blocks_dequantize() {
+        // Q8_0: scale 0.5 (f16 0x3800)
+        let mut q8 = vec![0x00, 0x38];
+        q8.extend((0..32).map(|i| (i as i8 - 16) as u8));
+        let values = dequantized(GgmlType::Q8_0, &q8).unwrap();
+        assert_eq!(values[0], -8.);
+        assert_eq!(values[31], 7.5);
+
+        // Q4_0: scale 2 (f16 0x4000); byte i holds element i low, i + 16 high
+        let mut q4 = vec![0x00, 0x40];
+        q4.extend((0..16).map(|i| ((15 - i as u8) << 4) | i as u8));
+        let values = dequantized(GgmlType::Q4_0, &q4).unwrap();
+        assert_eq!(values.len(), 32);
+        assert_eq!((values[0], values[15]), (-16., 14.));
+        assert_eq!((values[16], values[31]), (14., -16.));
+
+        // Q4_K: d = 1, dmin = 1; sub-block 0 scale 2 min 1, sub-block 1
+        // scale 3 min 0, the rest 0
+        let mut q4k = vec![0x00, 0x3c, 0x00, 0x3c];
+        let mut scales = [0u8; 12];
+        scales[0] = 2;
+        scales[1] = 3;
+        scales[4] = 1;
+        q4k.extend_from_slice(&scales);
+        q4k.extend(std::iter::repeat_n(0x75, 128));
+        let values = dequantized(GgmlType::Q4K, &q4k).unwrap();
+        assert_eq!(values.len(), 256);
+        assert_eq!((values[0], values[32]), (2. * 5. - 1., 3. * 7.));
+        assert_eq!(values[64], 0.);
+
+        // the high 2 bits of sub-blocks 4..8 live in the top of bytes 0..8
+        let mut packed = [0u8; 12];
+        packed[0] = 0b1100_0000;
+        packed[4] = 0b0100_0000;
+        packed[8] = 0x21;
+        assert_eq!(scale_min_k4(4, &packed), (0x31, 0x12));
+
+        assert!(dequantized(GgmlType::Other(14), &[0; 210]).is_err());
+        assert_eq!(dequantized(GgmlType::BF16, &[0x80, 0x3f]).unwrap(), [1.]);
+    }
+}
@@ -27,6 +27,8 @@ pub enum HubError {
     Cache { context: String, message: String },
     /// A range request returned a different number of bytes than asked for.
     ShortRead { expected: usize, got: usize },
+    /// The file is not cached, and the client may not reach the network.
+    Offline { file: String },
 }
 
 impl fmt::Display for HubError {
@@ -40,6 +42,9 @@ impl fmt::Display for HubError {
             Self::ShortRead { expected, got } => {
                 write!(f, "expected {expected} bytes, received {got}")
             }
+            Self::Offline { file } => {
+                write!(f, "{file} is not cached, and the hub client is offline")
+            }
         }
     }
 }
@@ -26,6 +26,8 @@ pub struct Api {
     endpoint: Endpoint,
     url_template: UrlTemplate,
     cache_dir: PathBuf,
+    /// See [Self::with_offline].
+    offline: bool,
     agent: ureq::Agent,
     /// Used for metadata: the `x-repo-commit` / `x-linked-etag` headers only
     /// exist on the hub's own redirect response, not on the CDN's.
@@ -34,12 +36,14 @@ pub struct Api {
 
 impl Api {
     /// A client for `https://huggingface.co`, caching under `$HF_HOME/hub` (or
-    /// `~/.cache/huggingface/hub`).
+    /// `~/.cache/huggingface/hub`). Offline when `HF_HUB_OFFLINE` is set to a
+    /// true value, as the Python client is.
     pub fn new() -> Result<Self, HubError> {
         Ok(Self {
             endpoint: Endpoint::default(),
             url_template: UrlTemplate::default(),
             cache_dir: default_cache_dir(),
+            offline: offline_from_env(),
             agent: ureq::AgentBuilder::new().user_agent(USER_AGENT).build(),
             no_redirect_agent: ureq::AgentBuilder::new()
                 .user_agent(USER_AGENT)
@@ -54,6 +58,13 @@ impl Api {
         self
     }
 
+    /// An offline client only serves what is already cached, and fails with
+    /// [HubError::Offline] rather than reaching the network for the rest.
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
     /// A handle on one repository.
     pub fn repo(&self, repo: Repo) -> ApiRepo {
         ApiRepo {
@@ -157,6 +168,9 @@ impl ApiRepo {
         }
 
         let url = self.url(filename);
+        if self.api.offline {
+            return Err(HubError::Offline { file: url.0 });
+        }
         let metadata = self.api.metadata(&url)?;
 
         let blob = self.blob_path(&metadata.etag);
@@ -292,6 +306,16 @@ fn default_cache_dir() -> PathBuf {
     home.join(".cache").join("huggingface").join("hub")
 }
 
+/// `HF_HUB_OFFLINE`, read the way `huggingface_hub` reads its boolean flags.
+fn offline_from_env() -> bool {
+    std::env::var("HF_HUB_OFFLINE").is_ok_and(|value| {
+        matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "1" | "on" | "yes" | "true"
+        )
+    })
+}
+
 /// Resolves a same-host redirect target against the URL it came from.
 fn join_relative(base: &str, location: &str) -> String {
     // `base` looks like "https://host/path…"; keep everything up to the host.
@@ -1,12 +1,37 @@
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub mod config_json;
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub mod eval;
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub mod gguf;
 pub mod hub;
+#[cfg(all(
+    any(feature = "mamba1", feature = "mamba2", feature = "mamba3"),
+    not(target_arch = "wasm32")
+))]
+pub mod record_cache;
+pub mod reference;
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub mod registry;
 pub mod sampling;
+pub mod stats;
 #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
 mod store_load;
 pub mod token_output_stream;
 pub mod tokenizer;
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub mod verify;
 
 #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
-pub use store_load::{Checkpoint, load_mamba, tie_lm_head};
+pub use store_load::{
+    Checkpoint, Layout, SHARD_INDEX, cast_mamba, export_checkpoint, load_mamba, shard_files,
+    split_lm_head, tie_lm_head,
+};
+#[cfg(all(
+    any(feature = "mamba1", feature = "mamba2", feature = "mamba3"),
+    not(target_arch = "wasm32")
+))]
+pub use store_load::{load_weights, save_checkpoint, save_weights};
 
 #[allow(unused_imports)]
 use burn::prelude::*;
@@ -18,23 +43,33 @@ use tokenizer::Tokenizer;
 #[allow(unused_imports)]
 use burn_mamba::prelude::*;
 use sampling::LogitsProcessor;
+#[allow(unused_imports)]
+use stats::{FinishReason, GenerationStats, GenerationTimer};
+
+pub use precision::RuntimePrecision;
 
+/// The host side's float: logits are cast to it on the device and read back as
+/// it, whatever [RuntimePrecision] the model runs in.
 #[allow(unused_imports)]
 pub type Precision = f32;
 use burn::tensor::{FloatDType, IntDType};
+/// [Precision] as a dtype, for the cast before a readback.
 pub const PRECISION_FLOAT_D_TYPE: FloatDType = FloatDType::F32;
 pub const PRECISION_INT_D_TYPE: IntDType = IntDType::I32;
 
-/// One compiled-in checkpoint: everything an entry point needs to fetch, build
-/// and label a model, as plain data.
+/// One checkpoint: everything an entry point needs to fetch, build and label a
+/// model, as plain data.
 ///
 /// Any number of model features may be enabled at once; [hf::MODELS] is the
-/// resulting runtime list (highest priority first) and [hf::preferred] is what
-/// an entry point that runs a single model picks.
+/// resulting list of built-ins (highest priority first). More can be added at
+/// runtime, see [registry]; [hf::preferred] is what an entry point that runs a
+/// single model picks out of the combined set.
 #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
 #[derive(Clone, Copy, Debug)]
 pub struct ModelSpec {
-    /// Selection name; matches the cargo feature and the `frontend/` directory.
+    /// Selection name. For the 130m and 187m checkpoints it matches the cargo
+    /// feature and the `frontend/` directory; the larger ones share a
+    /// `mamba1-large` / `mamba2-large` feature and are named by size.
     pub id: &'static str,
     /// Human-readable name, for logs and the browser UI's asset card.
     pub display_name: &'static str,
@@ -43,16 +78,49 @@ pub struct ModelSpec {
     /// A [hub::RevisionPath].
     pub revision_path: &'static str,
     /// A [hub::FilePath].
+    pub file_path_config_json: &'static str,
+    /// A [hub::FilePath].
     pub file_path_model_safetensors: &'static str,
     /// A [hub::RepoId], of the repo this checkpoint's tokenizer comes from.
     pub tokenizer_repo_id: &'static str,
     /// A [hub::FilePath].
     pub file_path_tokenizer_json: &'static str,
-    /// The hardcoded topology, mirroring the checkpoint's `config.json`.
-    pub config: fn() -> MambaVocabNetConfig,
-    /// The scan the parallel path takes, including the chunk length the
-    /// checkpoint was trained with.
-    pub ssd_path: fn() -> MambaSsdPath,
+    /// The model's shape; the entry points check it against the fetched
+    /// `config.json` with [config_json::check_spec].
+    pub topology: Topology,
+}
+
+/// Where a [ModelSpec]'s topology comes from.
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+#[derive(Clone, Copy, Debug)]
+pub enum Topology {
+    /// Written out in code, mirroring the checkpoint's `config.json`, as the
+    /// built-ins are.
+    Code {
+        config: fn() -> MambaVocabNetConfig,
+        /// The scan the parallel path takes, including the chunk length the
+        /// checkpoint was trained with.
+        ssd_path: fn() -> MambaSsdPath,
+    },
+    /// A `config.json`, checked when the spec is registered.
+    ConfigJson(&'static config_json::ConfigJson),
+}
+
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+impl ModelSpec {
+    pub fn config(&self) -> MambaVocabNetConfig {
+        match self.topology {
+            Topology::Code { config, .. } => config(),
+            Topology::ConfigJson(json) => json.vocab_net_config().expect("checked by the registry"),
+        }
+    }
+
+    pub fn ssd_path(&self) -> MambaSsdPath {
+        match self.topology {
+            Topology::Code { ssd_path, .. } => ssd_path(),
+            Topology::ConfigJson(json) => json.ssd_path().expect("checked by the registry"),
+        }
+    }
 }
 
 pub mod hf {
@@ -61,7 +129,9 @@ pub mod hf {
 
     /// Every checkpoint compiled into this build, **highest priority first**.
     ///
-    /// The order is the priority: mamba3-mimo > mamba3-siso > mamba2 > mamba1.
+    /// The order is the priority: mamba3-mimo > mamba3-siso > mamba2 > mamba1,
+    /// then the larger Mamba-2 and Mamba-1 checkpoints, smallest first — they
+    /// only run when picked by id.
     #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
     pub const MODELS: &[&ModelSpec] = &[
         #[cfg(feature = "mamba3-mimo")]
@@ -72,31 +142,54 @@ pub mod hf {
         &mamba2_130m::SPEC,
         #[cfg(feature = "mamba1")]
         &mamba1_130m::SPEC,
+        #[cfg(feature = "mamba2-large")]
+        &mamba2_370m::SPEC,
+        #[cfg(feature = "mamba2-large")]
+        &mamba2_780m::SPEC,
+        #[cfg(feature = "mamba2-large")]
+        &mamba2_1_3b::SPEC,
+        #[cfg(feature = "mamba2-large")]
+        &mamba2_2_7b::SPEC,
+        #[cfg(feature = "mamba1-large")]
+        &mamba1_370m::SPEC,
+        #[cfg(feature = "mamba1-large")]
+        &mamba1_790m::SPEC,
+        #[cfg(feature = "mamba1-large")]
+        &mamba1_1_4b::SPEC,
+        #[cfg(feature = "mamba1-large")]
+        &mamba1_2_8b::SPEC,
     ];
 
+    /// Every checkpoint this process knows: the ones registered at runtime,
+    /// then [MODELS]; highest priority first. See [crate::registry].
+    #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+    pub fn models() -> Vec<&'static ModelSpec> {
+        crate::registry::global().models().to_vec()
+    }
+
     /// The checkpoint to use when exactly one model should run: the
-    /// highest-priority entry of [MODELS].
+    /// highest-priority entry of [models].
     ///
-    /// [None] only for a build with no checkpoint feature — `mamba3` on its own
-    /// enables the blocks but neither 187m topology.
+    /// [None] only for a build with no checkpoint feature (`mamba3` on its own
+    /// enables the blocks but neither 187m topology) and nothing registered.
     #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
     pub fn preferred() -> Option<&'static ModelSpec> {
-        MODELS.first().copied()
+        crate::registry::global().preferred()
     }
 
-    /// Looks a compiled-in checkpoint up by [ModelSpec::id].
+    /// Looks a checkpoint, built-in or registered, up by [ModelSpec::id].
     #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
     pub fn by_id(id: &str) -> Option<&'static ModelSpec> {
-        MODELS.iter().copied().find(|model| model.id == id)
+        crate::registry::global().by_id(id)
     }
 
-    /// The [ModelSpec::id] of every compiled-in checkpoint, for error messages.
+    /// The [ModelSpec::id] of every known checkpoint, for error messages.
     #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
     pub fn ids() -> Vec<&'static str> {
-        MODELS.iter().map(|model| model.id).collect()
+        crate::registry::global().ids()
     }
 
-    /// The tokenizer of the Mamba-1 / Mamba-2 130m checkpoints.
+    /// The tokenizer of the Mamba-1 / Mamba-2 checkpoints.
     pub mod tokenizer {
         #[allow(unused_imports)]
         use crate::hub::{FilePath, RepoId};
@@ -124,149 +217,302 @@ pub mod hf {
         pub const FILE_PATH_TOKENIZER_JSON: &str = "tokenizer.json";
     }
 
+    /// The revision of the checkpoints above 130m: `main`, the one revision
+    /// sure to exist. Which of these repos also has a safetensors conversion,
+    /// and under which `refs/pr/N`, was not checked when they were added;
+    /// `MAMBA_REVISION` selects one where `main` has no `model.safetensors`.
+    #[cfg(any(feature = "mamba1-large", feature = "mamba2-large"))]
+    const LARGE_REVISION: &str = "main";
+
+    /// Shared shape of the `state-spaces` Mamba-1 checkpoints, 130m to 2.8b.
+    ///
+    /// They differ only in `d_model` and `n_layer`; all are mixer-only
+    /// (`d_intermediate: 0`) over the GPT-NeoX vocabulary, padded to a multiple
+    /// of 8, with a tied LM head.
     #[cfg(feature = "mamba1")]
-    pub mod mamba1_130m {
-        #[allow(unused_imports)]
-        use crate::hub::{FilePath, RepoId, RevisionPath};
-        use burn_mamba::mamba1;
-        use burn_mamba::prelude::*;
-
-        /// This checkpoint's entry in [super::MODELS].
-        pub const SPEC: crate::ModelSpec = crate::ModelSpec {
-            id: "mamba1",
-            display_name: DISPLAY_NAME,
-            repo_id: REPO_ID,
-            revision_path: REVISION_PATH,
-            file_path_model_safetensors: FILE_PATH_MODEL_SAFETENSORS,
-            tokenizer_repo_id: tokenizer_source::REPO_ID,
-            file_path_tokenizer_json: tokenizer_source::FILE_PATH_TOKENIZER_JSON,
-            config,
-            ssd_path,
-        };
+    macro_rules! mamba1 {
+        (
+            $module:ident,
+            id = $id:literal,
+            repo = $repo:literal,
+            $(#[$revision_doc:meta])*
+            revision = $revision:expr,
+            display_name = $display_name:literal,
+            d_model = $d_model:expr,
+            n_layer = $n_layer:expr,
+        ) => {
+            pub mod $module {
+                #[allow(unused_imports)]
+                use crate::hub::{FilePath, RepoId, RevisionPath};
+                use burn_mamba::mamba1;
+                use burn_mamba::prelude::*;
 
-        /// A [RepoId].
-        pub const REPO_ID: &str = "state-spaces/mamba-130m";
-        /// A [RevisionPath].
-        ///
-        /// Safetensor PR conversion made by a bot.
-        pub const REVISION_PATH: &str = "refs/pr/1";
-        /// A [FilePath].
-        pub const FILE_PATH_CONFIG_JSON: &str = "config.json";
-        /// A [FilePath].
-        pub const FILE_PATH_MODEL_SAFETENSORS: &str = "model.safetensors";
-
-        /// Where this checkpoint's `tokenizer.json` comes from.
-        pub use super::tokenizer as tokenizer_source;
-
-        /// Shown on the browser UI's asset card.
-        pub const DISPLAY_NAME: &str = "Mamba-130m";
-
-        pub const VOCAB_SIZE: usize = 50277;
-        pub const PAD_VOCAB_SIZE_MULTIPLE: usize = 8;
-        pub const N_LAYER: usize = 24;
-        pub const D_MODEL: usize = 768;
-
-        pub fn config() -> MambaVocabNetConfig {
-            MambaVocabNetConfig::Mamba1 {
-                n_real_layers: N_LAYER, // 24
-                n_virtual_layers: None,
-                vocab_size: VOCAB_SIZE,                           // 50277
-                pad_vocab_size_multiple: PAD_VOCAB_SIZE_MULTIPLE, // 8
-                missing_lm_head: true,
-                ignore_first_residual: false,
-                ignore_last_residual: false,
-                residuals: ResidualsConfig::Standard,
-                // The 130m checkpoints are mixer-only (`d_intermediate: 0`).
-                mlp: None,
-                mamba_block: mamba1::prelude::Mamba1Config::new(
-                    D_MODEL, // 768
-                )
-                .with_state_rank(16) // default
-                .with_conv_kernel(4) // default
-                .with_expand(2) // default
-                .with_has_proj_bias(false) // default
-                .with_has_conv_bias(true), // default
-            }
-        }
+                /// This checkpoint's entry in [super::MODELS].
+                pub const SPEC: crate::ModelSpec = crate::ModelSpec {
+                    id: $id,
+                    display_name: DISPLAY_NAME,
+                    repo_id: REPO_ID,
+                    revision_path: REVISION_PATH,
+                    file_path_config_json: FILE_PATH_CONFIG_JSON,
+                    file_path_model_safetensors: FILE_PATH_MODEL_SAFETENSORS,
+                    tokenizer_repo_id: tokenizer_source::REPO_ID,
+                    file_path_tokenizer_json: tokenizer_source::FILE_PATH_TOKENIZER_JSON,
+                    topology: crate::Topology::Code { config, ssd_path },
+                };
 
-        pub fn ssd_path() -> MambaSsdPath {
-            MambaSsdPath::Mamba1
-        }
+                /// A [RepoId].
+                pub const REPO_ID: &str = $repo;
+                /// A [RevisionPath].
+                ///
+                $(#[$revision_doc])*
+                pub const REVISION_PATH: &str = $revision;
+                /// A [FilePath].
+                pub const FILE_PATH_CONFIG_JSON: &str = "config.json";
+                /// A [FilePath].
+                pub const FILE_PATH_MODEL_SAFETENSORS: &str = "model.safetensors";
+
+                /// Where this checkpoint's `tokenizer.json` comes from.
+                pub use super::tokenizer as tokenizer_source;
+
+                /// Shown on the browser UI's asset card.
+                pub const DISPLAY_NAME: &str = $display_name;
+
+                pub const VOCAB_SIZE: usize = 50277;
+                pub const PAD_VOCAB_SIZE_MULTIPLE: usize = 8;
+                pub const N_LAYER: usize = $n_layer;
+                pub const D_MODEL: usize = $d_model;
+
+                pub fn config() -> MambaVocabNetConfig {
+                    MambaVocabNetConfig::Mamba1 {
+                        n_real_layers: N_LAYER,
+                        n_virtual_layers: None,
+                        vocab_size: VOCAB_SIZE, // 50277
+                        pad_vocab_size_multiple: PAD_VOCAB_SIZE_MULTIPLE, // 8
+                        missing_lm_head: true,
+                        ignore_first_residual: false,
+                        ignore_last_residual: false,
+                        residuals: ResidualsConfig::Standard,
+                        // The Mamba-1 checkpoints are mixer-only (`d_intermediate: 0`).
+                        mlp: None,
+                        mamba_block: mamba1::prelude::Mamba1Config::new(D_MODEL)
+                            .with_state_rank(16) // default
+                            .with_conv_kernel(4) // default
+                            .with_expand(2) // default
+                            .with_has_proj_bias(false) // default
+                            .with_has_conv_bias(true), // default
+                    }
+                }
+
+                pub fn ssd_path() -> MambaSsdPath {
+                    MambaSsdPath::Mamba1
+                }
+            }
+        };
     }
 
+    #[cfg(feature = "mamba1")]
+    mamba1!(
+        mamba1_130m,
+        id = "mamba1",
+        repo = "state-spaces/mamba-130m",
+        /// Safetensor PR conversion made by a bot — `main` carries only
+        /// `pytorch_model.bin`.
+        revision = "refs/pr/1",
+        display_name = "Mamba-130m",
+        d_model = 768,
+        n_layer = 24,
+    );
+
+    #[cfg(feature = "mamba1-large")]
+    mamba1!(
+        mamba1_370m,
+        id = "mamba1-370m",
+        repo = "state-spaces/mamba-370m",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba-370m",
+        d_model = 1024,
+        n_layer = 48,
+    );
+
+    #[cfg(feature = "mamba1-large")]
+    mamba1!(
+        mamba1_790m,
+        id = "mamba1-790m",
+        repo = "state-spaces/mamba-790m",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba-790m",
+        d_model = 1536,
+        n_layer = 48,
+    );
+
+    #[cfg(feature = "mamba1-large")]
+    mamba1!(
+        mamba1_1_4b,
+        id = "mamba1-1.4b",
+        repo = "state-spaces/mamba-1.4b",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba-1.4b",
+        d_model = 2048,
+        n_layer = 48,
+    );
+
+    #[cfg(feature = "mamba1-large")]
+    mamba1!(
+        mamba1_2_8b,
+        id = "mamba1-2.8b",
+        repo = "state-spaces/mamba-2.8b",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba-2.8b",
+        d_model = 2560,
+        n_layer = 64,
+    );
+
+    /// Shared shape of the `state-spaces` Mamba-2 checkpoints, 130m to 2.7b.
+    ///
+    /// They differ only in `d_model` and `n_layer`; all are mixer-only
+    /// (`d_intermediate: 0`) over the GPT-NeoX vocabulary, padded to a multiple
+    /// of 16, with a tied LM head and 64-wide heads (`n_heads = 2·d_model/64`).
     #[cfg(feature = "mamba2")]
-    pub mod mamba2_130m {
-        #[allow(unused_imports)]
-        use crate::hub::{FilePath, RepoId, RevisionPath};
-        use burn_mamba::mamba2;
-        use burn_mamba::prelude::*;
-
-        /// This checkpoint's entry in [super::MODELS].
-        pub const SPEC: crate::ModelSpec = crate::ModelSpec {
-            id: "mamba2",
-            display_name: DISPLAY_NAME,
-            repo_id: REPO_ID,
-            revision_path: REVISION_PATH,
-            file_path_model_safetensors: FILE_PATH_MODEL_SAFETENSORS,
-            tokenizer_repo_id: tokenizer_source::REPO_ID,
-            file_path_tokenizer_json: tokenizer_source::FILE_PATH_TOKENIZER_JSON,
-            config,
-            ssd_path,
-        };
+    macro_rules! mamba2 {
+        (
+            $module:ident,
+            id = $id:literal,
+            repo = $repo:literal,
+            $(#[$revision_doc:meta])*
+            revision = $revision:expr,
+            display_name = $display_name:literal,
+            d_model = $d_model:expr,
+            n_layer = $n_layer:expr,
+        ) => {
+            pub mod $module {
+                #[allow(unused_imports)]
+                use crate::hub::{FilePath, RepoId, RevisionPath};
+                use burn_mamba::mamba2;
+                use burn_mamba::prelude::*;
 
-        /// A [RepoId].
-        pub const REPO_ID: &str = "state-spaces/mamba2-130m";
-        /// A [RevisionPath].
-        ///
-        /// Safetensor PR conversion made by a bot.
-        pub const REVISION_PATH: &str = "refs/pr/1";
-        /// A [FilePath].
-        pub const FILE_PATH_CONFIG_JSON: &str = "config.json";
-        /// A [FilePath].
-        pub const FILE_PATH_MODEL_SAFETENSORS: &str = "model.safetensors";
-
-        /// Where this checkpoint's `tokenizer.json` comes from.
-        pub use super::tokenizer as tokenizer_source;
-
-        /// Shown on the browser UI's asset card.
-        pub const DISPLAY_NAME: &str = "Mamba2-130m";
-
-        pub const VOCAB_SIZE: usize = 50277;
-        pub const PAD_VOCAB_SIZE_MULTIPLE: usize = 16;
-        pub const N_LAYER: usize = 24;
-        pub const D_MODEL: usize = 768;
-
-        pub fn config() -> MambaVocabNetConfig {
-            MambaVocabNetConfig::Mamba2 {
-                n_real_layers: N_LAYER, // 24
-                n_virtual_layers: None,
-                vocab_size: VOCAB_SIZE,                           // 50277
-                pad_vocab_size_multiple: PAD_VOCAB_SIZE_MULTIPLE, // 16
-                missing_lm_head: true,
-                ignore_first_residual: false,
-                ignore_last_residual: false,
-                residuals: ResidualsConfig::Standard,
-                // The 130m checkpoints are mixer-only (`d_intermediate: 0`).
-                mlp: None,
-                mamba_block: mamba2::prelude::Mamba2Config::new(
-                    D_MODEL, // 768
-                )
-                .with_state_rank(128) // default
-                .with_conv_kernel(4) // default
-                .with_expand(2) // default
-                .with_per_head_dim(64) // default; n_heads = 768*2/64 = 24
-                .with_ngroups(1) // default
-                .with_is_norm_before_gate(false)
-                .with_has_proj_bias(false) // default
-                .with_has_conv_bias(true), // default
-            }
-        }
+                /// This checkpoint's entry in [super::MODELS].
+                pub const SPEC: crate::ModelSpec = crate::ModelSpec {
+                    id: $id,
+                    display_name: DISPLAY_NAME,
+                    repo_id: REPO_ID,
+                    revision_path: REVISION_PATH,
+                    file_path_config_json: FILE_PATH_CONFIG_JSON,
+                    file_path_model_safetensors: FILE_PATH_MODEL_SAFETENSORS,
+                    tokenizer_repo_id: tokenizer_source::REPO_ID,
+                    file_path_tokenizer_json: tokenizer_source::FILE_PATH_TOKENIZER_JSON,
+                    topology: crate::Topology::Code { config, ssd_path },
+                };
 
-        pub fn ssd_path() -> MambaSsdPath {
-            MambaSsdPath::Mamba2(Mamba2SsdPath::SerialRecalculated(None))
-        }
+                /// A [RepoId].
+                pub const REPO_ID: &str = $repo;
+                /// A [RevisionPath].
+                ///
+                $(#[$revision_doc])*
+                pub const REVISION_PATH: &str = $revision;
+                /// A [FilePath].
+                pub const FILE_PATH_CONFIG_JSON: &str = "config.json";
+                /// A [FilePath].
+                pub const FILE_PATH_MODEL_SAFETENSORS: &str = "model.safetensors";
+
+                /// Where this checkpoint's `tokenizer.json` comes from.
+                pub use super::tokenizer as tokenizer_source;
+
+                /// Shown on the browser UI's asset card.
+                pub const DISPLAY_NAME: &str = $display_name;
+
+                pub const VOCAB_SIZE: usize = 50277;
+                pub const PAD_VOCAB_SIZE_MULTIPLE: usize = 16;
+                pub const N_LAYER: usize = $n_layer;
+                pub const D_MODEL: usize = $d_model;
+
+                pub fn config() -> MambaVocabNetConfig {
+                    MambaVocabNetConfig::Mamba2 {
+                        n_real_layers: N_LAYER,
+                        n_virtual_layers: None,
+                        vocab_size: VOCAB_SIZE, // 50277
+                        pad_vocab_size_multiple: PAD_VOCAB_SIZE_MULTIPLE, // 16
+                        missing_lm_head: true,
+                        ignore_first_residual: false,
+                        ignore_last_residual: false,
+                        residuals: ResidualsConfig::Standard,
+                        // The Mamba-2 checkpoints are mixer-only (`d_intermediate: 0`).
+                        mlp: None,
+                        mamba_block: mamba2::prelude::Mamba2Config::new(D_MODEL)
+                            .with_state_rank(128) // default
+                            .with_conv_kernel(4) // default
+                            .with_expand(2) // default
+                            .with_per_head_dim(64) // default
+                            .with_ngroups(1) // default
+                            .with_is_norm_before_gate(false)
+                            .with_has_proj_bias(false) // default
+                            .with_has_conv_bias(true), // default
+                    }
+                }
+
+                pub fn ssd_path() -> MambaSsdPath {
+                    MambaSsdPath::Mamba2(Mamba2SsdPath::SerialRecalculated(None))
+                }
+            }
+        };
     }
 
+    #[cfg(feature = "mamba2")]
+    mamba2!(
+        mamba2_130m,
+        id = "mamba2",
+        repo = "state-spaces/mamba2-130m",
+        /// Safetensor PR conversion made by a bot — `main` carries only
+        /// `pytorch_model.bin`.
+        revision = "refs/pr/1",
+        display_name = "Mamba2-130m",
+        d_model = 768,
+        n_layer = 24,
+    );
+
+    #[cfg(feature = "mamba2-large")]
+    mamba2!(
+        mamba2_370m,
+        id = "mamba2-370m",
+        repo = "state-spaces/mamba2-370m",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba2-370m",
+        d_model = 1024,
+        n_layer = 48,
+    );
+
+    #[cfg(feature = "mamba2-large")]
+    mamba2!(
+        mamba2_780m,
+        id = "mamba2-780m",
+        repo = "state-spaces/mamba2-780m",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba2-780m",
+        d_model = 1536,
+        n_layer = 48,
+    );
+
+    #[cfg(feature = "mamba2-large")]
+    mamba2!(
+        mamba2_1_3b,
+        id = "mamba2-1.3b",
+        repo = "state-spaces/mamba2-1.3b",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba2-1.3b",
+        d_model = 2048,
+        n_layer = 48,
+    );
+
+    #[cfg(feature = "mamba2-large")]
+    mamba2!(
+        mamba2_2_7b,
+        id = "mamba2-2.7b",
+        repo = "state-spaces/mamba2-2.7b",
+        revision = super::LARGE_REVISION,
+        display_name = "Mamba2-2.7b",
+        d_model = 2560,
+        n_layer = 64,
+    );
+
     /// Shared shape of the two official Mamba-3 187m checkpoints.
     ///
     /// Both are 12 layers of `Mamba3` mixer + SwiGLU MLP over the Llama-3.1
@@ -300,11 +546,11 @@ pub mod hf {
                     display_name: DISPLAY_NAME,
                     repo_id: REPO_ID,
                     revision_path: REVISION_PATH,
+                    file_path_config_json: FILE_PATH_CONFIG_JSON,
                     file_path_model_safetensors: FILE_PATH_MODEL_SAFETENSORS,
                     tokenizer_repo_id: tokenizer_source::REPO_ID,
                     file_path_tokenizer_json: tokenizer_source::FILE_PATH_TOKENIZER_JSON,
-                    config,
-                    ssd_path,
+                    topology: crate::Topology::Code { config, ssd_path },
                 };
 
                 /// A [RepoId].
@@ -414,22 +660,127 @@ pub struct MambaWrapper {
     pub tokenizer: TokenOutputStream,
     pub mamba: MambaVocabNet,
     pub mamba_config: MambaVocabNetConfig,
+    /// Ids the sampler must never pick, in ascending order: the padding past the
+    /// real vocabulary and, see [Self::with_unrenderable_masked], any id the
+    /// tokenizer cannot render.
+    masked_ids: Vec<u32>,
+    /// [split_lm_head] of `mamba`, split on the first [Self::step_batch] that
+    /// asks for hidden states.
+    headless_mamba: std::cell::OnceCell<(MambaVocabNet, burn::nn::Linear)>,
+    /// See [Self::with_finite_check].
+    check_finite: bool,
+}
+
+/// One row of a [MambaWrapper::step_batch].
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub struct StepOutput {
+    /// `[padded_vocab]`.
+    pub logits: Vec<Precision>,
+    /// The final-layer hidden state, `[d_model]`, when asked for.
+    pub hidden: Option<Vec<Precision>>,
 }
 
 pub struct LogitsProcessorWrapper {
     logits_processor: LogitsProcessor,
     repeat_penalty: f32,
+    /// How far back every penalty below looks, in tokens.
     repeat_last_n: usize,
+    /// See [sampling::apply_no_repeat_ngram]; `0` is off.
+    no_repeat_ngram_size: usize,
+    dry: Option<sampling::Dry>,
+    /// `(max_period, min_repeats)`, see [sampling::detect_loop].
+    loop_stop: Option<(usize, usize)>,
+    /// Where the sampled tokens of the current run start, i.e. its prompt
+    /// length; `None` until the run samples its first token.
+    sampled_from: Option<usize>,
+    /// The period of the loop the last sampled token completed.
+    looped: Option<usize>,
+    /// Scratch: the penalized window of tokens.
+    context: Vec<u32>,
+    /// Scratch: its distinct tokens, see [sampling::apply_repeat_penalty].
+    seen: Vec<u32>,
 }
 
 #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
 impl MambaWrapper {
-    pub fn new(spec: &'static ModelSpec, tokenizer: Tokenizer, mamba: MambaVocabNet) -> Self {
-        Self {
+    /// Errors if the `tokenizer` has ids past the model's vocabulary.
+    pub fn new(
+        spec: &'static ModelSpec,
+        tokenizer: Tokenizer,
+        mamba: MambaVocabNet,
+    ) -> anyhow::Result<Self> {
+        let mamba_config = spec.config();
+        let vocab_size = vocab_size(&mamba_config);
+        anyhow::ensure!(
+            tokenizer.id_bound() <= vocab_size,
+            "the tokenizer of {} has ids up to {} ({} base tokens plus added ones), \
+             past the model's vocabulary of {vocab_size}",
+            spec.display_name,
+            tokenizer.id_bound().saturating_sub(1),
+            tokenizer.vocab_size(),
+        );
+        let masked_ids = (vocab_size as u32..padded_vocab_size(&mamba_config) as u32).collect();
+        Ok(Self {
             spec,
             tokenizer: TokenOutputStream::new(tokenizer),
             mamba,
-            mamba_config: (spec.config)(),
+            mamba_config,
+            masked_ids,
+            headless_mamba: std::cell::OnceCell::new(),
+            check_finite: false,
+        })
+    }
+
+    /// Scans the logits of every `step` and `forward` for NaN and infinities,
+    /// failing with a [verify::NonFiniteLogits] that names the first layer to
+    /// produce one — rather than letting the sampler fail on a distribution that
+    /// sums to NaN. Finding the layer re-runs the call once per layer, but only
+    /// on failure; the scan itself is a pass over logits already read back.
+    pub fn with_finite_check(mut self, check_finite: bool) -> Self {
+        self.check_finite = check_finite;
+        self
+    }
+
+    /// Also masks the ids below the vocabulary size that the tokenizer has no
+    /// token for, the holes some vocabularies leave between their base and
+    /// their added tokens. The padding past the vocabulary is always masked.
+    pub fn with_unrenderable_masked(mut self, mask: bool) -> Self {
+        let vocab_size = vocab_size(&self.mamba_config) as u32;
+        let padded = padded_vocab_size(&self.mamba_config) as u32;
+        let tokenizer = self.tokenizer.tokenizer();
+        self.masked_ids = (0..padded)
+            .filter(|id| *id >= vocab_size || (mask && tokenizer.id_to_token(*id).is_none()))
+            .collect();
+        self
+    }
+
+    /// With [Self::with_finite_check], fails when `logits`, `[rows,
+    /// padded_vocab]` as read back, hold a NaN or an infinity, localized by
+    /// re-running `run` from the `caches` the call started from.
+    fn ensure_finite(
+        &self,
+        logits: &[Precision],
+        path: &'static str,
+        caches: Option<MambaCaches>,
+        run: impl Fn(&MambaVocabNet, Option<MambaCaches>) -> (Vec<Precision>, MambaCaches),
+    ) -> anyhow::Result<()> {
+        if !self.check_finite {
+            return Ok(());
+        }
+        match verify::NonFiniteLogits::find(logits, self.padded_vocab_size(), path) {
+            None => Ok(()),
+            Some(found) => Err(verify::locate_non_finite(found, &self.mamba, caches, run).into()),
+        }
+    }
+
+    /// Sets the logits of the ids that have no token — the vocabulary padding,
+    /// and any hole in the tokenizer if asked — to `-inf`, so they are never
+    /// sampled.
+    pub fn mask_logits(&self, logits: &mut [Precision]) {
+        for id in &self.masked_ids {
+            if let Some(logit) = logits.get_mut(*id as usize) {
+                *logit = Precision::NEG_INFINITY;
+            }
         }
     }
 
@@ -463,8 +814,48 @@ impl MambaWrapper {
         Ok(caches)
     }
 
+    /// Caches whose row `i` is row `rows[i]` of `caches`: a row repeated to
+    /// fan one prefill out over a batch, or one picked out of a batch.
+    ///
+    /// Every cache tensor is batch-first. The rows are gathered on the host,
+    /// through the tensors' snapshots, and applied to fresh caches of the new
+    /// batch size.
+    pub fn select_cache_rows(
+        &self,
+        caches: &MambaCaches,
+        rows: &[usize],
+    ) -> anyhow::Result<MambaCaches> {
+        use burn::store::{ModuleSnapshot, TensorSnapshot};
+        let snapshots = caches
+            .collect(None, None, false)
+            .into_iter()
+            .map(|snapshot| {
+                let path = snapshot.full_path();
+                let data = snapshot
+                    .to_data()
+                    .map_err(|e| anyhow::anyhow!("failed to read the cache {path}: {e:?}"))?;
+                let data = select_rows(data, rows)
+                    .map_err(|e| anyhow::anyhow!("the cache {path}: {e}"))?;
+                Ok(TensorSnapshot::from_data(
+                    data,
+                    snapshot.path_stack.clone().unwrap_or_default(),
+                    snapshot.container_stack.clone().unwrap_or_default(),
+                    snapshot.tensor_id.unwrap_or_default(),
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut selected = self.empty_caches(rows.len())?;
+        let result = selected.apply(snapshots, None, None, false);
+        anyhow::ensure!(
+            result.errors.is_empty() && result.missing.is_empty(),
+            "failed to select the cache rows: {:?}",
+            result.errors
+        );
+        Ok(selected)
+    }
+
     /// Reset and make up to `sample_len - 1` parallel (training-friendly) calls to generate up to `sample_len - 1` tokens.
-    /// Returns how many tokens and the instant after the first token got generated.
+    /// Returns how the run went; see [GenerationStats].
     ///
     /// `mamba2_chunk_size`: Chunk size for Mamba2 selective scan. Defaults to 256. No effect for Mamba1.
     pub fn run_parallel(
@@ -472,10 +863,12 @@ impl MambaWrapper {
         prompt: &str,
         sample_len: usize,
         logits_processor_config: &mut LogitsProcessorWrapper,
-    ) -> anyhow::Result<(usize, Option<std::time::Instant>)> {
+    ) -> anyhow::Result<GenerationStats> {
         use std::io::Write;
         let (mut tokens, eos_token) = self.reset_prompt(prompt)?;
         let device = device(&self.mamba);
+        let prompt_len = tokens.len();
+        let mut timer = GenerationTimer::start(prompt_len);
 
         // prints the first token (if present), as this is used as *input* to the model
         if let Some(t) = tokens.first() {
@@ -485,34 +878,47 @@ impl MambaWrapper {
         }
         std::io::stdout().flush()?;
 
-        let mut instant = None;
+        let mut finish_reason = FinishReason::Length;
         let mut i = 0;
         'outer: while i < sample_len {
             let input: Tensor<1, Int> = Tensor::from_data(tokens.as_slice(), &device);
             let input = input.unsqueeze();
 
-            let ssd_path = (self.spec.ssd_path)();
-            let (logits_list, _caches) = self.mamba.forward(input, None, ssd_path, None);
-            if i == 0 {
-                instant = Some(std::time::Instant::now());
-            }
+            let ssd_path = self.spec.ssd_path();
+            let (logits_list, _caches) = self.mamba.forward(input.clone(), None, ssd_path, None);
 
-            let logits_list = logits_list.into_data().to_vec::<Precision>().unwrap();
+            let logits_list = read_back(logits_list);
+            timer.prefilled();
+            self.ensure_finite(&logits_list, "forward", None, |mamba, caches| {
+                let ssd_path = self.spec.ssd_path();
+                let (logits, caches) = mamba.forward(input.clone(), caches, ssd_path, None);
+                (read_back(logits), caches)
+            })?;
 
             // logits contains an output for each timestep
             let logits_list = logits_list
                 .chunks_exact(self.padded_vocab_size())
                 .skip(i)
-                .map(<[Precision]>::to_vec)
+                .map(|logits| {
+                    let mut logits = logits.to_vec();
+                    self.mask_logits(&mut logits);
+                    logits
+                })
                 .collect::<Vec<_>>();
 
             //
 
             for logits in logits_list.into_iter() {
                 let next_token = logits_processor_config.add_logits(i, &mut tokens, logits)?;
-                if next_token == eos_token {
+                if let Some(reason) =
+                    finish_reason_of(next_token, eos_token, logits_processor_config)
+                {
+                    finish_reason = reason;
                     break 'outer;
                 }
+                if i + 1 >= prompt_len {
+                    timer.token();
+                }
 
                 // if the token has some valid representation, print it
                 if let Some(t) = self.tokenizer.next_token(next_token as u32) {
@@ -527,17 +933,17 @@ impl MambaWrapper {
         if let Some(rest) = self.tokenizer.decode_rest() {
             print!("{rest}");
         }
-        Ok((i, instant))
+        Ok(timer.finish(finish_reason))
     }
 
     /// Reset and make up to `sample_len - 1` sequential (inference-friendly) calls to generate up to `sample_len - 1` tokens.
-    /// Returns how many tokens and the instant after the first token got generated.
+    /// Returns how the run went; see [GenerationStats].
     pub fn run_sequential(
         &mut self,
         prompt: &str,
         sample_len: usize,
         logits_processor_config: &mut LogitsProcessorWrapper,
-    ) -> anyhow::Result<(usize, Option<std::time::Instant>)> {
+    ) -> anyhow::Result<GenerationStats> {
         use std::io::Write;
         let (mut tokens, eos_token) = self.reset_prompt(prompt)?;
 
@@ -551,18 +957,24 @@ impl MambaWrapper {
 
         let mut caches = self.empty_caches(1)?;
 
-        let mut instant = None;
+        let prompt_len = tokens.len();
+        let mut timer = GenerationTimer::start(prompt_len);
+        let mut finish_reason = FinishReason::Length;
         let mut i = 0;
         while i < sample_len {
             let (next_logits, new_caches) = self.step(tokens[i], Some(caches))?;
             caches = new_caches;
-            if i == 0 {
-                instant = Some(std::time::Instant::now());
+            if i + 1 >= prompt_len {
+                timer.prefilled();
             }
             let next_token = logits_processor_config.add_logits(i, &mut tokens, next_logits)?;
-            if next_token == eos_token {
+            if let Some(reason) = finish_reason_of(next_token, eos_token, logits_processor_config) {
+                finish_reason = reason;
                 break;
             }
+            if i + 1 >= prompt_len {
+                timer.token();
+            }
 
             // if the token has some valid representation, print it
             if let Some(t) = self.tokenizer.next_token(next_token as u32) {
@@ -577,7 +989,336 @@ impl MambaWrapper {
         if let Some(rest) = self.tokenizer.decode_rest() {
             print!("{rest}");
         }
-        Ok((i, instant))
+        Ok(timer.finish(finish_reason))
+    }
+
+    /// Samples `sampler.batch()` independent completions of `prompt`, each up to
+    /// `sample_len` tokens, and returns their text (the prompt left out).
+    ///
+    /// The prompt is run once, as a single row, and its caches are repeated
+    /// into `n` rows (see [Self::select_cache_rows]). From there each row steps
+    /// its own continuation, drawn by its own row of `sampler` (see
+    /// [sampling::BatchLogitsProcessor]), with the repeat penalty looking back
+    /// `repeat_last_n` tokens over that row alone. A row that reaches eos is done;
+    /// the batch steps until every row is.
+    pub fn run_sequential_n(
+        &mut self,
+        prompt: &str,
+        sample_len: usize,
+        sampler: &mut sampling::BatchLogitsProcessor,
+        repeat_last_n: usize,
+    ) -> anyhow::Result<Vec<String>> {
+        let (prompt_tokens, eos_token) = self.reset_prompt(prompt)?;
+        anyhow::ensure!(!prompt_tokens.is_empty(), "cannot prefill an empty prompt");
+        let n = sampler.batch();
+        let device = device(&self.mamba);
+        let vocab = self.padded_vocab_size();
+
+        // every row starts from the same prompt, so it is prefilled once and
+        // its caches and last logits fanned out to the `n` rows
+        let input: Tensor<1, Int> = Tensor::from_data(prompt_tokens.as_slice(), &device);
+        let ssd_path = self.spec.ssd_path();
+        let (logits, caches) = self.mamba.forward(input.unsqueeze(), None, ssd_path, None);
+        let mut caches = self.select_cache_rows(&caches, &vec![0; n])?;
+        let [_, len, _] = logits.dims();
+        let mut logits = read_back(logits.narrow(1, len - 1, 1)).repeat(n);
+        for row in logits.chunks_exact_mut(vocab) {
+            self.mask_logits(row);
+        }
+
+        let mut rows = vec![prompt_tokens.clone(); n];
+        let mut done = vec![false; n];
+        for _ in 0..sample_len {
+            let contexts = rows
+                .iter()
+                .map(|tokens| {
+                    let start_at = tokens.len().saturating_sub(repeat_last_n + 1);
+                    tokens[start_at..].iter().map(|t| *t as u32).collect()
+                })
+                .collect::<Vec<Vec<u32>>>();
+            let contexts = contexts.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            let next_tokens = sampler.sample(&mut logits, &contexts)?;
+
+            for ((tokens, done), next_token) in rows.iter_mut().zip(&mut done).zip(&next_tokens) {
+                if !*done {
+                    *done = *next_token as usize == eos_token;
+                    if !*done {
+                        tokens.push(*next_token as usize);
+                    }
+                }
+            }
+            if done.iter().all(|done| *done) {
+                break;
+            }
+
+            let inputs = next_tokens.iter().map(|t| *t as usize).collect::<Vec<_>>();
+            let (next_logits, new_caches) = self.step_rows(&inputs, Some(caches))?;
+            logits = next_logits;
+            caches = new_caches;
+        }
+
+        let tokenizer = self.tokenizer.tokenizer();
+        Ok(rows
+            .iter()
+            .map(|tokens| {
+                let generated = tokens[prompt_tokens.len()..]
+                    .iter()
+                    .map(|t| *t as u32)
+                    .collect::<Vec<_>>();
+                tokenizer.decode(&generated, true)
+            })
+            .collect())
+    }
+
+    /// Like [Self::run_sequential], but the tokens are sampled on the device by
+    /// `sampler`: each step reads back one token id instead of the whole logits,
+    /// and the prompt's steps read nothing back at all.
+    /// Returns how the run went; see [GenerationStats].
+    pub fn run_sequential_on_device(
+        &mut self,
+        prompt: &str,
+        sample_len: usize,
+        sampler: &mut sampling::device::DeviceSampler,
+    ) -> anyhow::Result<GenerationStats> {
+        use std::io::Write;
+        let (mut tokens, eos_token) = self.reset_prompt(prompt)?;
+
+        // prints the first token (if present), as this is used as *input* to the model
+        if let Some(t) = tokens.first() {
+            if let Some(t) = self.tokenizer.next_token(*t as u32) {
+                print!("{t}")
+            }
+        }
+        std::io::stdout().flush()?;
+
+        let device = device(&self.mamba);
+        let mut caches = self.empty_caches(1)?;
+
+        let mut timer = GenerationTimer::start(tokens.len());
+        let mut finish_reason = FinishReason::Length;
+        let mut i = 0;
+        while i < sample_len {
+            let next_token = if i + 1 < tokens.len() {
+                // the next token is given, so its logits are never read
+                let input = Tensor::from_data([tokens[i]], &device);
+                let (_logits, new_caches) = self.mamba.step(input, Some(caches), None);
+                caches = new_caches;
+                tokens[i + 1]
+            } else {
+                timer.prefilled();
+                let ((next_token, _log_prob), new_caches) =
+                    self.step_sample(tokens[i], Some(caches), sampler, &tokens)?;
+                caches = new_caches;
+                tokens.push(next_token as usize);
+                if next_token as usize != eos_token {
+                    timer.token();
+                }
+                next_token as usize
+            };
+            if next_token == eos_token {
+                finish_reason = FinishReason::Eos;
+                break;
+            }
+
+            // if the token has some valid representation, print it
+            if let Some(t) = self.tokenizer.next_token(next_token as u32) {
+                print!("{t}");
+                std::io::stdout().flush()?;
+            }
+
+            i += 1;
+        }
+        if let Some(rest) = self.tokenizer.decode_rest() {
+            print!("{rest}");
+        }
+        Ok(timer.finish(finish_reason))
+    }
+
+    /// Like [Self::run_sequential], but with classifier-free guidance: a second
+    /// cache decodes `negative_prompt` followed by the same generated tokens, and
+    /// each sampled token comes from both branches' logits mixed by
+    /// `guidance_scale` (see [sampling::apply_guidance]).
+    ///
+    /// The recurrent state is fixed-size, so the unconditioned branch costs one
+    /// extra cache and one extra [Self::step] per generated token, whatever the
+    /// length of either prompt. An empty `negative_prompt` is the plain
+    /// unconditioned model, started from the eos token — the document separator
+    /// the checkpoints were trained with.
+    /// Returns how the run went; see [GenerationStats].
+    pub fn run_sequential_cfg(
+        &mut self,
+        prompt: &str,
+        negative_prompt: &str,
+        guidance_scale: f32,
+        sample_len: usize,
+        logits_processor_config: &mut LogitsProcessorWrapper,
+    ) -> anyhow::Result<GenerationStats> {
+        use std::io::Write;
+        let (mut negative_tokens, _eos_token) = self.reset_prompt(negative_prompt)?;
+        let (mut tokens, eos_token) = self.reset_prompt(prompt)?;
+        if negative_tokens.is_empty() {
+            negative_tokens.push(eos_token);
+        }
+
+        // prints the first token (if present), as this is used as *input* to the model
+        if let Some(t) = tokens.first() {
+            if let Some(t) = self.tokenizer.next_token(*t as u32) {
+                print!("{t}")
+            }
+        }
+        std::io::stdout().flush()?;
+
+        // both prompts are the prefill
+        let mut timer = GenerationTimer::start(tokens.len());
+        let (mut unconditioned_logits, mut unconditioned_caches) =
+            self.prefill(&negative_tokens, self.empty_caches(1)?)?;
+        let mut caches = self.empty_caches(1)?;
+
+        let mut finish_reason = FinishReason::Length;
+        let mut i = 0;
+        while i < sample_len {
+            let (mut next_logits, new_caches) = self.step(tokens[i], Some(caches))?;
+            caches = new_caches;
+
+            // only the generated tokens are guided; the prompt ones are given
+            let is_generating = i + 1 >= tokens.len();
+            if is_generating {
+                timer.prefilled();
+                sampling::apply_guidance(&mut next_logits, &unconditioned_logits, guidance_scale);
+            }
+            let next_token = logits_processor_config.add_logits(i, &mut tokens, next_logits)?;
+            if let Some(reason) = finish_reason_of(next_token, eos_token, logits_processor_config) {
+                finish_reason = reason;
+                break;
+            }
+            if is_generating {
+                timer.token();
+                // the unconditioned branch continues with the same text
+                let (logits, new_caches) = self.step(next_token, Some(unconditioned_caches))?;
+                unconditioned_logits = logits;
+                unconditioned_caches = new_caches;
+            }
+
+            // if the token has some valid representation, print it
+            if let Some(t) = self.tokenizer.next_token(next_token as u32) {
+                print!("{t}");
+                std::io::stdout().flush()?;
+            }
+
+            i += 1;
+        }
+        if let Some(rest) = self.tokenizer.decode_rest() {
+            print!("{rest}");
+        }
+        Ok(timer.finish(finish_reason))
+    }
+
+    /// Contrastive search: each token is the one of the `top_k` most likely that
+    /// best trades its probability for how unlike every previous token its
+    /// hidden state is, weighted by `alpha` (see [sampling::contrastive_pick]).
+    ///
+    /// The prompt is prefilled once, as a single row whose caches are then
+    /// repeated into `top_k` rows. Each token, the candidates are batched through
+    /// one [Self::step_batch]; the picked candidate's row of the new caches is
+    /// repeated over every row in turn, and its logits are the next step's.
+    /// Returns how the run went; see [GenerationStats].
+    pub fn run_contrastive(
+        &mut self,
+        prompt: &str,
+        sample_len: usize,
+        top_k: usize,
+        alpha: f32,
+    ) -> anyhow::Result<GenerationStats> {
+        use std::io::Write;
+        let (mut tokens, eos_token) = self.reset_prompt(prompt)?;
+        let top_k = top_k.max(1);
+
+        // the prompt is given, so it's printed upfront
+        for t in &tokens {
+            if let Some(t) = self.tokenizer.next_token(*t as u32) {
+                print!("{t}")
+            }
+        }
+        std::io::stdout().flush()?;
+
+        // the hidden state after each token so far
+        let mut timer = GenerationTimer::start(tokens.len());
+        let mut history = Vec::with_capacity(tokens.len() + sample_len);
+        let mut caches = self.empty_caches(1)?;
+        let mut logits = None;
+        for token in &tokens {
+            let (mut outputs, new_caches) = self.step_batch(&[*token], Some(caches), true)?;
+            caches = new_caches;
+            let output = outputs.swap_remove(0);
+            history.extend(output.hidden);
+            logits = Some(output.logits);
+        }
+        let mut logits = logits.ok_or_else(|| anyhow::anyhow!("cannot prefill an empty prompt"))?;
+        let mut caches = self.select_cache_rows(&caches, &vec![0; top_k])?;
+        timer.prefilled();
+
+        let mut finish_reason = FinishReason::Length;
+        let mut i = 0;
+        while i < sample_len {
+            let candidates = sampling::top_k_candidates(&logits, top_k);
+            let mut inputs = candidates
+                .iter()
+                .map(|(token, _pr)| *token as usize)
+                .collect::<Vec<_>>();
+            inputs.resize(top_k, inputs[0]);
+            let (outputs, new_caches) = self.step_batch(&inputs, Some(caches), true)?;
+            let mut outputs = outputs
+                .into_iter()
+                .take(candidates.len())
+                .collect::<Vec<_>>();
+            let candidate_hidden = outputs
+                .iter()
+                .map(|output| output.hidden.clone().expect("hidden states were asked for"))
+                .collect::<Vec<_>>();
+            let pick = sampling::contrastive_pick(&candidates, &candidate_hidden, &history, alpha);
+            let next_token = candidates[pick].0 as usize;
+            tokens.push(next_token);
+            if next_token == eos_token {
+                finish_reason = FinishReason::Eos;
+                break;
+            }
+            timer.token();
+
+            // the picked candidate already stepped: its row becomes every row
+            caches = self.select_cache_rows(&new_caches, &vec![pick; top_k])?;
+            let picked = outputs.swap_remove(pick);
+            logits = picked.logits;
+            history.extend(picked.hidden);
+
+            // if the token has some valid representation, print it
+            if let Some(t) = self.tokenizer.next_token(next_token as u32) {
+                print!("{t}");
+                std::io::stdout().flush()?;
+            }
+
+            i += 1;
+        }
+        if let Some(rest) = self.tokenizer.decode_rest() {
+            print!("{rest}");
+        }
+        Ok(timer.finish(finish_reason))
+    }
+
+    /// Steps through every one of `tokens` from `caches`, returning the logits
+    /// that follow the last one.
+    pub fn prefill(
+        &self,
+        tokens: &[usize],
+        mut caches: MambaCaches,
+    ) -> anyhow::Result<(Vec<Precision>, MambaCaches)> {
+        let mut logits = None;
+        for token in tokens {
+            let (next_logits, new_caches) = self.step(*token, Some(caches))?;
+            logits = Some(next_logits);
+            caches = new_caches;
+        }
+        let logits = logits.ok_or_else(|| anyhow::anyhow!("cannot prefill an empty prompt"))?;
+        Ok((logits, caches))
     }
 
     /// Make a cached call to generate a logits.
@@ -588,21 +1329,128 @@ impl MambaWrapper {
         input: usize,
         caches: Option<MambaCaches>,
     ) -> anyhow::Result<(Vec<Precision>, MambaCaches)> {
+        let device = device(&self.mamba);
+        let input: Tensor<1, Int> = Tensor::from_data([input], &device);
+        let retry = self.check_finite.then(|| caches.clone());
+
+        let (logits, new_caches) = self.mamba.step(input.clone(), caches, None);
+        assert_eq!([1, self.padded_vocab_size()], logits.dims());
+
+        let mut logits = read_back(logits);
+        self.ensure_finite(&logits, "step", retry.flatten(), |mamba, caches| {
+            let (logits, caches) = mamba.step(input.clone(), caches, None);
+            (read_back(logits), caches)
+        })?;
+        self.mask_logits(&mut logits);
+
+        Ok((logits, new_caches))
+    }
+
+    /// Like [Self::step], but `sampler` picks the next token on the device, given
+    /// every token so far: only the token and its log-probability are read back.
+    pub fn step_sample(
+        &self,
+        input: usize,
+        caches: Option<MambaCaches>,
+        sampler: &mut sampling::device::DeviceSampler,
+        tokens: &[usize],
+    ) -> anyhow::Result<((u32, f32), MambaCaches)> {
         let device = device(&self.mamba);
         let input = Tensor::from_data([input], &device);
 
         let (logits, new_caches) = self.mamba.step(input, caches, None);
-        assert_eq!([1, self.padded_vocab_size()], logits.dims());
+        let logits =
+            sampling::device::mask_ids(logits.cast(PRECISION_FLOAT_D_TYPE), &self.masked_ids);
+        let picked = sampler.sample(logits, tokens)?;
 
-        let logits = logits
-            .cast(PRECISION_FLOAT_D_TYPE)
-            .into_data()
-            .to_vec::<Precision>()
-            .unwrap();
+        Ok((picked, new_caches))
+    }
+
+    /// Like [Self::step], for a batch of one token per cache row: the logits come
+    /// back as one row-major `[batch, padded_vocab]` buffer, which is what
+    /// [sampling::BatchLogitsProcessor] samples.
+    pub fn step_rows(
+        &self,
+        inputs: &[usize],
+        caches: Option<MambaCaches>,
+    ) -> anyhow::Result<(Vec<Precision>, MambaCaches)> {
+        let device = device(&self.mamba);
+        let input: Tensor<1, Int> =
+            Tensor::from_data(TensorData::new(inputs.to_vec(), [inputs.len()]), &device);
+        let retry = self.check_finite.then(|| caches.clone());
+
+        let (logits, new_caches) = self.mamba.step(input.clone(), caches, None);
+        assert_eq!([inputs.len(), self.padded_vocab_size()], logits.dims());
+
+        let mut logits = read_back(logits);
+        self.ensure_finite(&logits, "step", retry.flatten(), |mamba, caches| {
+            let (logits, caches) = mamba.step(input.clone(), caches, None);
+            (read_back(logits), caches)
+        })?;
+        for row in logits.chunks_exact_mut(self.padded_vocab_size()) {
+            self.mask_logits(row);
+        }
 
         Ok((logits, new_caches))
     }
 
+    /// Like [Self::step], for a batch of one token per cache row. With
+    /// `with_hidden`, each row also carries its final-layer hidden state: the
+    /// step runs through the model without its head ([split_lm_head]), and the
+    /// head is then applied to the hidden states it returned.
+    pub fn step_batch(
+        &self,
+        inputs: &[usize],
+        caches: Option<MambaCaches>,
+        with_hidden: bool,
+    ) -> anyhow::Result<(Vec<StepOutput>, MambaCaches)> {
+        let device = device(&self.mamba);
+        let input: Tensor<1, Int> =
+            Tensor::from_data(TensorData::new(inputs.to_vec(), [inputs.len()]), &device);
+        let retry = self.check_finite.then(|| caches.clone());
+
+        let (logits, hidden, new_caches) = if with_hidden {
+            let (headless, head) = self
+                .headless_mamba
+                .get_or_init(|| split_lm_head(&self.mamba, &device));
+            let (hidden, new_caches) = headless.step(input.clone(), caches, None);
+            (head.forward(hidden.clone()), Some(hidden), new_caches)
+        } else {
+            let (logits, new_caches) = self.mamba.step(input.clone(), caches, None);
+            (logits, None, new_caches)
+        };
+        let vocab = self.padded_vocab_size();
+        assert_eq!(logits.dims(), [inputs.len(), vocab]);
+
+        let logits = read_back(logits);
+        if self.check_finite {
+            self.ensure_finite(&logits, "step", retry.flatten(), |mamba, caches| {
+                let (logits, caches) = mamba.step(input.clone(), caches, None);
+                (read_back(logits), caches)
+            })?;
+        }
+        let hidden = hidden.map(|hidden| {
+            let [_, d_model] = hidden.dims();
+            (read_back(hidden), d_model)
+        });
+        let outputs = logits
+            .chunks_exact(vocab)
+            .enumerate()
+            .map(|(row, logits)| {
+                let mut logits = logits.to_vec();
+                self.mask_logits(&mut logits);
+                StepOutput {
+                    logits,
+                    hidden: hidden
+                        .as_ref()
+                        .map(|(hidden, d_model)| hidden[row * d_model..][..*d_model].to_vec()),
+                }
+            })
+            .collect();
+
+        Ok((outputs, new_caches))
+    }
+
     pub fn padded_vocab_size(&self) -> usize {
         padded_vocab_size(&self.mamba_config)
     }
@@ -621,9 +1469,42 @@ impl LogitsProcessorWrapper {
             logits_processor,
             repeat_penalty,
             repeat_last_n,
+            no_repeat_ngram_size: 0,
+            dry: None,
+            loop_stop: None,
+            sampled_from: None,
+            looped: None,
+            context: Vec::new(),
+            seen: Vec::new(),
         }
     }
 
+    /// Never samples a token that would repeat an `n`-gram of the last
+    /// `repeat_last_n` tokens.
+    pub fn with_no_repeat_ngram(mut self, n: usize) -> Self {
+        self.no_repeat_ngram_size = n;
+        self
+    }
+
+    /// Applies the [sampling::Dry] penalty over the last `repeat_last_n` tokens.
+    pub fn with_dry(mut self, dry: sampling::Dry) -> Self {
+        self.dry = Some(dry);
+        self
+    }
+
+    /// Flags a generation whose sampled tokens end in a block of at most
+    /// `max_period` tokens repeated `min_repeats` times; see [Self::looped].
+    pub fn with_loop_stop(mut self, max_period: usize, min_repeats: usize) -> Self {
+        self.loop_stop = Some((max_period, min_repeats));
+        self
+    }
+
+    /// The period of the verbatim loop the last sampled token completed, when
+    /// [Self::with_loop_stop] is on. The run loops stop on it as they do on eos.
+    pub fn looped(&self) -> Option<usize> {
+        self.looped
+    }
+
     /// Add logits that represents a token.
     ///
     /// `i` is the i-th call. For the first call, `i` should be `0`.
@@ -633,13 +1514,9 @@ impl LogitsProcessorWrapper {
         tokens: &mut Vec<usize>,
         mut logits: Vec<Precision>,
     ) -> anyhow::Result<usize> {
-        if self.repeat_penalty != 1. {
-            let start_at = i.saturating_sub(self.repeat_last_n);
-            let context = tokens[start_at..i + 1]
-                .iter()
-                .map(|e| *e as u32)
-                .collect::<Vec<u32>>();
-            sampling::apply_repeat_penalty(&mut logits, self.repeat_penalty, &context);
+        if i == 0 {
+            self.looped = None;
+            self.sampled_from = None;
         }
 
         let next_token;
@@ -651,14 +1528,40 @@ impl LogitsProcessorWrapper {
             // should it still sample? idk
             // let _discarded_token = logits_processor.sample(&logits)?;
         } else {
+            let sampled_from = *self.sampled_from.get_or_insert(i + 1);
+            self.apply_penalties(i, tokens, &mut logits);
+
             // try to predict the next token
             next_token = self.logits_processor.sample(&logits)? as usize;
             // add the token to the "tokens" list
             tokens.push(next_token);
             // *generated_tokens += 1;
+
+            if let Some((max_period, min_repeats)) = self.loop_stop {
+                let sampled = &tokens[sampled_from.min(tokens.len())..];
+                self.looped = sampling::detect_loop(sampled, max_period, min_repeats);
+            }
         }
         Ok(next_token)
     }
+
+    /// Every enabled penalty, over the last `repeat_last_n` tokens up to `i`.
+    fn apply_penalties(&mut self, i: usize, tokens: &[usize], logits: &mut [Precision]) {
+        if self.repeat_penalty == 1. && self.no_repeat_ngram_size == 0 && self.dry.is_none() {
+            return;
+        }
+        let start_at = i.saturating_sub(self.repeat_last_n);
+        let context = &mut self.context;
+        context.clear();
+        context.extend(tokens[start_at..i + 1].iter().map(|e| *e as u32));
+        if self.repeat_penalty != 1. {
+            sampling::apply_repeat_penalty(logits, self.repeat_penalty, context, &mut self.seen);
+        }
+        sampling::apply_no_repeat_ngram(logits, self.no_repeat_ngram_size, context);
+        if let Some(dry) = &self.dry {
+            sampling::apply_dry_penalty(logits, dry, context);
+        }
+    }
 }
 
 #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
@@ -673,6 +1576,44 @@ pub fn device(model: &MambaVocabNet) -> Device {
     }
 }
 
+/// Whether a run loop stops at `next_token`, and why.
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub(crate) fn finish_reason_of(
+    next_token: usize,
+    eos_token: usize,
+    logits_processor: &LogitsProcessorWrapper,
+) -> Option<FinishReason> {
+    if next_token == eos_token {
+        Some(FinishReason::Eos)
+    } else {
+        logits_processor.looped().map(|_| FinishReason::Loop)
+    }
+}
+
+/// Reads `logits` back to the host as [Precision].
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+fn read_back<const D: usize>(logits: Tensor<D>) -> Vec<Precision> {
+    // `into_vec` takes the readback buffer over instead of copying it
+    logits
+        .cast(PRECISION_FLOAT_D_TYPE)
+        .into_data()
+        .into_vec::<Precision>()
+        .unwrap()
+}
+
+/// The checkpoint's *unpadded* vocabulary — the range a real token id lives in.
+#[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+pub fn vocab_size(config: &MambaVocabNetConfig) -> usize {
+    match config {
+        #[cfg(feature = "mamba1")]
+        MambaVocabNetConfig::Mamba1 { vocab_size, .. } => *vocab_size,
+        #[cfg(feature = "mamba2")]
+        MambaVocabNetConfig::Mamba2 { vocab_size, .. } => *vocab_size,
+        #[cfg(feature = "mamba3")]
+        MambaVocabNetConfig::Mamba3 { vocab_size, .. } => *vocab_size,
+    }
+}
+
 #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
 #[allow(irrefutable_let_patterns)]
 pub fn padded_vocab_size(config: &MambaVocabNetConfig) -> usize {
@@ -704,6 +1645,26 @@ pub fn padded_vocab_size(config: &MambaVocabNetConfig) -> usize {
     }
 }
 
+/// Row `rows[i]` of `data`'s first dim as its row `i`, whatever the dtype.
+fn select_rows(data: TensorData, rows: &[usize]) -> anyhow::Result<TensorData> {
+    let shape = data.shape.to_vec();
+    let Some((&batch, rest)) = shape.split_first() else {
+        anyhow::bail!("a scalar has no rows");
+    };
+    if let Some(row) = rows.iter().find(|row| **row >= batch) {
+        anyhow::bail!("no row {row} in a batch of {batch}");
+    }
+    let bytes = data.as_bytes();
+    let row_len = bytes.len() / batch.max(1);
+    let selected = rows
+        .iter()
+        .flat_map(|row| &bytes[row * row_len..(row + 1) * row_len])
+        .copied()
+        .collect::<Vec<u8>>();
+    let shape = [&[rows.len()], rest].concat();
+    Ok(TensorData::from_bytes_vec(selected, shape, data.dtype))
+}
+
 #[cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
 pub fn empty_caches(
     batch: usize,
@@ -766,11 +1727,61 @@ pub fn empty_caches(
 mod tests {
     use super::*;
 
+    /// Each run's loop check starts at its own prompt's end, however long the
+    /// previous run's prompt was.
+    #[test]
+    fn loops_are_looked_for_past_each_runs_prompt() {
+        let mut processor = LogitsProcessorWrapper::new(0, None, None, 1., 64).with_loop_stop(1, 3);
+        let logits = |token: usize| {
+            let mut logits = vec![0.; 8];
+            logits[token] = 1.;
+            logits
+        };
+        let mut tokens = vec![5];
+        processor.add_logits(0, &mut tokens, logits(1)).unwrap();
+        assert_eq!(tokens, [5, 1]);
+
+        // a prompt that is itself a loop: only the sampled `2` counts
+        let mut tokens = vec![2; 4];
+        for i in 0..4 {
+            processor.add_logits(i, &mut tokens, logits(2)).unwrap();
+        }
+        assert_eq!(tokens, [2; 5]);
+        assert_eq!(processor.looped(), None);
+    }
+
+    #[test]
+    fn rows_are_selected_whole() {
+        let data = TensorData::new((0..12).map(|v| v as f32).collect(), [3, 2, 2]);
+        let selected = select_rows(data, &[2, 0, 2]).unwrap();
+        assert_eq!(selected.shape.to_vec(), [3, 2, 2]);
+        assert_eq!(
+            selected.to_vec::<f32>().unwrap(),
+            [8., 9., 10., 11., 0., 1., 2., 3., 8., 9., 10., 11.]
+        );
+        let repeated = select_rows(TensorData::new(vec![7i64, 8], [1, 2]), &[0; 3]).unwrap();
+        assert_eq!(repeated.to_vec::<i64>().unwrap(), [7, 8, 7, 8, 7, 8]);
+        assert!(select_rows(TensorData::new(vec![1f32], [1]), &[1]).is_err());
+    }
+
     /// Highest priority first, and every [ModelSpec::id] resolvable — that
     /// ordering is what the entry points take when they run a single model.
     #[test]
     fn models_are_listed_by_descending_priority() {
-        const PRIORITY: [&str; 4] = ["mamba3-mimo", "mamba3-siso", "mamba2", "mamba1"];
+        const PRIORITY: [&str; 12] = [
+            "mamba3-mimo",
+            "mamba3-siso",
+            "mamba2",
+            "mamba1",
+            "mamba2-370m",
+            "mamba2-780m",
+            "mamba2-1.3b",
+            "mamba2-2.7b",
+            "mamba1-370m",
+            "mamba1-790m",
+            "mamba1-1.4b",
+            "mamba1-2.8b",
+        ];
 
         let ids = hf::ids();
         let ranks: Vec<usize> = ids
@@ -806,7 +1817,7 @@ mod tests {
         assert_ne!(checkpoints[0].1, checkpoints[1].1);
 
         for (spec, chunk_size) in checkpoints {
-            let path = (spec.ssd_path)();
+            let path = spec.ssd_path();
             let MambaSsdPath::Mamba3(Mamba3SsdPath::SerialRecalculated(Some(got))) = path else {
                 panic!("{}: expected a chunked Mamba-3 scan, got {path:?}", spec.id)
             };
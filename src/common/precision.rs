@@ -0,0 +1,165 @@
+//! The float type a model runs in, chosen at runtime.
+//!
+//! The mamba2 and mamba3 checkpoints are stored in half precision, and an f32
+//! model of them takes twice their memory. [RuntimePrecision] keeps them — or
+//! casts any checkpoint — to f16 or bf16 instead: it is the dtype [load_mamba]
+//! casts the weights to and the default `Device::configure` installs, so the
+//! activations and the caches follow.
+//!
+//! The mixed variants keep the parameters the recurrence is sensitive to in
+//! f32: the decay `a_log`, the skip `d`, the `dt_bias` that feeds the softplus,
+//! and every norm's scale. They are a sliver of the model's size. Whether a
+//! backend accepts their f32 operands next to half-precision activations is up
+//! to the backend.
+//!
+//! Logits are read back as [crate::Precision] (f32) whatever the model runs in:
+//! sampling, penalties and the evaluation modes all happen there.
+//!
+//! [load_mamba]: crate::load_mamba
+
+use burn::tensor::{FloatDType, IntDType};
+
+/// See the module docs. Parsed from `f32`, `f16`, `bf16`, `f16-mixed` and
+/// `bf16-mixed`, which is also how it displays.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RuntimePrecision {
+    #[default]
+    F32,
+    F16 {
+        /// Keeps the sensitive parameters in f32.
+        mixed: bool,
+    },
+    Bf16 {
+        /// Keeps the sensitive parameters in f32.
+        mixed: bool,
+    },
+}
+
+impl RuntimePrecision {
+    /// What `MAMBA_PRECISION` names, f32 when it is unset.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var("MAMBA_PRECISION") {
+            Ok(precision) => precision
+                .parse()
+                .map_err(|e| anyhow::anyhow!("MAMBA_PRECISION: {e}")),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// The dtype of the weights, the activations and the caches.
+    pub fn float_dtype(self) -> FloatDType {
+        match self {
+            Self::F32 => FloatDType::F32,
+            Self::F16 { .. } => FloatDType::F16,
+            Self::Bf16 { .. } => FloatDType::BF16,
+        }
+    }
+
+    /// The defaults `Device::configure` takes.
+    pub fn device_dtypes(self) -> (FloatDType, IntDType) {
+        (self.float_dtype(), crate::PRECISION_INT_D_TYPE)
+    }
+
+    pub fn is_mixed(self) -> bool {
+        matches!(self, Self::F16 { mixed: true } | Self::Bf16 { mixed: true })
+    }
+
+    /// The dtype of the parameter at `path`, a Burn module path such as
+    /// `layers.real_layers.3.mamba_block.a_log_h`.
+    pub fn param_dtype(self, path: &str) -> FloatDType {
+        match self.is_mixed() && keeps_f32(path) {
+            true => FloatDType::F32,
+            false => self.float_dtype(),
+        }
+    }
+}
+
+/// Whether a mixed-precision model keeps the parameter at `path` in f32.
+pub fn keeps_f32(path: &str) -> bool {
+    let mut segments = path.rsplit('.');
+    let (param, module) = (segments.next(), segments.next());
+    match module {
+        Some("mamba_block") => {
+            matches!(param, Some("a_log" | "a_log_h" | "d" | "d_h" | "dt_bias_h"))
+        }
+        // `norm`, `norm_f`, `norm2`, the Mamba-3 `b_norm`/`c_norm`, …
+        Some(module) => module.contains("norm"),
+        None => false,
+    }
+}
+
+impl std::str::FromStr for RuntimePrecision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (dtype, mixed) = match s.strip_suffix("-mixed") {
+            Some(dtype) => (dtype, true),
+            None => (s, false),
+        };
+        match (dtype.to_ascii_lowercase().as_str(), mixed) {
+            ("f32", false) => Ok(Self::F32),
+            ("f16", mixed) => Ok(Self::F16 { mixed }),
+            ("bf16", mixed) => Ok(Self::Bf16 { mixed }),
+            _ => anyhow::bail!("expected f32, f16, bf16, f16-mixed or bf16-mixed, got {s:?}"),
+        }
+    }
+}
+
+impl std::fmt::Display for RuntimePrecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (dtype, mixed) = match *self {
+            Self::F32 => ("f32", false),
+            Self::F16 { mixed } => ("f16", mixed),
+            Self::Bf16 { mixed } => ("bf16", mixed),
+        };
+        match mixed {
+            true => write!(f, "{dtype}-mixed"),
+            false => write!(f, "{dtype}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_it_displays() {
+        for s in ["f32", "f16", "bf16", "f16-mixed", "bf16-mixed"] {
+            let precision: RuntimePrecision = s.parse().unwrap();
+            assert_eq!(precision.to_string(), s);
+        }
+        assert_eq!(
+            "BF16".parse::<RuntimePrecision>().unwrap().to_string(),
+            "bf16"
+        );
+        assert!("f32-mixed".parse::<RuntimePrecision>().is_err());
+        assert!("f64".parse::<RuntimePrecision>().is_err());
+    }
+
+    #[test]
+    fn mixed_keeps_the_recurrence_parameters_in_f32() {
+        let mixed = RuntimePrecision::Bf16 { mixed: true };
+        for path in [
+            "layers.real_layers.0.mamba_block.a_log_h",
+            "layers.real_layers.0.mamba_block.d",
+            "layers.real_layers.0.mamba_block.dt_bias_h",
+            "layers.real_layers.0.mamba_block.norm.gamma",
+            "layers.real_layers.0.mamba_block.c_norm.gamma",
+            "layers.real_layers.0.norm.gamma",
+            "norm_f.gamma",
+        ] {
+            assert_eq!(mixed.param_dtype(path), FloatDType::F32, "{path}");
+        }
+        for path in [
+            "embedding.weight",
+            "lm_head.weight",
+            "layers.real_layers.0.mamba_block.in_proj.weight",
+            "layers.real_layers.0.mamba_block.conv1d.weight",
+        ] {
+            assert_eq!(mixed.param_dtype(path), FloatDType::BF16, "{path}");
+        }
+        let plain = RuntimePrecision::Bf16 { mixed: false };
+        assert_eq!(plain.param_dtype("norm_f.gamma"), FloatDType::BF16);
+    }
+}
@@ -0,0 +1,336 @@
+//! An opt-in cache of loaded models, in Burn's own record format.
+//!
+//! [load_mamba] re-does the whole import on every start: the key remapping,
+//! the `Linear` transposes, the cast of every tensor to the [RuntimePrecision]
+//! and the transpose of the tied head. A [RecordCache] keeps the result, already
+//! adapted, as a `NamedMpk` record, and later runs load that instead.
+//!
+//! A record is keyed by the checkpoint files it came from (a hub blob is named
+//! by its etag; any other file by its path, size and modification time) and by
+//! the topology, so a new revision or a changed config gets a record of its
+//! own. Checkpoints that are only bytes — fetched in memory, or a GGUF — have no
+//! such identity, and are always imported.
+//!
+//! The key holds the [RuntimePrecision] too, since the import casts the
+//! weights to it. A record is always written in f32, which holds the values of
+//! every precision exactly, but Burn reads it back in the device's default
+//! float; a half-precision record is therefore cast to its precision again on
+//! load. A mixed one keeps its f32 parameters in a small safetensors file next
+//! to the record as well, since the default float would have rounded them.
+//!
+//! Next to each record, the time its import took is kept, so a run that loads
+//! the record logs how much of the start-up it saved.
+
+use crate::precision::keeps_f32;
+use crate::{Checkpoint, RuntimePrecision, cast_mamba, load_mamba};
+use burn::prelude::*;
+use burn::record::{FullPrecisionSettings, NamedMpkFileRecorder};
+use burn::store::{FloatCastAdapter, ModuleSnapshot, PathFilter, SafetensorsStore};
+use burn::tensor::FloatDType;
+use burn_mamba::prelude::*;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// A directory of records, see the module docs.
+#[derive(Clone, Debug)]
+pub struct RecordCache {
+    dir: PathBuf,
+}
+
+/// Kept next to each record.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Timing {
+    /// How long [load_mamba] took to produce the record.
+    import_ms: f64,
+}
+
+impl RecordCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// The directory `MAMBA_RECORD_CACHE` names, if it is set.
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os("MAMBA_RECORD_CACHE").map(Self::new)
+    }
+
+    /// The model the cached record holds, else [load_mamba]'s, which is then
+    /// recorded. Failing to read or to write a record is only a warning: the
+    /// checkpoint is still there to import.
+    pub fn load(
+        &self,
+        checkpoint: Checkpoint,
+        mamba_config: MambaVocabNetConfig,
+        precision: RuntimePrecision,
+        device: &Device,
+    ) -> anyhow::Result<MambaVocabNet> {
+        let Some(key) = key(&checkpoint, &mamba_config, precision)? else {
+            log::info!("the checkpoint has no file identity to cache its record under");
+            return load_mamba(checkpoint, mamba_config, precision, device);
+        };
+        let record = self.dir.join(format!("{key}.mpk"));
+
+        if record.is_file() {
+            let start = Instant::now();
+            match self.read(&key, &mamba_config, precision, device) {
+                Ok(mamba) => {
+                    let load_ms = start.elapsed().as_secs_f64() * 1000.;
+                    match self.timing(&key) {
+                        Some(Timing { import_ms }) => log::info!(
+                            "loaded the record {record:?} in {load_ms:.0}ms, against \
+                             {import_ms:.0}ms to import the checkpoint: {:.0}ms saved \
+                             ({:.1}x faster)",
+                            import_ms - load_ms,
+                            import_ms / load_ms
+                        ),
+                        None => log::info!("loaded the record {record:?} in {load_ms:.0}ms"),
+                    }
+                    return Ok(mamba);
+                }
+                Err(e) => log::warn!("ignoring the unreadable record {record:?}: {e:?}"),
+            }
+        }
+
+        let start = Instant::now();
+        let mamba = load_mamba(checkpoint, mamba_config, precision, device)?;
+        let import_ms = start.elapsed().as_secs_f64() * 1000.;
+        match self.insert(&key, &mamba, precision, import_ms) {
+            Ok(()) => log::info!("recorded the imported model as {record:?}"),
+            Err(e) => log::warn!("failed to record the imported model: {e}"),
+        }
+        Ok(mamba)
+    }
+
+    /// The model of the record under `key`, at `precision`.
+    fn read(
+        &self,
+        key: &str,
+        mamba_config: &MambaVocabNetConfig,
+        precision: RuntimePrecision,
+        device: &Device,
+    ) -> anyhow::Result<MambaVocabNet> {
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+        // the record holds the head, tied or not, so one is built to take it
+        let mut with_head = mamba_config.clone();
+        crate::store_load::set_missing_lm_head(&mut with_head, false);
+        let mut mamba = with_head
+            .init(device)
+            .load_file(self.dir.join(format!("{key}.mpk")), &recorder, device)
+            .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        if precision != RuntimePrecision::F32 {
+            cast_mamba(&mut mamba, precision)?;
+        }
+        if precision.is_mixed() {
+            let mut store = SafetensorsStore::from_file(self.kept_f32(key))
+                .allow_partial(true)
+                .with_from_adapter(FloatCastAdapter::to(FloatDType::F32.into()));
+            let result = mamba
+                .load_from(&mut store)
+                .map_err(|e| anyhow::anyhow!("failed to load the f32 parameters: {e}"))?;
+            anyhow::ensure!(
+                !result.applied.is_empty(),
+                "the record has no f32 parameters"
+            );
+        }
+        Ok(mamba)
+    }
+
+    /// The f32 parameters of a mixed-precision record.
+    fn kept_f32(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}-f32.safetensors"))
+    }
+
+    fn timing(&self, key: &str) -> Option<Timing> {
+        let json = std::fs::read(self.dir.join(format!("{key}.json"))).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    /// Writes the record under a temporary name first, so an interrupted
+    /// write never leaves a truncated record behind.
+    fn insert(
+        &self,
+        key: &str,
+        mamba: &MambaVocabNet,
+        precision: RuntimePrecision,
+        import_ms: f64,
+    ) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let recorder = NamedMpkFileRecorder::<FullPrecisionSettings>::new();
+        let partial = self.dir.join(format!("{key}-partial"));
+        mamba
+            .clone()
+            .save_file(&partial, &recorder)
+            .map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        if precision.is_mixed() {
+            let mut store = SafetensorsStore::from_file(self.kept_f32(key))
+                .filter(PathFilter::from_predicate(|path, _| keeps_f32(path)));
+            mamba
+                .save_into(&mut store)
+                .map_err(|e| anyhow::anyhow!("failed to save the f32 parameters: {e}"))?;
+        }
+        std::fs::write(
+            self.dir.join(format!("{key}.json")),
+            serde_json::to_vec(&Timing { import_ms })?,
+        )?;
+        std::fs::rename(
+            partial.with_extension("mpk"),
+            self.dir.join(format!("{key}.mpk")),
+        )?;
+        Ok(())
+    }
+}
+
+/// The record's file stem: a hash of everything the adapted model depends on,
+/// or `None` for a checkpoint without files.
+fn key(
+    checkpoint: &Checkpoint,
+    mamba_config: &MambaVocabNetConfig,
+    precision: RuntimePrecision,
+) -> anyhow::Result<Option<String>> {
+    let mut files = Vec::new();
+    if !identify(checkpoint, &mut files)? {
+        return Ok(None);
+    }
+    let identity = format!(
+        "{}\n{mamba_config:?}\n{precision}\n{}",
+        files.join("\n"),
+        // the module layout the record follows can change with the crate
+        env!("CARGO_PKG_VERSION"),
+    );
+    Ok(Some(format!("{:016x}", fnv1a(identity.as_bytes()))))
+}
+
+/// Pushes one line per file of `checkpoint` onto `files`; `false` if any part
+/// of it is not a file.
+fn identify(checkpoint: &Checkpoint, files: &mut Vec<String>) -> anyhow::Result<bool> {
+    match checkpoint {
+        Checkpoint::File(path) => {
+            files.push(file_identity(path)?);
+            Ok(true)
+        }
+        Checkpoint::Sharded(shards) => {
+            for shard in shards {
+                if !identify(shard, files)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        Checkpoint::Bytes(_) | Checkpoint::Gguf(_) => Ok(false),
+    }
+}
+
+/// A hub blob's etag — its file name in the cache's `blobs/` — else the
+/// path, size and modification time.
+fn file_identity(path: &Path) -> anyhow::Result<String> {
+    let path = std::fs::canonicalize(path)
+        .map_err(|e| anyhow::anyhow!("failed to resolve {path:?}: {e}"))?;
+    let in_blobs = path
+        .parent()
+        .and_then(Path::file_name)
+        .is_some_and(|dir| dir == "blobs");
+    if let (true, Some(etag)) = (in_blobs, path.file_name()) {
+        return Ok(format!("etag {}", etag.to_string_lossy()));
+    }
+    let metadata = std::fs::metadata(&path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!(
+        "file {path:?} {} {}",
+        metadata.len(),
+        modified.as_nanos()
+    ))
+}
+
+/// 64-bit FNV-1a: stable across builds and platforms, unlike the std hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blobs_are_identified_by_their_etag() {
+        let dir = std::env::temp_dir().join(format!("record-cache-{}", std::process::id()));
+        let blobs = dir.join("blobs");
+        std::fs::create_dir_all(&blobs).unwrap();
+        let blob = blobs.join("0123abcd");
+        std::fs::write(&blob, b"weights").unwrap();
+        let other = dir.join("model.safetensors");
+        std::fs::write(&other, b"weights").unwrap();
+
+        assert_eq!(file_identity(&blob).unwrap(), "etag 0123abcd");
+        assert!(file_identity(&other).unwrap().starts_with("file "));
+
+        let mut files = Vec::new();
+        let sharded = Checkpoint::Sharded(vec![Checkpoint::File(blob), Checkpoint::File(other)]);
+        assert!(identify(&sharded, &mut files).unwrap());
+        assert_eq!(files.len(), 2);
+        assert!(!identify(&Checkpoint::Bytes(Vec::new()), &mut files).unwrap());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// A model read from its record is the one imported, parameter for
+    /// parameter and dtype for dtype, with its head as tied or untied as the
+    /// checkpoint's.
+    #[test]
+    fn records_load_back_as_the_import() {
+        use crate::store_load::fixtures;
+        let device: Device = Default::default();
+        let dir = std::env::temp_dir().join(format!("record-round-trip-{}", std::process::id()));
+        let configs: &[&str] = &[
+            #[cfg(feature = "mamba1")]
+            r#"{"d_model": 32, "n_layer": 2, "vocab_size": 64,
+                "ssm_cfg": {"d_state": 8, "d_conv": 4, "expand": 2}}"#,
+            #[cfg(feature = "mamba2")]
+            r#"{"d_model": 32, "n_layer": 2, "vocab_size": 64,
+                "ssm_cfg": {"layer": "Mamba2", "d_state": 16, "d_conv": 4, "expand": 2,
+                            "headdim": 16, "ngroups": 1}}"#,
+        ];
+        for (i, (config, tied)) in configs
+            .iter()
+            .flat_map(|config| [(config, true), (config, false)])
+            .enumerate()
+        {
+            let config = crate::config_json::ConfigJson::from_bytes(config.as_bytes())
+                .and_then(|config| config.vocab_net_config())
+                .unwrap();
+            let (_, bytes, config) = fixtures::exported(&config, tied, &device);
+            let exported = dir.join(i.to_string());
+            std::fs::create_dir_all(&exported).unwrap();
+            std::fs::write(exported.join("model.safetensors"), bytes).unwrap();
+
+            let cache = RecordCache::new(dir.join("records"));
+            for precision in [
+                RuntimePrecision::F32,
+                RuntimePrecision::Bf16 { mixed: true },
+            ] {
+                let checkpoint = || Checkpoint::File(exported.join("model.safetensors"));
+                let imported = cache
+                    .load(checkpoint(), config.clone(), precision, &device)
+                    .unwrap();
+                let key = key(&checkpoint(), &config, precision).unwrap().unwrap();
+                let recorded = cache.read(&key, &config, precision, &device).unwrap();
+
+                let id = format!("{precision}, tied {tied}");
+                fixtures::assert_same_params(&imported, &recorded, &id);
+                let head_is_tied = crate::store_load::head_is_tied;
+                assert_eq!(head_is_tied(&recorded).unwrap(), tied, "{precision}");
+            }
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fnv1a_matches_the_reference_vectors() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+}
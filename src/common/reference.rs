@@ -0,0 +1,276 @@
+//! Reference logits: a fixed token sequence and the logits another
+//! implementation — the upstream `state-spaces/mamba` PyTorch code — produced
+//! for it, read from a local safetensors or `.npy` file.
+//!
+//! Only what such a dump needs is read: little-endian, C-ordered arrays of
+//! f16, bf16, f32 or f64 logits and of integer token ids, all widened to f32
+//! and `usize`. A safetensors file is read by burn-store; a `.npy` header is
+//! parsed here, its data then going through the same [TensorData].
+
+use burn::store::{ModuleStore, SafetensorsStore};
+use burn::tensor::{DType, TensorData};
+use std::collections::BTreeMap;
+
+/// Logits recorded for `tokens`, `[tokens.len(), vocab]` row-major.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReferenceLogits {
+    pub tokens: Vec<usize>,
+    pub vocab: usize,
+    pub logits: Vec<f32>,
+}
+
+impl ReferenceLogits {
+    /// Reads a safetensors file holding `input_ids`, `[len]` or `[1, len]`, and
+    /// `logits`, `[len, vocab]` or `[1, len, vocab]`.
+    pub fn from_safetensors(bytes: &[u8]) -> anyhow::Result<Self> {
+        let arrays = safetensors(bytes)?;
+        let array = |name: &str| {
+            arrays
+                .get(name)
+                .ok_or_else(|| anyhow::anyhow!("no {name:?} tensor; found {:?}", arrays.keys()))
+        };
+        Self::new(ints(array("input_ids")?)?, array("logits")?)
+    }
+
+    /// Reads the logits from a `.npy` array, `[len, vocab]` or `[1, len,
+    /// vocab]`, for the given `tokens` — which a bare array cannot carry.
+    pub fn from_npy(bytes: &[u8], tokens: Vec<usize>) -> anyhow::Result<Self> {
+        Self::new(tokens, &npy(bytes)?)
+    }
+
+    fn new(tokens: Vec<usize>, logits: &TensorData) -> anyhow::Result<Self> {
+        let shape: Vec<usize> = match logits.shape.as_slice() {
+            [1, rest @ ..] if rest.len() == 2 => rest.to_vec(),
+            shape => shape.to_vec(),
+        };
+        let [len, vocab] = shape[..] else {
+            anyhow::bail!("expected [len, vocab] logits, got {:?}", logits.shape);
+        };
+        anyhow::ensure!(
+            len == tokens.len(),
+            "{len} rows of logits for {} tokens",
+            tokens.len()
+        );
+        Ok(Self {
+            tokens,
+            vocab,
+            logits: floats(logits)?,
+        })
+    }
+}
+
+/// Token ids written out as `.npy`, `[len]` or `[1, len]`, or as a
+/// comma-separated list.
+pub fn parse_tokens(npy_or_list: &[u8]) -> anyhow::Result<Vec<usize>> {
+    if npy_or_list.starts_with(NPY_MAGIC) {
+        return ints(&npy(npy_or_list)?);
+    }
+    std::str::from_utf8(npy_or_list)?
+        .split(',')
+        .map(|id| {
+            id.trim()
+                .parse()
+                .map_err(|e| anyhow::anyhow!("{id:?} is not a token id: {e}"))
+        })
+        .collect()
+}
+
+fn floats(array: &TensorData) -> anyhow::Result<Vec<f32>> {
+    anyhow::ensure!(
+        matches!(
+            array.dtype,
+            DType::F16 | DType::BF16 | DType::F32 | DType::F64
+        ),
+        "expected float logits, got {:?}",
+        array.dtype
+    );
+    array
+        .clone()
+        .convert::<f32>()
+        .to_vec::<f32>()
+        .map_err(|e| anyhow::anyhow!("unreadable logits: {e:?}"))
+}
+
+fn ints(array: &TensorData) -> anyhow::Result<Vec<usize>> {
+    anyhow::ensure!(
+        matches!(array.dtype, DType::I32 | DType::U32 | DType::I64),
+        "expected integer token ids, got {:?}",
+        array.dtype
+    );
+    anyhow::ensure!(
+        matches!(array.shape.as_slice(), [_] | [1, _]),
+        "expected [len] token ids, got {:?}",
+        array.shape
+    );
+    array
+        .clone()
+        .convert::<i64>()
+        .to_vec::<i64>()
+        .map_err(|e| anyhow::anyhow!("unreadable token ids: {e:?}"))?
+        .into_iter()
+        .map(usize::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow::anyhow!("negative token id: {e}"))
+}
+
+/// The arrays of a safetensors file, by name.
+fn safetensors(bytes: &[u8]) -> anyhow::Result<BTreeMap<String, TensorData>> {
+    let mut store = SafetensorsStore::from_bytes(Some(bytes.to_vec()));
+    let arrays = store
+        .get_all_snapshots()
+        .map_err(|e| anyhow::anyhow!("not a safetensors file: {e}"))?;
+    arrays
+        .iter()
+        .map(|(name, array)| {
+            let data = array
+                .to_data()
+                .map_err(|e| anyhow::anyhow!("{name:?} is unreadable: {e:?}"))?;
+            Ok((name.clone(), data))
+        })
+        .collect()
+}
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+/// The array of a `.npy` file: a magic string and version, a header length
+/// (2 bytes in version 1, 4 after), a Python dict literal header, then the data.
+fn npy(bytes: &[u8]) -> anyhow::Result<TensorData> {
+    anyhow::ensure!(
+        bytes.starts_with(NPY_MAGIC) && bytes.len() >= 10,
+        "not a .npy file"
+    );
+    let (header_len, header_start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 => {
+            anyhow::ensure!(bytes.len() >= 12, "not a .npy file");
+            let len = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+            (len as usize, 12)
+        }
+        version => anyhow::bail!("unsupported .npy version {version}"),
+    };
+    let header = bytes
+        .get(header_start..header_start + header_len)
+        .ok_or_else(|| anyhow::anyhow!("the .npy header runs past the file"))?;
+    let header = std::str::from_utf8(header)?;
+
+    // `{'descr': '<f4', 'fortran_order': False, 'shape': (3, 4), }`
+    let value = |key: &str| {
+        let start = header
+            .find(&format!("'{key}':"))
+            .ok_or_else(|| anyhow::anyhow!("the .npy header lacks {key:?}: {header}"))?;
+        Ok::<_, anyhow::Error>(header[start + key.len() + 3..].trim_start())
+    };
+    anyhow::ensure!(
+        value("fortran_order")?.starts_with("False"),
+        "Fortran-ordered .npy arrays are not supported"
+    );
+    let descr = value("descr")?;
+    let descr = descr
+        .strip_prefix('\'')
+        .and_then(|d| d.split('\'').next())
+        .ok_or_else(|| anyhow::anyhow!("invalid .npy descr in {header}"))?;
+    let dtype = match descr {
+        "<f2" => DType::F16,
+        "<f4" => DType::F32,
+        "<f8" => DType::F64,
+        "<i4" => DType::I32,
+        "<i8" => DType::I64,
+        "<u4" => DType::U32,
+        descr => anyhow::bail!("unsupported .npy dtype {descr:?}"),
+    };
+    let shape = value("shape")?;
+    let shape = shape
+        .strip_prefix('(')
+        .and_then(|s| s.split(')').next())
+        .ok_or_else(|| anyhow::anyhow!("invalid .npy shape in {header}"))?
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(|dim| dim.parse::<usize>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let data = &bytes[header_start + header_len..];
+    let len = shape.iter().product::<usize>() * dtype.size();
+    anyhow::ensure!(
+        data.len() == len,
+        "{dtype:?} {shape:?} array holds {} bytes",
+        data.len()
+    );
+    Ok(TensorData::from_bytes_vec(data.to_vec(), shape, dtype))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn npy_file(descr: &str, shape: &str, data: &[u8]) -> Vec<u8> {
+        let mut header =
+            format!("{{'descr': '{descr}', 'fortran_order': False, 'shape': {shape}, }}");
+        // padded so the data starts 64-byte aligned, newline-terminated
+        while (10 + header.len() + 1) % 64 != 0 {
+            header.push(' ');
+        }
+        header.push('\n');
+        let mut file = NPY_MAGIC.to_vec();
+        file.extend([1, 0]);
+        file.extend((header.len() as u16).to_le_bytes());
+        file.extend(header.as_bytes());
+        file.extend(data);
+        file
+    }
+
+    #[test]
+    fn reads_npy_logits_and_ids() {
+        let logits: Vec<u8> = [0.5f32, -1., 2., 3.25, 0., 1.]
+            .iter()
+            .flat_map(|l| l.to_le_bytes())
+            .collect();
+        let ids: Vec<u8> = [7i64, 3].iter().flat_map(|i| i.to_le_bytes()).collect();
+
+        let tokens = parse_tokens(&npy_file("<i8", "(2,)", &ids)).unwrap();
+        assert_eq!(tokens, vec![7, 3]);
+        assert_eq!(parse_tokens(b"7, 3").unwrap(), vec![7, 3]);
+
+        let reference = ReferenceLogits::from_npy(&npy_file("<f4", "(1, 2, 3)", &logits), tokens);
+        let reference = reference.unwrap();
+        assert_eq!(reference.vocab, 3);
+        assert_eq!(reference.logits, vec![0.5, -1., 2., 3.25, 0., 1.]);
+
+        let mismatched = ReferenceLogits::from_npy(&npy_file("<f4", "(3, 2)", &logits), vec![7, 3]);
+        assert!(mismatched.is_err());
+    }
+
+    #[test]
+    fn reads_safetensors_logits_and_ids() {
+        // bf16 logits: the top half of the f32 bits
+        let logits: Vec<u8> = [1.5f32, -2., 0.25, 4.]
+            .iter()
+            .flat_map(|l| ((l.to_bits() >> 16) as u16).to_le_bytes())
+            .collect();
+        let ids: Vec<u8> = [1i32, 2].iter().flat_map(|i| i.to_le_bytes()).collect();
+        let header = format!(
+            r#"{{"__metadata__":{{"model":"mamba-130m"}},"input_ids":{{"dtype":"I32","shape":[1,2],"data_offsets":[0,8]}},"logits":{{"dtype":"BF16","shape":[1,2,2],"data_offsets":[8,{}]}}}}"#,
+            8 + logits.len()
+        );
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend(header.as_bytes());
+        file.extend(&ids);
+        file.extend(&logits);
+
+        let reference = ReferenceLogits::from_safetensors(&file).unwrap();
+        assert_eq!(reference.tokens, vec![1, 2]);
+        assert_eq!(reference.vocab, 2);
+        assert_eq!(reference.logits, vec![1.5, -2., 0.25, 4.]);
+    }
+
+    #[test]
+    fn widens_half_precision() {
+        let values = [1., -2., 65504., 2f32.powi(-24), f32::INFINITY];
+        let halves: Vec<u8> = values
+            .iter()
+            .flat_map(|v| half::f16::from_f32(*v).to_le_bytes())
+            .collect();
+        let reference = ReferenceLogits::from_npy(&npy_file("<f2", "(1, 5)", &halves), vec![0]);
+        assert_eq!(reference.unwrap().logits, values);
+    }
+}
@@ -0,0 +1,278 @@
+//! The checkpoints a process can run: the feature-gated built-ins of
+//! [hf::MODELS], plus any a library user registers at runtime, from code or
+//! from a JSON file.
+//!
+//! A [ModelSpec] is plain `'static` data, so a registered one is leaked: specs
+//! are registered once, at startup, and live as long as the process anyway.
+//!
+//! The file is JSON only, not TOML: its `config` is the checkpoint's
+//! `config.json` pasted in as is, and TOML has no `null` for the fields some
+//! of those leave empty. It is a list of entries; everything but `id`,
+//! `repo_id` and `config` has a default:
+//!
+//! ```json
+//! [{
+//!     "id": "my-mamba2",
+//!     "display_name": "My Mamba2",
+//!     "repo_id": "me/my-mamba2",
+//!     "revision": "main",
+//!     "model_file": "model.safetensors",
+//!     "config_file": "config.json",
+//!     "tokenizer_repo_id": "EleutherAI/gpt-neox-20b",
+//!     "tokenizer_file": "tokenizer.json",
+//!     "config": {"d_model": 768, "n_layer": 24, "vocab_size": 50277,
+//!                "ssm_cfg": {"layer": "Mamba2"}, "pad_vocab_size_multiple": 16}
+//! }]
+//! ```
+
+use crate::config_json::ConfigJson;
+use crate::{ModelSpec, Topology, hf};
+use std::sync::{LazyLock, RwLock, RwLockReadGuard};
+
+/// A priority-ordered set of [ModelSpec]s with unique ids.
+#[derive(Clone, Debug)]
+pub struct Registry {
+    /// Highest priority first.
+    models: Vec<&'static ModelSpec>,
+    /// How many of [Self::models], at the front, were registered rather than
+    /// built in.
+    registered: usize,
+}
+
+impl Default for Registry {
+    /// The built-ins, [hf::MODELS].
+    fn default() -> Self {
+        Self {
+            models: hf::MODELS.to_vec(),
+            registered: 0,
+        }
+    }
+}
+
+impl Registry {
+    /// An empty registry, without even the built-ins.
+    pub fn empty() -> Self {
+        Self {
+            models: Vec::new(),
+            registered: 0,
+        }
+    }
+
+    /// Highest priority first.
+    pub fn models(&self) -> &[&'static ModelSpec] {
+        &self.models
+    }
+
+    pub fn preferred(&self) -> Option<&'static ModelSpec> {
+        self.models.first().copied()
+    }
+
+    pub fn by_id(&self, id: &str) -> Option<&'static ModelSpec> {
+        self.models.iter().copied().find(|model| model.id == id)
+    }
+
+    pub fn ids(&self) -> Vec<&'static str> {
+        self.models.iter().map(|model| model.id).collect()
+    }
+
+    /// Adds `spec` below the specs registered before it but above the
+    /// built-ins: a checkpoint someone went to the trouble of registering is
+    /// the one they mean to run. Fails if its id is taken, or if its topology
+    /// does not build in this binary.
+    pub fn register(&mut self, spec: ModelSpec) -> anyhow::Result<&'static ModelSpec> {
+        let config = match spec.topology {
+            Topology::ConfigJson(json) => Some(json),
+            Topology::Code { .. } => None,
+        };
+        self.check(spec.id, config)?;
+        Ok(self.insert(spec))
+    }
+
+    /// Registers every entry of a JSON list (see the module docs), in order.
+    /// All or nothing: if any entry is refused, none is registered.
+    pub fn register_json(&mut self, json: &str) -> anyhow::Result<Vec<&'static ModelSpec>> {
+        let entries: Vec<SpecJson> = serde_json::from_str(json)
+            .map_err(|e| anyhow::anyhow!("not a list of model specs: {e}"))?;
+        let mut ids = std::collections::HashSet::new();
+        for entry in &entries {
+            anyhow::ensure!(
+                ids.insert(entry.id.as_str()),
+                "the model id {:?} is listed twice",
+                entry.id
+            );
+            self.check(&entry.id, Some(&entry.config))?;
+        }
+        // checked above, so nothing is leaked for a list that is refused
+        Ok(entries
+            .into_iter()
+            .map(|entry| self.insert(entry.into_spec()))
+            .collect())
+    }
+
+    /// Fails if `id` is taken, or if `config` does not build in this binary.
+    fn check(&self, id: &str, config: Option<&ConfigJson>) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.by_id(id).is_none(),
+            "a model with id {id:?} is already registered"
+        );
+        if let Some(config) = config {
+            config
+                .vocab_net_config()
+                .and_then(|_| config.ssd_path())
+                .map_err(|e| anyhow::anyhow!("model {id:?}: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Leaks a checked `spec` into the registry, below the ones registered
+    /// before it.
+    fn insert(&mut self, spec: ModelSpec) -> &'static ModelSpec {
+        let spec: &'static ModelSpec = Box::leak(Box::new(spec));
+        self.models.insert(self.registered, spec);
+        self.registered += 1;
+        spec
+    }
+}
+
+/// One entry of a JSON registry file.
+#[derive(serde::Deserialize)]
+struct SpecJson {
+    id: String,
+    display_name: Option<String>,
+    repo_id: String,
+    #[serde(default = "default_revision")]
+    revision: String,
+    #[serde(default = "default_model_file")]
+    model_file: String,
+    #[serde(default = "default_config_file")]
+    config_file: String,
+    /// Defaults to [Self::repo_id].
+    tokenizer_repo_id: Option<String>,
+    #[serde(default = "default_tokenizer_file")]
+    tokenizer_file: String,
+    config: ConfigJson,
+}
+
+fn default_revision() -> String {
+    "main".into()
+}
+
+fn default_model_file() -> String {
+    "model.safetensors".into()
+}
+
+fn default_config_file() -> String {
+    "config.json".into()
+}
+
+fn default_tokenizer_file() -> String {
+    "tokenizer.json".into()
+}
+
+impl SpecJson {
+    fn into_spec(self) -> ModelSpec {
+        fn leak(s: String) -> &'static str {
+            Box::leak(s.into_boxed_str())
+        }
+        let tokenizer_repo_id = self
+            .tokenizer_repo_id
+            .unwrap_or_else(|| self.repo_id.clone());
+        ModelSpec {
+            display_name: leak(self.display_name.unwrap_or_else(|| self.id.clone())),
+            id: leak(self.id),
+            repo_id: leak(self.repo_id),
+            revision_path: leak(self.revision),
+            file_path_config_json: leak(self.config_file),
+            file_path_model_safetensors: leak(self.model_file),
+            tokenizer_repo_id: leak(tokenizer_repo_id),
+            file_path_tokenizer_json: leak(self.tokenizer_file),
+            topology: Topology::ConfigJson(Box::leak(Box::new(self.config))),
+        }
+    }
+}
+
+static GLOBAL: LazyLock<RwLock<Registry>> = LazyLock::new(Default::default);
+
+/// The process-wide registry [hf::by_id], [hf::preferred] and the entry points
+/// read.
+pub fn global() -> RwLockReadGuard<'static, Registry> {
+    GLOBAL
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// [Registry::register] into [global].
+pub fn register(spec: ModelSpec) -> anyhow::Result<&'static ModelSpec> {
+    GLOBAL
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .register(spec)
+}
+
+/// [Registry::register_json] into [global].
+pub fn register_json(json: &str) -> anyhow::Result<Vec<&'static ModelSpec>> {
+    GLOBAL
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .register_json(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MY_MAMBA: &str = r#"[{
+        "id": "my-mamba",
+        "repo_id": "me/my-mamba",
+        "config": {"d_model": 64, "n_layer": 2, "vocab_size": 1000, "ssm_cfg": {}}
+    }]"#;
+
+    #[cfg(feature = "mamba1")]
+    #[test]
+    fn registered_specs_rank_above_the_builtins() {
+        let mut registry = Registry::default();
+        let builtins = registry.ids();
+
+        let registered = registry.register_json(MY_MAMBA).unwrap();
+        assert_eq!(registered.len(), 1);
+        let spec = registry.by_id("my-mamba").unwrap();
+        assert_eq!((spec.repo_id, spec.revision_path), ("me/my-mamba", "main"));
+        assert_eq!(spec.tokenizer_repo_id, "me/my-mamba");
+        assert_eq!(registry.preferred().map(|spec| spec.id), Some("my-mamba"));
+        assert_eq!(registry.ids()[1..], builtins[..]);
+
+        let again = registry.register_json(MY_MAMBA).unwrap_err();
+        assert!(again.to_string().contains("already registered"), "{again}");
+    }
+
+    /// A list with one bad entry registers none of them, so it can be fixed
+    /// and registered again.
+    #[cfg(feature = "mamba1")]
+    #[test]
+    fn a_refused_list_registers_nothing() {
+        let entry = |id: &str, layer: &str| {
+            serde_json::json!({"id": id, "repo_id": format!("me/{id}"),
+                "config": {"d_model": 64, "n_layer": 2, "vocab_size": 1000,
+                           "ssm_cfg": {"layer": layer}}})
+        };
+        let list = |entries: &[serde_json::Value]| serde_json::Value::from(entries).to_string();
+        let mut registry = Registry::empty();
+
+        let hyena = list(&[entry("my-mamba", "Mamba1"), entry("their-mamba", "Hyena")]);
+        assert!(registry.register_json(&hyena).is_err());
+        let twice = list(&[entry("my-mamba", "Mamba1"), entry("my-mamba", "Mamba1")]);
+        let error = registry.register_json(&twice).unwrap_err();
+        assert!(error.to_string().contains("listed twice"), "{error}");
+        assert!(registry.ids().is_empty());
+
+        let both = list(&[entry("my-mamba", "Mamba1"), entry("their-mamba", "Mamba1")]);
+        assert_eq!(registry.register_json(&both).unwrap().len(), 2);
+        assert_eq!(registry.ids(), ["my-mamba", "their-mamba"]);
+    }
+
+    #[test]
+    fn an_unknown_layer_is_refused_at_registration() {
+        let hyena = MY_MAMBA.replace(r#""ssm_cfg": {}"#, r#""ssm_cfg": {"layer": "Hyena"}"#);
+        assert!(Registry::empty().register_json(&hyena).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Device-side sampling: the [LogitsProcessor](super::LogitsProcessor) pipeline
+//! as Burn ops, so a decode step reads back one token id and its log-probability
+//! instead of the whole `[1, padded_vocab]` logits.
+//!
+//! The host sampler stays the reference. Both draw the same uniform from the same
+//! seeded generator and walk the candidates in the same order — index order for
+//! [Sampling::All], most likely first otherwise — so they pick the same token,
+//! up to float rounding right at a cumulative-probability boundary.
+
+use super::Sampling;
+use burn::prelude::*;
+use burn::tensor::activation::log_softmax;
+use burn::tensor::{FloatDType, IndexingUpdateOp};
+use rand::{Rng, SeedableRng};
+
+/// Turns a `[1, vocab]` logits tensor into a token id, on its device.
+pub struct DeviceSampler {
+    rng: rand::rngs::StdRng,
+    sampling: Sampling,
+    repeat_penalty: f32,
+    repeat_last_n: usize,
+    /// Scratch: the distinct tokens of the penalized window.
+    seen: Vec<u32>,
+}
+
+impl DeviceSampler {
+    /// Without a repeat penalty; see [Self::with_repeat_penalty].
+    pub fn new(seed: u64, sampling: Sampling) -> Self {
+        Self {
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
+            sampling,
+            repeat_penalty: 1.,
+            repeat_last_n: 0,
+            seen: Vec::new(),
+        }
+    }
+
+    /// [super::apply_repeat_penalty] over the last `repeat_last_n` tokens before
+    /// the current one, as [crate::LogitsProcessorWrapper] applies it.
+    pub fn with_repeat_penalty(mut self, repeat_penalty: f32, repeat_last_n: usize) -> Self {
+        self.repeat_penalty = repeat_penalty;
+        self.repeat_last_n = repeat_last_n;
+        self
+    }
+
+    /// Picks the next token from `logits`, `[1, vocab]`, given every token so far.
+    ///
+    /// Returns the token and its log-probability under the temperature-scaled
+    /// distribution it was drawn from (the unscaled one for [Sampling::ArgMax]).
+    /// Each comes back in a one-element read of its own: the id stays an int,
+    /// since the device's default float may be f16 or bf16 (see
+    /// [crate::RuntimePrecision]), which would round most vocabulary ids.
+    pub fn sample(&mut self, logits: Tensor<2>, tokens: &[usize]) -> anyhow::Result<(u32, f32)> {
+        let [batch, vocab] = logits.dims();
+        anyhow::ensure!(
+            batch == 1 && vocab > 0,
+            "expected [1, vocab] logits, got [{batch}, {vocab}]"
+        );
+        let logits = self.apply_repeat_penalty(logits, tokens);
+
+        let (token, log_probs) = match self.sampling {
+            Sampling::ArgMax => (logits.clone().argmax(1), log_softmax(logits, 1)),
+            Sampling::All { temperature } => {
+                let log_probs = log_softmax(logits.div_scalar(temperature), 1);
+                (self.draw(log_probs.clone()), log_probs)
+            }
+            Sampling::TopP { p, temperature } if p <= 0. || p >= 1. => {
+                // simply sample from the predicted probability distribution
+                let log_probs = log_softmax(logits.div_scalar(temperature), 1);
+                (self.draw(log_probs.clone()), log_probs)
+            }
+            Sampling::TopP { p, temperature } => {
+                let log_probs = log_softmax(logits.div_scalar(temperature), 1);
+                let (candidates, ids) = log_probs.clone().sort_descending_with_indices(1);
+                let position = self.draw(nucleus(candidates, p));
+                (ids.gather(1, position), log_probs)
+            }
+            Sampling::TopK { k, temperature } => {
+                let log_probs = log_softmax(logits.div_scalar(temperature), 1);
+                let (candidates, ids) = log_probs.clone().topk_with_indices(k.clamp(1, vocab), 1);
+                let position = self.draw(candidates);
+                (ids.gather(1, position), log_probs)
+            }
+            Sampling::TopKThenTopP { k, p, temperature } => {
+                let log_probs = log_softmax(logits.div_scalar(temperature), 1);
+                let (candidates, ids) = log_probs.clone().topk_with_indices(k.clamp(1, vocab), 1);
+                // `p` is a share of the whole distribution, as if unfiltered
+                let candidates = if p <= 0. {
+                    candidates
+                } else {
+                    nucleus(candidates, p)
+                };
+                let position = self.draw(candidates);
+                (ids.gather(1, position), log_probs)
+            }
+        };
+
+        let log_prob = log_probs
+            .gather(1, token.clone())
+            .cast(FloatDType::F32)
+            .into_data()
+            .into_vec::<f32>()
+            .map_err(|e| anyhow::anyhow!("failed to read the sampled log-prob back: {e:?}"))?[0];
+        let token = token
+            .into_data()
+            .convert::<i64>()
+            .into_vec::<i64>()
+            .map_err(|e| anyhow::anyhow!("failed to read the sampled token back: {e:?}"))?[0];
+        anyhow::ensure!(
+            log_prob.is_finite(),
+            "invalid distribution: the sampled token has log-probability {log_prob}"
+        );
+        Ok((token as u32, log_prob))
+    }
+
+    /// The position, `[1, 1]`, the next uniform draw lands on when walking the
+    /// candidates' `log_weights`, `[1, n]`, in order: [super::LogitsProcessor]'s
+    /// scan, as "how many cumulative probabilities are still under the draw".
+    fn draw(&mut self, log_weights: Tensor<2>) -> Tensor<2, Int> {
+        let [_, n] = log_weights.dims();
+        let weights = log_weights.exp();
+        let total = weights.clone().sum_dim(1);
+        let cdf = weights.cumsum(1).div(total);
+        let uniform = self.rng.random::<f32>();
+        cdf.lower_equal_elem(uniform)
+            .int()
+            .sum_dim(1)
+            .clamp_max((n - 1) as i64)
+    }
+
+    fn apply_repeat_penalty(&mut self, logits: Tensor<2>, tokens: &[usize]) -> Tensor<2> {
+        if self.repeat_penalty == 1. || tokens.is_empty() {
+            return logits;
+        }
+        let [_, vocab] = logits.dims();
+        let start_at = (tokens.len() - 1).saturating_sub(self.repeat_last_n);
+        self.seen.clear();
+        self.seen.extend(
+            tokens[start_at..]
+                .iter()
+                .filter(|token| **token < vocab)
+                .map(|token| *token as u32),
+        );
+        self.seen.sort_unstable();
+        self.seen.dedup();
+        if self.seen.is_empty() {
+            return logits;
+        }
+
+        let seen = id_mask(&self.seen, vocab, &logits.device());
+        let penalized = logits.clone().div_scalar(self.repeat_penalty).mask_where(
+            logits.clone().lower_elem(0.),
+            logits.clone().mul_scalar(self.repeat_penalty),
+        );
+        logits.mask_where(seen, penalized)
+    }
+}
+
+/// Sends the `ids` of a `[1, vocab]` logits tensor to `-inf`, so that no sampler
+/// picks them; see [crate::MambaWrapper::mask_logits].
+pub fn mask_ids(logits: Tensor<2>, ids: &[u32]) -> Tensor<2> {
+    if ids.is_empty() {
+        return logits;
+    }
+    let [_, vocab] = logits.dims();
+    let mask = id_mask(ids, vocab, &logits.device());
+    logits.mask_fill(mask, f32::NEG_INFINITY)
+}
+
+/// `[1, vocab]`, true at each of the distinct `ids`.
+fn id_mask(ids: &[u32], vocab: usize, device: &Device) -> Tensor<2, Bool> {
+    let indices = Tensor::<1, Int>::from_data(ids, device);
+    let ones = Tensor::<1>::ones([ids.len()], device);
+    Tensor::<1>::zeros([vocab], device)
+        .scatter(0, indices, ones, IndexingUpdateOp::Add)
+        .greater_elem(0.)
+        .unsqueeze()
+}
+
+/// Leaves out, with a `-inf` log-weight, every candidate of `log_probs` (sorted
+/// most likely first) past the smallest prefix whose probability exceeds `p`.
+fn nucleus(log_probs: Tensor<2>, p: f64) -> Tensor<2> {
+    let probs = log_probs.clone().exp();
+    let before = probs.clone().cumsum(1).sub(probs);
+    log_probs.mask_fill(before.greater_equal_elem(p), f32::NEG_INFINITY)
+}
+
+/// Device-side sampling against the host reference, on the default device.
+///
+/// Like the other tests that need a device, these only build along with a
+/// model feature, which is what brings a backend in.
+#[cfg(all(test, any(feature = "mamba1", feature = "mamba2", feature = "mamba3")))]
+mod tests {
+    use super::*;
+    use crate::sampling::{LogitsProcessor, apply_repeat_penalty};
+
+    const VOCAB: usize = 64;
+
+    /// Distinct, well-spread logits, so no draw sits on a rounding boundary.
+    fn logits() -> Vec<f32> {
+        (0..VOCAB)
+            .map(|i| ((i * 37) % VOCAB) as f32 * 0.125 - 4.)
+            .collect()
+    }
+
+    fn log_softmax_host(logits: &[f32], temperature: f64) -> Vec<f32> {
+        let scaled: Vec<f32> = logits.iter().map(|l| l / temperature as f32).collect();
+        let max = scaled.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let log_sum = scaled.iter().map(|l| (l - max).exp()).sum::<f32>().ln() + max;
+        scaled.iter().map(|l| l - log_sum).collect()
+    }
+
+    #[test]
+    fn device_sampling_matches_the_host_reference() {
+        let device: Device = Default::default();
+        let tokens: Vec<usize> = vec![3, 9, 27, 17, 51, 9, 0];
+        let strategies = [
+            (Sampling::ArgMax, 1.),
+            (Sampling::All { temperature: 0.7 }, 0.7),
+            (
+                Sampling::TopK {
+                    k: 8,
+                    temperature: 1.3,
+                },
+                1.3,
+            ),
+            (
+                Sampling::TopP {
+                    p: 0.8,
+                    temperature: 1.,
+                },
+                1.,
+            ),
+            (
+                Sampling::TopKThenTopP {
+                    k: 12,
+                    p: 0.5,
+                    temperature: 0.9,
+                },
+                0.9,
+            ),
+        ];
+
+        for (sampling, temperature) in strategies {
+            let mut host = LogitsProcessor::from_sampling(42, sampling.clone());
+            let mut on_device =
+                DeviceSampler::new(42, sampling.clone()).with_repeat_penalty(1.3, 4);
+
+            let mut penalized = logits();
+            let mut seen = Vec::new();
+            let context: Vec<u32> = tokens[tokens.len() - 5..]
+                .iter()
+                .map(|t| *t as u32)
+                .collect();
+            apply_repeat_penalty(&mut penalized, 1.3, &context, &mut seen);
+            let log_probs = log_softmax_host(&penalized, temperature);
+
+            for draw in 0..32 {
+                let want = host.sample(&penalized).unwrap();
+                let input = Tensor::<1>::from_data(logits().as_slice(), &device).unsqueeze();
+                let (got, log_prob) = on_device.sample(input, &tokens).unwrap();
+                assert_eq!(got, want, "{sampling:?}, draw {draw}");
+                let expected = log_probs[got as usize];
+                assert!(
+                    (log_prob - expected).abs() < 1e-4,
+                    "{sampling:?}, draw {draw}: log-prob {log_prob} != {expected}"
+                );
+            }
+        }
+    }
+}
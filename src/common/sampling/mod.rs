@@ -0,0 +1,678 @@
+//! Logits post-processing and token sampling over plain `[f32]` slices.
+//!
+//! Mirrors the behaviour of `candle_transformers::generation::LogitsProcessor` and
+//! `candle_transformers::utils::apply_repeat_penalty`, but without a tensor library:
+//! logits leave the Burn tensor as a `Vec<f32>` and are sampled here directly.
+//!
+//! [device] does the same with Burn ops, reading back only the picked token; this
+//! host implementation is its reference.
+
+use rand::{Rng, SeedableRng};
+
+pub mod device;
+
+/// How the next token is picked from the logits.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Sampling {
+    /// Always take the highest logit.
+    ArgMax,
+    /// Sample from the full (temperature-scaled) distribution.
+    All { temperature: f64 },
+    /// Sample among the `k` most likely tokens.
+    TopK { k: usize, temperature: f64 },
+    /// Nucleus sampling: smallest set of tokens whose probability exceeds `p`.
+    TopP { p: f64, temperature: f64 },
+    /// Top-k, then nucleus sampling within it.
+    TopKThenTopP { k: usize, p: f64, temperature: f64 },
+}
+
+/// Turns a logits vector into a token id.
+///
+/// Sampling a 128k vocabulary every step is hot enough that nothing here
+/// allocates once warmed up: the weights and candidate ids live in scratch
+/// buffers kept across calls, top-k is a partial selection, and top-p only sorts
+/// as many of the most likely tokens as its nucleus turns out to need.
+pub struct LogitsProcessor {
+    rng: rand::rngs::StdRng,
+    sampling: Sampling,
+    /// Scratch: unnormalized probabilities, `[vocab]`.
+    weights: Vec<f32>,
+    /// Scratch: candidate token ids.
+    indices: Vec<u32>,
+}
+
+impl LogitsProcessor {
+    /// From an explicit [Sampling] strategy.
+    pub fn from_sampling(seed: u64, sampling: Sampling) -> Self {
+        let rng = rand::rngs::StdRng::seed_from_u64(seed);
+        Self {
+            rng,
+            sampling,
+            weights: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    /// A missing (or ~zero) `temperature` means [Sampling::ArgMax].
+    pub fn new(seed: u64, temperature: Option<f64>, top_p: Option<f64>) -> Self {
+        let temperature = temperature.and_then(|v| if v < 1e-7 { None } else { Some(v) });
+        let sampling = match temperature {
+            None => Sampling::ArgMax,
+            Some(temperature) => match top_p {
+                None => Sampling::All { temperature },
+                Some(p) => Sampling::TopP { p, temperature },
+            },
+        };
+        Self::from_sampling(seed, sampling)
+    }
+
+    /// Picks the next token id from `logits`.
+    pub fn sample(&mut self, logits: &[f32]) -> anyhow::Result<u32> {
+        match self.sampling {
+            Sampling::ArgMax => sample_argmax(logits),
+            Sampling::All { temperature } => {
+                let total = exp_weights(logits, temperature, &mut self.weights);
+                self.indices.clear();
+                self.indices.extend(0..logits.len() as u32);
+                self.sample_indices(total, self.indices.len())
+            }
+            Sampling::TopP { p, temperature } => {
+                let total = exp_weights(logits, temperature, &mut self.weights);
+                self.indices.clear();
+                self.indices.extend(0..logits.len() as u32);
+                let len = if p <= 0.0 || p >= 1.0 {
+                    // simply sample from the predicted probability distribution
+                    self.indices.len()
+                } else {
+                    // top-p (nucleus) sampling, leaving the least likely tokens out
+                    self.nucleus(self.indices.len(), p as f32 * total)
+                };
+                self.sample_indices(total, len)
+            }
+            Sampling::TopK { k, temperature } => {
+                let k = self.select_top_k(logits, k);
+                let total = self.exp_candidate_weights(logits, k, temperature);
+                self.sample_indices(total, k)
+            }
+            Sampling::TopKThenTopP { k, p, temperature } => {
+                // `p` is a share of the whole distribution, as if unfiltered
+                let full_total = exp_weights(logits, temperature, &mut self.weights);
+                let k = self.select_top_k(logits, k);
+                let total = self.indices[..k]
+                    .iter()
+                    .map(|&i| self.weights[i as usize])
+                    .sum::<f32>();
+                let top_p = p as f32 * full_total;
+                let len = if p <= 0.0 || top_p >= total {
+                    k
+                } else {
+                    self.nucleus(k, top_p)
+                };
+                let total = self.indices[..len]
+                    .iter()
+                    .map(|&i| self.weights[i as usize])
+                    .sum::<f32>();
+                self.sample_indices(total, len)
+            }
+        }
+    }
+
+    /// Moves the `k` highest of `logits` to the front of the candidate ids, most
+    /// likely first, and returns how many there are.
+    ///
+    /// The order is what [Self::sample_indices] scans, so it is also what the
+    /// [device] sampler's `topk` reproduces.
+    fn select_top_k(&mut self, logits: &[f32], k: usize) -> usize {
+        self.indices.clear();
+        self.indices.extend(0..logits.len() as u32);
+        let k = k.clamp(1, logits.len().max(1));
+        if k < self.indices.len() {
+            self.indices.select_nth_unstable_by(k - 1, |&i, &j| {
+                logits[j as usize].total_cmp(&logits[i as usize])
+            });
+        }
+        self.indices[..k]
+            .sort_unstable_by(|&i, &j| logits[j as usize].total_cmp(&logits[i as usize]));
+        k
+    }
+
+    /// [exp_weights], for the first `k` candidates only.
+    fn exp_candidate_weights(&mut self, logits: &[f32], k: usize, temperature: f64) -> f32 {
+        let temperature = temperature as f32;
+        self.weights.resize(logits.len(), 0.);
+        let max = self.indices[..k]
+            .iter()
+            .map(|&i| logits[i as usize])
+            .fold(f32::NEG_INFINITY, f32::max);
+        let mut total = 0.;
+        for &i in &self.indices[..k] {
+            let weight = ((logits[i as usize] - max) / temperature).exp();
+            self.weights[i as usize] = weight;
+            total += weight;
+        }
+        total
+    }
+
+    /// Sorts, most likely first, the smallest prefix of the first `len`
+    /// candidates whose weight exceeds `top_p`, and returns its length.
+    ///
+    /// Selects `64, 256, …` of the heaviest candidates until they cover `top_p`,
+    /// so a peaked distribution never pays for a sort of the whole vocabulary.
+    fn nucleus(&mut self, len: usize, top_p: f32) -> usize {
+        let weights = &self.weights;
+        let by_weight = |i: &u32, j: &u32| weights[*j as usize].total_cmp(&weights[*i as usize]);
+        let mut selected = 64.min(len);
+        loop {
+            let candidates = &mut self.indices[..len];
+            if selected < len {
+                candidates.select_nth_unstable_by(selected - 1, by_weight);
+            }
+            let head = &mut candidates[..selected];
+            head.sort_unstable_by(by_weight);
+            let mut cumsum = 0.;
+            for (n, &i) in head.iter().enumerate() {
+                if cumsum >= top_p {
+                    return n;
+                }
+                cumsum += weights[i as usize];
+            }
+            if selected == len {
+                return len;
+            }
+            selected = (selected * 4).min(len);
+        }
+    }
+
+    /// Samples among the first `len` candidates, by weight; `total` is their
+    /// summed weight.
+    fn sample_indices(&mut self, total: f32, len: usize) -> anyhow::Result<u32> {
+        if !(total.is_finite() && total > 0.) {
+            anyhow::bail!("invalid distribution: the weights sum to {total}");
+        }
+        let mut threshold = self.rng.random::<f32>() * total;
+        let candidates = &self.indices[..len];
+        for &i in candidates {
+            let weight = self.weights[i as usize];
+            if threshold < weight {
+                return Ok(i);
+            }
+            threshold -= weight;
+        }
+        // rounding left the threshold past the end: take the last possible token
+        candidates
+            .iter()
+            .rev()
+            .copied()
+            .find(|&i| self.weights[i as usize] > 0.)
+            .ok_or_else(|| anyhow::anyhow!("empty logits"))
+    }
+}
+
+/// Samples one token per row of a row-major `[batch, vocab]` logits buffer, each
+/// row with its own [Sampling], seed and repeat penalty — the batched
+/// counterpart of [LogitsProcessor], which runs each row.
+///
+/// The rows are sampled in place, so batched decode hands over the buffer it read
+/// back instead of splitting it into one `Vec` per row.
+#[derive(Default)]
+pub struct BatchLogitsProcessor {
+    rows: Vec<(LogitsProcessor, f32)>,
+    /// Scratch: see [apply_repeat_penalty].
+    seen: Vec<u32>,
+}
+
+impl BatchLogitsProcessor {
+    /// Appends a row, sampled by `sampling` from `seed`, with a `repeat_penalty`
+    /// over its context (`1` is off).
+    pub fn with_row(mut self, seed: u64, sampling: Sampling, repeat_penalty: f32) -> Self {
+        self.rows.push((
+            LogitsProcessor::from_sampling(seed, sampling),
+            repeat_penalty,
+        ));
+        self
+    }
+
+    /// How many rows each [Self::sample] takes.
+    pub fn batch(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Picks one token per row of `logits`, `[batch, vocab]`, penalizing each row
+    /// by its own `contexts[row]`. The logits are overwritten by the penalties.
+    pub fn sample(&mut self, logits: &mut [f32], contexts: &[&[u32]]) -> anyhow::Result<Vec<u32>> {
+        let batch = self.rows.len();
+        anyhow::ensure!(
+            batch > 0 && logits.len().is_multiple_of(batch),
+            "{} logits do not split into {batch} rows",
+            logits.len()
+        );
+        anyhow::ensure!(
+            contexts.len() == batch,
+            "{} penalty contexts for {batch} rows",
+            contexts.len()
+        );
+        let vocab = logits.len() / batch;
+        let rows = logits.chunks_exact_mut(vocab).zip(contexts);
+        let rows = rows.zip(self.rows.iter_mut());
+        let mut tokens = Vec::with_capacity(batch);
+        for ((logits, context), (processor, repeat_penalty)) in rows {
+            if *repeat_penalty != 1. {
+                apply_repeat_penalty(logits, *repeat_penalty, context, &mut self.seen);
+            }
+            tokens.push(processor.sample(logits)?);
+        }
+        Ok(tokens)
+    }
+}
+
+fn sample_argmax(logits: &[f32]) -> anyhow::Result<u32> {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|(_, u), (_, v)| u.total_cmp(v))
+        .map(|(i, _)| i as u32)
+        .ok_or_else(|| anyhow::anyhow!("empty logits"))
+}
+
+/// Writes the unnormalized, numerically-stable softmax of `logits / temperature`
+/// into `weights`, returning their sum.
+fn exp_weights(logits: &[f32], temperature: f64, weights: &mut Vec<f32>) -> f32 {
+    let temperature = temperature as f32;
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    weights.clear();
+    weights.extend(logits.iter().map(|&v| ((v - max) / temperature).exp()));
+    weights.iter().sum()
+}
+
+/// Numerically-stable softmax of `logits / temperature`.
+fn softmax(logits: &[f32], temperature: f64) -> Vec<f32> {
+    let mut prs = Vec::with_capacity(logits.len());
+    let sum = exp_weights(logits, temperature, &mut prs);
+    if sum > 0. {
+        for p in prs.iter_mut() {
+            *p /= sum;
+        }
+    }
+    prs
+}
+
+/// Classifier-free guidance: moves `logits` (the prompt-conditioned branch) away
+/// from `unconditioned` by `scale`, as `u + scale · (c - u)`.
+///
+/// `scale = 1` leaves `logits` unchanged and `scale = 0` samples the unconditioned
+/// branch alone; values above one steer towards the prompt. Mixing raw logits is
+/// the same as mixing log-probabilities, since each branch's log-sum-exp only adds
+/// a constant that the softmax discards.
+///
+/// A token masked to `-inf` on either side stays masked: the mix would
+/// otherwise take `-inf - -inf` and turn it into a NaN.
+pub fn apply_guidance(logits: &mut [f32], unconditioned: &[f32], scale: f32) {
+    for (logit, unconditioned) in logits.iter_mut().zip(unconditioned) {
+        *logit = if *logit == f32::NEG_INFINITY || *unconditioned == f32::NEG_INFINITY {
+            f32::NEG_INFINITY
+        } else {
+            unconditioned + scale * (*logit - unconditioned)
+        };
+    }
+}
+
+/// Bans every token that would complete an `n`-gram already present in
+/// `context`: the last `n - 1` tokens are looked up earlier in `context`, and
+/// whatever followed each occurrence gets a `-inf` logit.
+///
+/// `n = 0` is off; `n = 1` bans every token of `context`.
+pub fn apply_no_repeat_ngram(logits: &mut [f32], n: usize, context: &[u32]) {
+    if n == 0 || context.len() < n {
+        return;
+    }
+    let prefix = &context[context.len() + 1 - n..];
+    for ngram in context.windows(n) {
+        if ngram[..n - 1] == *prefix
+            && let Some(logit) = logits.get_mut(ngram[n - 1] as usize)
+        {
+            *logit = f32::NEG_INFINITY;
+        }
+    }
+}
+
+/// The "don't repeat yourself" penalty: discourages a token in proportion to how
+/// long a sequence it would extend that already occurred in the context.
+///
+/// Unlike [apply_repeat_penalty], which taxes every seen token alike, this only
+/// reaches a token once the text leading up to it is itself a repetition — which
+/// is what a verbatim loop is — and grows exponentially with the repeated length.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Dry {
+    /// Scale of the penalty; `0` is off.
+    pub multiplier: f32,
+    /// Growth per repeated token past [Self::allowed_length].
+    pub base: f32,
+    /// How long a repeated sequence may get before it is penalized at all.
+    pub allowed_length: usize,
+    /// Tokens no repetition may span (newlines, quotes…), so that a match never
+    /// continues across them.
+    pub sequence_breakers: Vec<u32>,
+}
+
+impl Dry {
+    /// The usual defaults, at `multiplier`: base 1.75, two-token allowance, no
+    /// sequence breakers.
+    pub fn new(multiplier: f32) -> Self {
+        Self {
+            multiplier,
+            base: 1.75,
+            allowed_length: 2,
+            sequence_breakers: Vec::new(),
+        }
+    }
+
+    pub fn with_sequence_breakers(mut self, sequence_breakers: Vec<u32>) -> Self {
+        self.sequence_breakers = sequence_breakers;
+        self
+    }
+}
+
+/// Subtracts `multiplier · base^(len - allowed_length)` from the logit of every
+/// token that would extend a repeat of length `len ≥ allowed_length`, where `len`
+/// is the longest suffix of `context` that also ends right before an earlier
+/// occurrence of that token.
+pub fn apply_dry_penalty(logits: &mut [f32], dry: &Dry, context: &[u32]) {
+    if dry.multiplier == 0. || context.len() < 2 {
+        return;
+    }
+    let is_breaker = |token: u32| dry.sequence_breakers.contains(&token);
+    let last = context.len() - 1;
+
+    // `end` is where an earlier occurrence of the context's tail ends; the token
+    // after it is the one the repetition would continue with.
+    let mut longest: Vec<(u32, usize)> = Vec::new();
+    for end in 0..last {
+        let next = context[end + 1];
+        if is_breaker(next) {
+            continue;
+        }
+        let mut len = 0;
+        while len <= end
+            && context[end - len] == context[last - len]
+            && !is_breaker(context[last - len])
+        {
+            len += 1;
+        }
+        if len < dry.allowed_length.max(1) {
+            continue;
+        }
+        match longest.iter_mut().find(|(token, _)| *token == next) {
+            Some((_, longest)) => *longest = (*longest).max(len),
+            None => longest.push((next, len)),
+        }
+    }
+
+    for (token, len) in longest {
+        if let Some(logit) = logits.get_mut(token as usize) {
+            let exponent = (len - dry.allowed_length) as i32;
+            *logit -= dry.multiplier * dry.base.powi(exponent);
+        }
+    }
+}
+
+/// The shortest period `k ≤ max_period` such that the last `k · min_repeats`
+/// tokens are one `k`-token block repeated `min_repeats` times.
+pub fn detect_loop(tokens: &[usize], max_period: usize, min_repeats: usize) -> Option<usize> {
+    let min_repeats = min_repeats.max(2);
+    (1..=max_period).find(|&period| {
+        let span = period * min_repeats;
+        if tokens.len() < span {
+            return false;
+        }
+        let tail = &tokens[tokens.len() - span..];
+        let block = &tail[span - period..];
+        tail.chunks_exact(period).all(|chunk| chunk == block)
+    })
+}
+
+/// The `top_k` most likely tokens of `logits`, most likely first, with their
+/// probabilities: the candidates of a contrastive search step.
+pub fn top_k_candidates(logits: &[f32], top_k: usize) -> Vec<(u32, f32)> {
+    let prs = softmax(logits, 1.);
+    let mut indices = (0..prs.len()).collect::<Vec<_>>();
+    let top_k = top_k.clamp(1, prs.len().max(1));
+    if top_k < prs.len() {
+        indices.select_nth_unstable_by(top_k, |&i, &j| prs[j].total_cmp(&prs[i]));
+        indices.truncate(top_k);
+    }
+    indices.sort_unstable_by(|&i, &j| prs[j].total_cmp(&prs[i]));
+    indices.into_iter().map(|i| (i as u32, prs[i])).collect()
+}
+
+/// Contrastive search: the index of the candidate maximising
+/// `(1 - alpha) · p(v) - alpha · max_j cos(h_v, h_j)`, where `h_v` is the hidden
+/// state after the candidate and `h_j` those of every previous token.
+///
+/// `alpha = 0` is greedy decoding; the degeneration penalty steers away from
+/// continuations whose representation the text already went through.
+pub fn contrastive_pick(
+    candidates: &[(u32, f32)],
+    candidate_hidden: &[Vec<f32>],
+    previous_hidden: &[Vec<f32>],
+    alpha: f32,
+) -> usize {
+    let score = |(index, (_token, pr)): (usize, &(u32, f32))| {
+        let degeneration = previous_hidden
+            .iter()
+            .map(|previous| cosine_similarity(&candidate_hidden[index], previous))
+            .fold(f32::NEG_INFINITY, f32::max);
+        // nothing to compare against yet
+        let degeneration = if degeneration.is_finite() {
+            degeneration
+        } else {
+            0.
+        };
+        (1. - alpha) * pr - alpha * degeneration
+    };
+    candidates
+        .iter()
+        .enumerate()
+        .map(|candidate| (candidate.0, score(candidate)))
+        .max_by(|(_, u), (_, v)| u.total_cmp(v))
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+fn cosine_similarity(u: &[f32], v: &[f32]) -> f32 {
+    let (mut dot, mut uu, mut vv) = (0., 0., 0.);
+    for (u, v) in u.iter().zip(v) {
+        dot += u * v;
+        uu += u * u;
+        vv += v * v;
+    }
+    let norm = (uu * vv).sqrt();
+    if norm > 0. { dot / norm } else { 0. }
+}
+
+/// Divides (or multiplies, for negative logits) the logits of every token already
+/// present in `context` by `penalty`, discouraging repetitions.
+///
+/// `seen` is scratch space, kept by the caller so that no call allocates.
+pub fn apply_repeat_penalty(
+    logits: &mut [f32],
+    penalty: f32,
+    context: &[u32],
+    seen: &mut Vec<u32>,
+) {
+    seen.clear();
+    seen.extend_from_slice(context);
+    seen.sort_unstable();
+    seen.dedup();
+    for token_id in seen.iter() {
+        if let Some(logit) = logits.get_mut(*token_id as usize) {
+            if *logit >= 0. {
+                *logit /= penalty
+            } else {
+                *logit *= penalty
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guidance_keeps_masked_ids_masked() {
+        // the last two ids are padding, masked in both branches; id 1 only in one
+        let mut logits = vec![
+            1.,
+            f32::NEG_INFINITY,
+            3.,
+            f32::NEG_INFINITY,
+            f32::NEG_INFINITY,
+        ];
+        let unconditioned = [2., 0., 1., f32::NEG_INFINITY, f32::NEG_INFINITY];
+        apply_guidance(&mut logits, &unconditioned, 1.5);
+        assert_eq!(logits[0], 0.5);
+        assert_eq!(logits[2], 4.);
+        assert_eq!(logits[1], f32::NEG_INFINITY);
+        assert!(logits[3..].iter().all(|l| *l == f32::NEG_INFINITY));
+
+        let mut processor = LogitsProcessor::from_sampling(0, Sampling::All { temperature: 1. });
+        let token = processor.sample(&logits).unwrap();
+        assert!(token == 0 || token == 2, "{token}");
+    }
+
+    #[test]
+    fn no_repeat_ngram_bans_the_completions_of_the_current_prefix() {
+        // "1 2 3 … 1 2": a trigram ban must forbid 3 (after "1 2") and nothing else.
+        let context = [1, 2, 3, 4, 1, 2];
+        let mut logits = vec![0.; 6];
+        apply_no_repeat_ngram(&mut logits, 3, &context);
+        let banned: Vec<usize> = (0..6).filter(|&i| logits[i].is_infinite()).collect();
+        assert_eq!(banned, vec![3]);
+
+        let mut logits = vec![0.; 6];
+        apply_no_repeat_ngram(&mut logits, 0, &context);
+        assert!(logits.iter().all(|l| *l == 0.));
+    }
+
+    #[test]
+    fn dry_grows_with_the_repeated_length() {
+        let dry = Dry::new(1.);
+        // The tail "5 6 7" already occurred followed by 8; "6 7" followed by 9.
+        let context = [5, 6, 7, 8, 0, 6, 7, 9, 0, 5, 6, 7];
+        let mut logits = vec![0.; 10];
+        apply_dry_penalty(&mut logits, &dry, &context);
+        // len 3 for 8: 1.75^(3-2); len 2 for 9: 1.75^0.
+        assert_eq!(logits[8], -1.75);
+        assert_eq!(logits[9], -1.);
+        // "7" then "0": only a one-token repeat, within the allowance.
+        assert_eq!(logits[0], 0.);
+
+        let mut logits = vec![0.; 10];
+        apply_dry_penalty(&mut logits, &dry.with_sequence_breakers(vec![7]), &context);
+        assert!(logits.iter().all(|l| *l == 0.));
+    }
+
+    #[test]
+    fn loops_are_detected_by_their_shortest_period() {
+        assert_eq!(detect_loop(&[9, 1, 2, 1, 2, 1, 2], 4, 3), Some(2));
+        assert_eq!(detect_loop(&[9, 1, 2, 1, 2], 4, 3), None);
+        assert_eq!(detect_loop(&[3, 3, 3], 4, 3), Some(1));
+        assert_eq!(detect_loop(&[], 4, 3), None);
+    }
+
+    #[test]
+    fn contrastive_search_trades_confidence_for_novelty() {
+        let logits = [0., 3., 2., 1.];
+        let candidates = top_k_candidates(&logits, 2);
+        assert_eq!(
+            candidates
+                .iter()
+                .map(|(token, _)| *token)
+                .collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        // the most likely candidate points back where the text has been
+        let previous = vec![vec![1., 0.]];
+        let hidden = vec![vec![1., 0.], vec![0., 1.]];
+        assert_eq!(contrastive_pick(&candidates, &hidden, &previous, 0.), 0);
+        assert_eq!(contrastive_pick(&candidates, &hidden, &previous, 0.6), 1);
+    }
+
+    #[test]
+    fn top_k_and_top_p_only_sample_their_candidates() {
+        // flat enough that the nucleus outgrows the first partial selection
+        let logits: Vec<f32> = (0..1000).map(|i| -(i as f32) * 0.01).collect();
+        let prs = softmax(&logits, 1.);
+        let mut cumsum = 0.;
+        let nucleus = prs.iter().take_while(|&&p| {
+            let inside = cumsum < 0.9;
+            cumsum += p;
+            inside
+        });
+        let nucleus = nucleus.count() as u32;
+        assert!(nucleus > 64);
+
+        let mut top_p = LogitsProcessor::from_sampling(
+            0,
+            Sampling::TopP {
+                p: 0.9,
+                temperature: 1.,
+            },
+        );
+        let mut top_k = LogitsProcessor::from_sampling(
+            0,
+            Sampling::TopK {
+                k: 5,
+                temperature: 1.,
+            },
+        );
+        for _ in 0..1000 {
+            assert!(top_p.sample(&logits).unwrap() < nucleus);
+            assert!(top_k.sample(&logits).unwrap() < 5);
+        }
+    }
+
+    #[test]
+    fn batched_rows_sample_like_their_own_processors() {
+        let row = |shift: usize| -> Vec<f32> {
+            (0..50)
+                .map(|i| ((i * 7 + shift) % 50) as f32 * 0.1)
+                .collect()
+        };
+        let settings = [
+            (1, Sampling::All { temperature: 1. }, 1.),
+            (
+                2,
+                Sampling::TopK {
+                    k: 5,
+                    temperature: 0.5,
+                },
+                1.2,
+            ),
+            (3, Sampling::ArgMax, 2.),
+        ];
+        let contexts: [&[u32]; 3] = [&[1, 2], &[], &[6, 6, 7]];
+
+        let mut batched = settings.iter().fold(
+            BatchLogitsProcessor::default(),
+            |batched, (seed, sampling, penalty)| {
+                batched.with_row(*seed, sampling.clone(), *penalty)
+            },
+        );
+        let mut singles: Vec<_> = settings
+            .iter()
+            .map(|(seed, sampling, _)| LogitsProcessor::from_sampling(*seed, sampling.clone()))
+            .collect();
+
+        for _ in 0..20 {
+            let mut logits: Vec<f32> = (0..3).flat_map(row).collect();
+            let got = batched.sample(&mut logits, &contexts).unwrap();
+            for (b, single) in singles.iter_mut().enumerate() {
+                let mut logits = row(b);
+                let mut seen = Vec::new();
+                apply_repeat_penalty(&mut logits, settings[b].2, contexts[b], &mut seen);
+                assert_eq!(got[b], single.sample(&logits).unwrap(), "row {b}");
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+//! What a generation run cost: [GenerationStats], timed by a [GenerationTimer]
+//! the run loops tick as they go.
+//!
+//! Every frontend reports the same numbers, and they serialize to JSON as they
+//! are, durations in milliseconds.
+
+use std::time::Duration;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// Why a generation ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// It reached the requested length.
+    Length,
+    /// The model sampled the end-of-sequence token.
+    Eos,
+    /// The loop stop detected a verbatim loop; see
+    /// [crate::LogitsProcessorWrapper::with_loop_stop].
+    Loop,
+}
+
+/// Nearest-rank percentiles of the time between consecutive generated tokens,
+/// in milliseconds; all zero with fewer than two tokens.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Serialize)]
+pub struct LatencyPercentiles {
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl LatencyPercentiles {
+    fn of(latencies: &[Duration]) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        let mut ms: Vec<f64> = latencies.iter().map(|l| millis(*l)).collect();
+        ms.sort_unstable_by(f64::total_cmp);
+        let rank = |p: f64| ms[((p * ms.len() as f64).ceil() as usize).clamp(1, ms.len()) - 1];
+        Self {
+            p50: rank(0.5),
+            p90: rank(0.9),
+            p99: rank(0.99),
+            max: ms[ms.len() - 1],
+            mean: ms.iter().sum::<f64>() / ms.len() as f64,
+        }
+    }
+}
+
+/// One generation run, as a [GenerationTimer] saw it.
+#[derive(Clone, Debug, PartialEq, serde::Serialize)]
+pub struct GenerationStats {
+    pub prompt_tokens: usize,
+    /// The sampled tokens kept in the output; a final eos is not one of them.
+    pub generated_tokens: usize,
+    /// From the start of the run until the prompt has gone through the model.
+    pub prefill_ms: f64,
+    /// From the start of the run until the first generated token, so the
+    /// prefill plus one sampling.
+    pub time_to_first_token_ms: f64,
+    /// Between each generated token and the next.
+    pub token_latency_ms: LatencyPercentiles,
+    pub total_ms: f64,
+    pub finish_reason: FinishReason,
+}
+
+impl GenerationStats {
+    /// Decoding throughput: one token per mean latency between tokens. The
+    /// prefill is left out; see [Self::time_to_first_token_ms] for it.
+    pub fn tokens_per_second(&self) -> f64 {
+        match self.token_latency_ms.mean {
+            mean if mean > 0. => 1000. / mean,
+            _ => 0.,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("the stats are plain numbers")
+    }
+}
+
+impl std::fmt::Display for GenerationStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} prompt + {} generated tokens ({:?}): prefill {:.1}ms, first token {:.1}ms, \
+             {:.2} token/s, latency p50 {:.1}ms p90 {:.1}ms p99 {:.1}ms",
+            self.prompt_tokens,
+            self.generated_tokens,
+            self.finish_reason,
+            self.prefill_ms,
+            self.time_to_first_token_ms,
+            self.tokens_per_second(),
+            self.token_latency_ms.p50,
+            self.token_latency_ms.p90,
+            self.token_latency_ms.p99,
+        )
+    }
+}
+
+/// Times a run into [GenerationStats]: started with the run, told when the
+/// prompt is through and each time a token is generated.
+#[derive(Clone, Debug)]
+pub struct GenerationTimer {
+    start: Instant,
+    prompt_tokens: usize,
+    prefill: Option<Duration>,
+    first_token: Option<Duration>,
+    last_token: Option<Instant>,
+    latencies: Vec<Duration>,
+}
+
+impl GenerationTimer {
+    pub fn start(prompt_tokens: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            prompt_tokens,
+            prefill: None,
+            first_token: None,
+            last_token: None,
+            latencies: Vec::new(),
+        }
+    }
+
+    pub fn prompt_tokens(&self) -> usize {
+        self.prompt_tokens
+    }
+
+    /// The prompt has gone through the model; only the first call counts.
+    pub fn prefilled(&mut self) {
+        self.prefill.get_or_insert_with(|| self.start.elapsed());
+    }
+
+    /// A token was generated. Also marks the prefill done, if nothing did.
+    pub fn token(&mut self) {
+        let now = Instant::now();
+        self.prefilled();
+        match self.last_token {
+            None => self.first_token = Some(now - self.start),
+            Some(last) => self.latencies.push(now - last),
+        }
+        self.last_token = Some(now);
+    }
+
+    pub fn finish(&self, finish_reason: FinishReason) -> GenerationStats {
+        let total = self.start.elapsed();
+        let prefill = self.prefill.unwrap_or(total);
+        GenerationStats {
+            prompt_tokens: self.prompt_tokens,
+            generated_tokens: self.first_token.map_or(0, |_| self.latencies.len() + 1),
+            prefill_ms: millis(prefill),
+            time_to_first_token_ms: millis(self.first_token.unwrap_or(total)),
+            token_latency_ms: LatencyPercentiles::of(&self.latencies),
+            total_ms: millis(total),
+            finish_reason,
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_percentiles_are_nearest_rank() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        let percentiles = LatencyPercentiles::of(&latencies);
+        assert_eq!(percentiles.p50, 50.);
+        assert_eq!(percentiles.p90, 90.);
+        assert_eq!(percentiles.p99, 99.);
+        assert_eq!(percentiles.max, 100.);
+        assert_eq!(percentiles.mean, 50.5);
+        assert_eq!(LatencyPercentiles::of(&[]), LatencyPercentiles::default());
+    }
+
+    #[test]
+    fn stats_count_tokens_and_serialize() {
+        let mut timer = GenerationTimer::start(3);
+        timer.prefilled();
+        for _ in 0..4 {
+            timer.token();
+        }
+        let stats = timer.finish(FinishReason::Eos);
+        assert_eq!((stats.prompt_tokens, stats.generated_tokens), (3, 4));
+        assert!(stats.prefill_ms <= stats.time_to_first_token_ms);
+        assert!(stats.time_to_first_token_ms <= stats.total_ms);
+
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(json["finish_reason"], "eos");
+        assert_eq!(json["generated_tokens"], 4);
+        assert!(json["token_latency_ms"]["p99"].is_number());
+
+        let empty = GenerationTimer::start(1).finish(FinishReason::Length);
+        assert_eq!(empty.generated_tokens, 0);
+        assert_eq!(empty.tokens_per_second(), 0.);
+    }
+}
@@ -3,82 +3,315 @@
 //! The whole import is declarative: a [SafetensorsStore] gets a key remapping
 //! (`backbone.…` → the Burn module paths) plus an adapter chain that transposes
 //! `Linear` weights (PyTorch `[out, in]` → Burn `[in, out]`) and casts every float
-//! tensor to [crate::Precision]. That last cast is why both checkpoints share one
-//! code path even though mamba-130m stores f32 and mamba2-130m stores f16.
+//! tensor to the [RuntimePrecision]. That last cast is why both checkpoints share
+//! one code path even though mamba-130m stores f32 and mamba2-130m stores f16.
+//! A GGUF checkpoint joins the same path as dequantized safetensors bytes; see
+//! [crate::gguf].
 
+use crate::RuntimePrecision;
+use crate::precision::keeps_f32;
 use burn::module::Param;
 use burn::prelude::*;
 use burn::store::{
-    FloatCastAdapter, ModuleAdapter, ModuleSnapshot, PyTorchToBurnAdapter, SafetensorsStore,
+    FloatCastAdapter, ModuleAdapter, ModuleSnapshot, PathFilter, PyTorchToBurnAdapter,
+    SafetensorsStore,
 };
+use burn::tensor::FloatDType;
 use burn_mamba::prelude::*;
 
 /// Where the checkpoint bytes come from.
+#[derive(Clone)]
 pub enum Checkpoint {
     /// A `model.safetensors` on disk; memory-mapped by the store.
     #[cfg(not(target_arch = "wasm32"))]
     File(std::path::PathBuf),
     /// Already-downloaded bytes (the browser has no filesystem).
     Bytes(Vec<u8>),
+    /// The shards of a checkpoint split across several files, as listed by its
+    /// `model.safetensors.index.json` (see [shard_files]); together they must
+    /// fill every parameter.
+    Sharded(Vec<Checkpoint>),
+    /// A llama.cpp checkpoint, dequantized to safetensors bytes once at the
+    /// start of the load.
+    Gguf(crate::gguf::Gguf),
 }
 
 impl Checkpoint {
-    fn store(self) -> SafetensorsStore {
+    /// The `model-0000x-of-0000N.safetensors` files of a sharded checkpoint
+    /// on disk, given its index.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_index(index: &std::path::Path) -> anyhow::Result<Self> {
+        let dir = index.parent().unwrap_or(std::path::Path::new(""));
+        let files = shard_files(&std::fs::read(index)?)?;
+        Ok(Checkpoint::Sharded(
+            files
+                .into_iter()
+                .map(|file| Checkpoint::File(dir.join(file)))
+                .collect(),
+        ))
+    }
+
+    /// The name of every tensor, read from the safetensors header(s) alone by
+    /// burn-store's own (bounded) reader. A file is memory-mapped; bytes are
+    /// copied into the store, which owns what it reads.
+    pub fn tensor_names(&self) -> anyhow::Result<Vec<String>> {
         match self {
+            Checkpoint::Sharded(shards) => Ok(shards
+                .iter()
+                .map(Checkpoint::tensor_names)
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .concat()),
+            Checkpoint::Gguf(gguf) => gguf.tensor_names(),
+            single => {
+                use burn::store::ModuleStore;
+                single
+                    .clone()
+                    .store()?
+                    .keys()
+                    .map_err(|e| anyhow::anyhow!("not a safetensors file: {e}"))
+            }
+        }
+    }
+
+    /// Every single-file part, in order; a GGUF dequantized to `dtype`.
+    fn into_shards(self, dtype: FloatDType) -> anyhow::Result<Vec<Checkpoint>> {
+        Ok(match self {
+            Checkpoint::Sharded(shards) => shards
+                .into_iter()
+                .map(|shard| shard.into_shards(dtype))
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .concat(),
+            Checkpoint::Gguf(gguf) => vec![Checkpoint::Bytes(gguf.to_safetensors(dtype)?)],
+            single => vec![single],
+        })
+    }
+
+    fn store(self) -> anyhow::Result<SafetensorsStore> {
+        Ok(match self {
             #[cfg(not(target_arch = "wasm32"))]
             Checkpoint::File(path) => SafetensorsStore::from_file(path),
             Checkpoint::Bytes(bytes) => SafetensorsStore::from_bytes(Some(bytes)),
+            Checkpoint::Sharded(_) | Checkpoint::Gguf(_) => {
+                unreachable!("split and dequantized by into_shards")
+            }
+        })
+    }
+}
+
+/// How a checkpoint names its tensors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// `state-spaces`' own `mamba_ssm` checkpoints: `backbone.embedding`, and
+    /// the LM head tied to it.
+    StateSpaces,
+    /// `transformers`' `MambaForCausalLM` / `Mamba2ForCausalLM`, as in the
+    /// `state-spaces/mamba-*-hf` repos and the fine-tunes made with it:
+    /// `backbone.embeddings`, and an `lm_head.weight` when the head is untied.
+    Transformers,
+}
+
+impl Layout {
+    /// Tells the two apart by the embedding's name.
+    pub fn detect<'a>(names: impl IntoIterator<Item = &'a str>) -> Self {
+        match names
+            .into_iter()
+            .any(|name| name == "backbone.embeddings.weight")
+        {
+            true => Layout::Transformers,
+            false => Layout::StateSpaces,
         }
     }
 }
 
+/// The file name a sharded checkpoint's index goes by.
+pub const SHARD_INDEX: &str = "model.safetensors.index.json";
+
+/// The shard files a `model.safetensors.index.json` points at, each once,
+/// sorted by name, which is their `-0000x-of-` numbering.
+pub fn shard_files(index: &[u8]) -> anyhow::Result<Vec<String>> {
+    #[derive(serde::Deserialize)]
+    struct Index {
+        weight_map: std::collections::BTreeMap<String, String>,
+    }
+    let index: Index = serde_json::from_slice(index)
+        .map_err(|e| anyhow::anyhow!("not a safetensors index: {e}"))?;
+    let files: std::collections::BTreeSet<String> = index.weight_map.into_values().collect();
+    anyhow::ensure!(!files.is_empty(), "the safetensors index lists no shard");
+    Ok(files.into_iter().collect())
+}
+
 /// Builds the model from `mamba_config` and overwrites every parameter from the
 /// checkpoint, then ties the LM head to the (transposed) embedding.
+///
+/// Either [Layout] loads; which one is read off the tensor names. A checkpoint
+/// that stores its own `lm_head.weight` gets an untied head built for it,
+/// whatever `missing_lm_head` says, and keeps it.
+///
+/// A [Checkpoint::Sharded] one is applied shard by shard, each through the same
+/// remapping and adapters; unused and missing tensors are reported for the set
+/// as a whole, since any one shard leaves most parameters to the others.
+///
+/// Every float is cast to `precision`. A mixed one applies each shard twice,
+/// each time through a store of its own, so that neither pass inherits the
+/// other's filter or cast: the parameters [crate::precision::keeps_f32] names
+/// cast to f32, then the rest cast down. An in-memory shard is copied for that.
 pub fn load_mamba(
     checkpoint: Checkpoint,
-    mamba_config: MambaVocabNetConfig,
+    mut mamba_config: MambaVocabNetConfig,
+    precision: RuntimePrecision,
     device: &Device,
 ) -> anyhow::Result<MambaVocabNet> {
+    use std::collections::BTreeSet;
+
+    let names = checkpoint.tensor_names()?;
+    let layout = Layout::detect(names.iter().map(String::as_str));
+    let untied = names.iter().any(|name| name == "lm_head.weight");
+    log::info!("checkpoint layout: {layout:?}, untied LM head: {untied}, precision: {precision}");
+    if untied {
+        set_missing_lm_head(&mut mamba_config, false);
+    }
+
     let mut mamba: MambaVocabNet = mamba_config.init(device);
 
-    let mut store = checkpoint
-        .store()
-        // `MambaVocabNet` is an enum module: drop the `Mamba1`/`Mamba2` variant
-        // segment so the checkpoint paths line up with the inner network.
-        .skip_enum_variants(true)
-        .with_from_adapter(
-            PyTorchToBurnAdapter.chain(FloatCastAdapter::to(crate::PRECISION_FLOAT_D_TYPE.into())),
-        );
-    for (from, to) in key_remapping(&mamba_config) {
-        store = store.with_key_remapping(from, to);
+    let shards = checkpoint.into_shards(precision.float_dtype())?;
+    let sharded = shards.len() > 1;
+    let (mut applied, mut unused, mut missing) =
+        (BTreeSet::new(), BTreeSet::new(), BTreeSet::new());
+    let passes = precision_passes(precision);
+    for (i, shard) in shards.into_iter().enumerate() {
+        // a tensor is unused only if no pass took it
+        let mut shard_unused: Option<BTreeSet<String>> = None;
+        let copies = std::iter::repeat_n(shard, passes.len());
+        for (shard, (filter, dtype)) in copies.zip(&passes) {
+            let mut store = shard
+                .store()?
+                // `MambaVocabNet` is an enum module: drop the `Mamba1`/`Mamba2`
+                // variant segment so the checkpoint paths line up with the
+                // inner network.
+                .skip_enum_variants(true)
+                // a shard or a pass on its own is always partial; the set is
+                // checked below
+                .allow_partial(sharded || passes.len() > 1)
+                .with_from_adapter(
+                    PyTorchToBurnAdapter.chain(FloatCastAdapter::to((*dtype).into())),
+                );
+            if let Some(filter) = filter {
+                store = store.filter(filter.clone());
+            }
+            for (from, to) in key_remapping(&mamba_config, layout) {
+                store = store.with_key_remapping(from, to);
+            }
+            let result = mamba.load_from(&mut store).map_err(|e| match sharded {
+                true => anyhow::anyhow!("failed to load shard {i} of the mamba checkpoint: {e}"),
+                false => anyhow::anyhow!("failed to load the mamba checkpoint: {e}"),
+            })?;
+            applied.extend(result.applied);
+            missing.extend(result.missing);
+            let pass_unused = BTreeSet::from_iter(result.unused);
+            shard_unused = Some(match shard_unused {
+                Some(earlier) => earlier.intersection(&pass_unused).cloned().collect(),
+                None => pass_unused,
+            });
+        }
+        unused.extend(shard_unused.unwrap_or_default());
     }
+    let missing: Vec<_> = missing.difference(&applied).collect();
+    anyhow::ensure!(
+        missing.is_empty(),
+        "no shard of the mamba checkpoint fills {} parameter(s): {missing:?}",
+        missing.len()
+    );
 
-    let result = mamba
-        .load_from(&mut store)
-        .map_err(|e| anyhow::anyhow!("failed to load the mamba checkpoint: {e}"))?;
-    if !result.unused.is_empty() {
+    if !unused.is_empty() {
         log::warn!(
             "{} checkpoint tensor(s) went unused: {:?}",
-            result.unused.len(),
-            result.unused
+            unused.len(),
+            unused
         );
     }
-    log::info!(
-        "loaded {} tensors from the checkpoint",
-        result.applied.len()
-    );
+    log::info!("loaded {} tensors from the checkpoint", applied.len());
 
-    tie_lm_head(&mut mamba, device);
+    if !untied {
+        tie_lm_head(&mut mamba, device);
+    }
 
     Ok(mamba)
 }
 
+/// The loads a model at `precision` takes: the parameters each is limited to
+/// (`None` for all of them), and the dtype it casts them to.
+fn precision_passes(precision: RuntimePrecision) -> Vec<(Option<PathFilter>, FloatDType)> {
+    match precision.is_mixed() {
+        true => vec![
+            (
+                Some(PathFilter::from_predicate(|path, _| keeps_f32(path))),
+                FloatDType::F32,
+            ),
+            (
+                Some(PathFilter::from_predicate(|path, _| !keeps_f32(path))),
+                precision.float_dtype(),
+            ),
+        ],
+        false => vec![(None, precision.float_dtype())],
+    }
+}
+
+/// Casts the parameters of a model that was built rather than loaded — the
+/// benches' random one — to `precision`, as [load_mamba] would have. The model
+/// is saved to safetensors bytes in memory and loaded back through the casts.
+pub fn cast_mamba(mamba: &mut MambaVocabNet, precision: RuntimePrecision) -> anyhow::Result<()> {
+    let mut saved = SafetensorsStore::from_bytes(None);
+    mamba
+        .save_into(&mut saved)
+        .map_err(|e| anyhow::anyhow!("failed to save the model to cast it: {e}"))?;
+    let bytes = saved
+        .get_bytes()
+        .map_err(|e| anyhow::anyhow!("failed to save the model to cast it: {e}"))?;
+
+    let passes = precision_passes(precision);
+    let copies = std::iter::repeat_n(bytes, passes.len());
+    for (bytes, (filter, dtype)) in copies.zip(passes) {
+        // a store per pass, as in [load_mamba]
+        let mut store = SafetensorsStore::from_bytes(Some(bytes))
+            .allow_partial(true)
+            .with_from_adapter(FloatCastAdapter::to(dtype.into()));
+        if let Some(filter) = filter {
+            store = store.filter(filter);
+        }
+        mamba
+            .load_from(&mut store)
+            .map_err(|e| anyhow::anyhow!("failed to cast the model to {precision}: {e}"))?;
+    }
+    Ok(())
+}
+
+pub(crate) fn set_missing_lm_head(config: &mut MambaVocabNetConfig, missing: bool) {
+    match config {
+        #[cfg(feature = "mamba1")]
+        MambaVocabNetConfig::Mamba1 {
+            missing_lm_head, ..
+        } => *missing_lm_head = missing,
+        #[cfg(feature = "mamba2")]
+        MambaVocabNetConfig::Mamba2 {
+            missing_lm_head, ..
+        } => *missing_lm_head = missing,
+        #[cfg(feature = "mamba3")]
+        MambaVocabNetConfig::Mamba3 {
+            missing_lm_head, ..
+        } => *missing_lm_head = missing,
+    }
+}
+
 /// The `safetensors name → Burn module path` rewrites, applied in order.
 ///
 /// The structural rules run first (they strip the `backbone.` prefix and expand
 /// `layers.{i}.mixer` into `layers.real_layers.{i}.mamba_block`); the
-/// per-parameter renames then operate on the already-rewritten paths.
-fn key_remapping(config: &MambaVocabNetConfig) -> Vec<(&'static str, &'static str)> {
+/// per-parameter renames then operate on the already-rewritten paths. An
+/// untied `lm_head.weight` is already the Burn path.
+fn key_remapping(
+    config: &MambaVocabNetConfig,
+    layout: Layout,
+) -> Vec<(&'static str, &'static str)> {
     let mut rules: Vec<(&'static str, &'static str)> = vec![
         // backbone.layers.{i}.mixer.X -> layers.real_layers.{i}.mamba_block.X
         (
@@ -94,6 +327,9 @@ fn key_remapping(config: &MambaVocabNetConfig) -> Vec<(&'static str, &'static st
         // where the checkpoint says `weight`.
         (r"(^|\.)norm(_f|2)?\.weight$", "${1}norm${2}.gamma"),
     ];
+    if layout == Layout::Transformers {
+        rules.push((r"^embeddings\.", "embedding."));
+    }
 
     #[allow(irrefutable_let_patterns)]
     match config {
@@ -139,6 +375,240 @@ fn key_remapping(config: &MambaVocabNetConfig) -> Vec<(&'static str, &'static st
     rules
 }
 
+/// [key_remapping] backwards, to the [Layout::StateSpaces] names: the
+/// per-parameter renames first, while the paths still carry `mamba_block`.
+fn export_key_remapping(config: &MambaVocabNetConfig) -> Vec<(&'static str, &'static str)> {
+    let mut rules: Vec<(&'static str, &'static str)> = Vec::new();
+    #[allow(irrefutable_let_patterns)]
+    match config {
+        #[cfg(feature = "mamba1")]
+        MambaVocabNetConfig::Mamba1 { .. } => {
+            rules.push((r"\.mamba_block\.a_log$", ".mamba_block.A_log"));
+            rules.push((r"\.mamba_block\.d$", ".mamba_block.D"));
+        }
+        #[cfg(feature = "mamba2")]
+        MambaVocabNetConfig::Mamba2 { .. } => {
+            rules.push((r"\.mamba_block\.a_log_h$", ".mamba_block.A_log"));
+            rules.push((r"\.mamba_block\.d_h$", ".mamba_block.D"));
+            rules.push((r"\.mamba_block\.dt_bias_h$", ".mamba_block.dt_bias"));
+        }
+        #[cfg(feature = "mamba3")]
+        MambaVocabNetConfig::Mamba3 { mamba_block, .. } => {
+            rules.push((r"\.mamba_block\.d_h$", ".mamba_block.D"));
+            rules.push((r"\.mamba_block\.dt_bias_h$", ".mamba_block.dt_bias"));
+            rules.push((r"\.mamba_block\.b_bias_hmr$", ".mamba_block.B_bias"));
+            rules.push((r"\.mamba_block\.c_bias_hmr$", ".mamba_block.C_bias"));
+            rules.push((
+                r"\.mamba_block\.b_norm\.gamma$",
+                ".mamba_block.B_norm.weight",
+            ));
+            rules.push((
+                r"\.mamba_block\.c_norm\.gamma$",
+                ".mamba_block.C_norm.weight",
+            ));
+            if mamba_block.mimo_rank > 1 {
+                rules.push((r"\.mamba_block\.mimo_x_hmp$", ".mamba_block.mimo_x"));
+                rules.push((r"\.mamba_block\.mimo_z_hmp$", ".mamba_block.mimo_z"));
+                rules.push((r"\.mamba_block\.mimo_o_hmp$", ".mamba_block.mimo_o"));
+            }
+        }
+    }
+    rules.extend([
+        (r"(^|\.)norm(_f|2)?\.gamma$", "${1}norm${2}.weight"),
+        (
+            r"^layers\.real_layers\.(\d+)\.mamba_block\.",
+            "backbone.layers.$1.mixer.",
+        ),
+        (r"^layers\.real_layers\.(\d+)\.", "backbone.layers.$1."),
+        (r"^(embedding|norm_f)\.", "backbone.$1."),
+    ]);
+    rules
+}
+
+/// Saves every parameter of `mamba` to a safetensors file, under Burn's own
+/// module paths — not a checkpoint the HF tooling reads (see [save_checkpoint]
+/// for that), but one [load_weights] restores exactly, on any device.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_weights(mamba: &MambaVocabNet, path: &std::path::Path) -> anyhow::Result<()> {
+    let mut store = SafetensorsStore::from_file(path);
+    mamba
+        .save_into(&mut store)
+        .map_err(|e| anyhow::anyhow!("failed to save the weights to {path:?}: {e}"))
+}
+
+/// Builds the model from `mamba_config` and overwrites every parameter from a
+/// file [save_weights] wrote, then ties the LM head as [load_mamba] does.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn load_weights(
+    path: &std::path::Path,
+    mamba_config: MambaVocabNetConfig,
+    device: &Device,
+) -> anyhow::Result<MambaVocabNet> {
+    let mut mamba: MambaVocabNet = mamba_config.init(device);
+    let mut store = SafetensorsStore::from_file(path);
+    let result = mamba
+        .load_from(&mut store)
+        .map_err(|e| anyhow::anyhow!("failed to load the weights from {path:?}: {e}"))?;
+    anyhow::ensure!(
+        result.missing.is_empty(),
+        "{path:?} lacks {} parameter(s): {:?}",
+        result.missing.len(),
+        result.missing
+    );
+    tie_lm_head(&mut mamba, device);
+    Ok(mamba)
+}
+
+/// The inverse of [load_mamba]: `mamba` as a [Layout::StateSpaces] checkpoint,
+/// the `model.safetensors` bytes plus the `config.json` describing them.
+///
+/// The tensors take back their original names, and `Linear` weights PyTorch's
+/// `[out, in]`. A tied head is left out, as the published checkpoints leave it;
+/// an untied one is written as `lm_head.weight`. Which one `mamba` has is read
+/// off its weights rather than `mamba_config`'s `missing_lm_head`, which
+/// [load_mamba] overrides for a checkpoint with a head of its own. Every tensor
+/// is written as f32, so [load_mamba] on the result rebuilds `mamba` exactly.
+pub fn export_checkpoint(
+    mamba: &MambaVocabNet,
+    mamba_config: &MambaVocabNetConfig,
+) -> anyhow::Result<(Vec<u8>, crate::config_json::ConfigJson)> {
+    let tied = head_is_tied(mamba)?;
+    let mut mamba_config = mamba_config.clone();
+    set_missing_lm_head(&mut mamba_config, tied);
+    let mamba_config = &mamba_config;
+    let rules = burn::store::KeyRemapper::from_patterns(export_key_remapping(mamba_config))
+        .map_err(|e| anyhow::anyhow!("the export remapping does not compile: {e:?}"))?
+        .to_regex_pairs();
+
+    let mut tensors = Vec::new();
+    for snapshot in mamba.collect(None, None, true) {
+        let path = snapshot.full_path();
+        if tied && path == "lm_head.weight" {
+            continue;
+        }
+        let mut name = path.clone();
+        for (regex, replacement) in &rules {
+            name = regex.replace_all(&name, replacement.as_str()).to_string();
+        }
+        let mut shape = snapshot.shape.to_vec();
+        let data = snapshot
+            .to_data()
+            .map_err(|e| anyhow::anyhow!("failed to read {path}: {e:?}"))?;
+        let mut values = data
+            .convert::<f32>()
+            .to_vec::<f32>()
+            .map_err(|e| anyhow::anyhow!("failed to read {path}: {e:?}"))?;
+        // the same test the import's `PyTorchToBurnAdapter` makes, backwards
+        let linear = path.starts_with("layers.") || path == "lm_head.weight";
+        if linear && shape.len() == 2 && path.ends_with(".weight") {
+            let (rows, cols) = (shape[0], shape[1]);
+            values = (0..rows * cols)
+                .map(|i| values[(i % rows) * cols + i / rows])
+                .collect();
+            shape = vec![cols, rows];
+        }
+        tensors.push((name, shape, values));
+    }
+
+    // `GatedMlpConfig` rounds its width, so it is read off the built `fc1`
+    // (the gate and up projections, stacked)
+    let d_intermediate = tensors
+        .iter()
+        .find(|(name, _, _)| name == "backbone.layers.0.mlp.fc1.weight")
+        .map_or(0, |(_, shape, _)| shape[0] / 2);
+    let config_json =
+        crate::config_json::ConfigJson::from_vocab_net_config(mamba_config, d_intermediate)?;
+
+    let mut bytes = safetensors_header(
+        tensors
+            .iter()
+            .map(|(name, shape, _)| (name.clone(), shape.clone(), FloatDType::F32)),
+    );
+    for (_, _, values) in tensors {
+        bytes.extend(values.into_iter().flat_map(f32::to_le_bytes));
+    }
+    Ok((bytes, config_json))
+}
+
+/// Whether the LM head of `mamba` is the transposed embedding, as
+/// [tie_lm_head] builds it, or absent altogether.
+pub(crate) fn head_is_tied(mamba: &MambaVocabNet) -> anyhow::Result<bool> {
+    let (embedding, lm_head) = match mamba {
+        #[cfg(feature = "mamba1")]
+        MambaVocabNet::Mamba1(m) => (m.embedding.weight.val(), m.lm_head.clone()),
+        #[cfg(feature = "mamba2")]
+        MambaVocabNet::Mamba2(m) => (m.embedding.weight.val(), m.lm_head.clone()),
+        #[cfg(feature = "mamba3")]
+        MambaVocabNet::Mamba3(m) => (m.embedding.weight.val(), m.lm_head.clone()),
+    };
+    let Some(lm_head) = lm_head else {
+        return Ok(true);
+    };
+    if lm_head.bias.is_some() || lm_head.weight.dims() != [embedding.dims()[1], embedding.dims()[0]]
+    {
+        return Ok(false);
+    }
+    let values = |tensor: Tensor<2>| {
+        tensor
+            .into_data()
+            .convert::<f32>()
+            .to_vec::<f32>()
+            .map_err(|e| anyhow::anyhow!("failed to read the LM head: {e:?}"))
+    };
+    Ok(values(lm_head.weight.val().swap_dims(0, 1))? == values(embedding)?)
+}
+
+/// [export_checkpoint] into `dir`, as `model.safetensors` and `config.json`.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_checkpoint(
+    mamba: &MambaVocabNet,
+    mamba_config: &MambaVocabNetConfig,
+    dir: &std::path::Path,
+) -> anyhow::Result<()> {
+    let (model, config) = export_checkpoint(mamba, mamba_config)?;
+    std::fs::create_dir_all(dir)?;
+    for (file, bytes) in [
+        ("model.safetensors", model),
+        ("config.json", serde_json::to_vec_pretty(&config)?),
+    ] {
+        let path = dir.join(file);
+        std::fs::write(&path, bytes)
+            .map_err(|e| anyhow::anyhow!("failed to write {path:?}: {e}"))?;
+    }
+    Ok(())
+}
+
+/// The safetensors prefix for `(name, shape, dtype)` tensors whose data
+/// follows it back to back, in the same order: the header length, then the
+/// header, padded to keep the data 8-byte aligned. The whole file's capacity
+/// is reserved up front.
+pub(crate) fn safetensors_header(
+    tensors: impl IntoIterator<Item = (String, Vec<usize>, FloatDType)>,
+) -> Vec<u8> {
+    let mut header = serde_json::Map::new();
+    let mut end = 0;
+    for (name, shape, dtype) in tensors {
+        let (dtype, size) = match dtype {
+            FloatDType::F16 => ("F16", 2),
+            FloatDType::BF16 => ("BF16", 2),
+            FloatDType::F32 => ("F32", 4),
+            other => unreachable!("{other:?} tensors are never written"),
+        };
+        let len = shape.iter().product::<usize>() * size;
+        header.insert(
+            name,
+            serde_json::json!({"dtype": dtype, "shape": shape, "data_offsets": [end, end + len]}),
+        );
+        end += len;
+    }
+    let mut header = serde_json::to_vec(&header).expect("a JSON object");
+    header.resize(header.len().next_multiple_of(8), b' ');
+    let mut bytes = Vec::with_capacity(8 + header.len() + end);
+    bytes.extend_from_slice(&(header.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&header);
+    bytes
+}
+
 /// The checkpoints tie the LM head to the embedding (`missing_lm_head: true`),
 /// so the head is the transposed embedding table.
 ///
@@ -174,6 +644,96 @@ pub fn tie_lm_head(mamba: &mut MambaVocabNet, device: &Device) {
     }
 }
 
+/// `mamba` split at its LM head: a copy whose head is the identity, so that its
+/// `step` yields the (normed) final hidden state `[batch, d_model]`, and the
+/// head itself, to turn that hidden state into the logits.
+///
+/// burn-mamba's `step` only returns the head's output. Every weight but the
+/// `[d_model, d_model]` identity is shared with `mamba`.
+pub fn split_lm_head(mamba: &MambaVocabNet, device: &Device) -> (MambaVocabNet, burn::nn::Linear) {
+    fn lm_head(mamba: &MambaVocabNet) -> Option<burn::nn::Linear> {
+        match mamba {
+            #[cfg(feature = "mamba1")]
+            MambaVocabNet::Mamba1(m) => m.lm_head.clone(),
+            #[cfg(feature = "mamba2")]
+            MambaVocabNet::Mamba2(m) => m.lm_head.clone(),
+            #[cfg(feature = "mamba3")]
+            MambaVocabNet::Mamba3(m) => m.lm_head.clone(),
+        }
+    }
+
+    let mut headless = mamba.clone();
+    if lm_head(&headless).is_none() {
+        tie_lm_head(&mut headless, device);
+    }
+    let head = lm_head(&headless).expect("the LM head was just tied");
+
+    let [d_model, _vocab] = head.weight.dims();
+    let identity = Some(burn::nn::Linear {
+        weight: Param::from_tensor(Tensor::eye(d_model, device)),
+        bias: None,
+    });
+    match &mut headless {
+        #[cfg(feature = "mamba1")]
+        MambaVocabNet::Mamba1(m) => m.lm_head = identity,
+        #[cfg(feature = "mamba2")]
+        MambaVocabNet::Mamba2(m) => m.lm_head = identity,
+        #[cfg(feature = "mamba3")]
+        MambaVocabNet::Mamba3(m) => m.lm_head = identity,
+    }
+    (headless, head)
+}
+
+/// The round trip the checkpoint tests share: a random model exported, and the
+/// parameters to compare its reload against.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// `config`'s model on random weights, its head tied or one of its own,
+    /// and that model exported: the `model.safetensors` bytes, and the config
+    /// read back off the `config.json` beside them, which a reload builds.
+    pub(crate) fn exported(
+        config: &MambaVocabNetConfig,
+        tied: bool,
+        device: &Device,
+    ) -> (MambaVocabNet, Vec<u8>, MambaVocabNetConfig) {
+        // an untied model exported with the spec's config, as one loaded from
+        // a fine-tune with a head of its own would be
+        let mut init_config = config.clone();
+        set_missing_lm_head(&mut init_config, tied);
+        let mut mamba: MambaVocabNet = init_config.init(device);
+        if tied {
+            tie_lm_head(&mut mamba, device);
+        }
+        let (bytes, config_json) = export_checkpoint(&mamba, config).unwrap();
+        let config_json = serde_json::to_vec(&config_json).unwrap();
+        let config_json = crate::config_json::ConfigJson::from_bytes(&config_json).unwrap();
+        assert_eq!(config_json.tie_embeddings, tied);
+        (mamba, bytes, config_json.vocab_net_config().unwrap())
+    }
+
+    /// Every parameter of `mamba`, by module path.
+    pub(crate) fn params(mamba: &MambaVocabNet) -> BTreeMap<String, TensorData> {
+        mamba
+            .collect(None, None, true)
+            .into_iter()
+            .map(|snapshot| (snapshot.full_path(), snapshot.to_data().unwrap()))
+            .collect()
+    }
+
+    /// `actual` holds every parameter of `expected` and no other, bit for bit
+    /// and dtype for dtype.
+    pub(crate) fn assert_same_params(expected: &MambaVocabNet, actual: &MambaVocabNet, id: &str) {
+        let (expected, actual) = (params(expected), params(actual));
+        assert!(expected.keys().eq(actual.keys()), "{id}");
+        for (path, want) in &expected {
+            want.assert_eq(&actual[path], true);
+        }
+    }
+}
+
 /// Checks [`key_remapping`] against the real checkpoint manifests: nothing on
 /// either side of the load may be left dangling.
 ///
@@ -199,8 +759,12 @@ mod tests {
 
     /// One checkpoint's vendored `model.safetensors` header.
     struct Vendored {
-        /// The checkpoint it was read from.
+        /// The topology it loads into.
         spec: &'static crate::ModelSpec,
+        /// The repo and revision it was read from: the spec's own, except for
+        /// the `-hf` conversions, which share their topology with a spec.
+        repo_id: &'static str,
+        revision: &'static str,
         /// Everything outside `backbone.layers.`, plus the whole of layer 0.
         /// Every layer repeats layer 0's names and shapes, which is what lets
         /// one layer stand for all of them.
@@ -225,6 +789,24 @@ mod tests {
         ("backbone.layers.0.mixer.x_proj.weight", &[80, 1536]),
     ];
 
+    /// `state-spaces/mamba-130m-hf`: the same weights as [MAMBA1_MANIFEST],
+    /// in the [Layout::Transformers] naming.
+    #[cfg(feature = "mamba1")]
+    const MAMBA1_HF_MANIFEST: Manifest = &[
+        ("backbone.embeddings.weight", &[50280, 768]),
+        ("backbone.norm_f.weight", &[768]),
+        ("backbone.layers.0.norm.weight", &[768]),
+        ("backbone.layers.0.mixer.A_log", &[1536, 16]),
+        ("backbone.layers.0.mixer.D", &[1536]),
+        ("backbone.layers.0.mixer.conv1d.bias", &[1536]),
+        ("backbone.layers.0.mixer.conv1d.weight", &[1536, 1, 4]),
+        ("backbone.layers.0.mixer.dt_proj.bias", &[1536]),
+        ("backbone.layers.0.mixer.dt_proj.weight", &[1536, 48]),
+        ("backbone.layers.0.mixer.in_proj.weight", &[3072, 768]),
+        ("backbone.layers.0.mixer.out_proj.weight", &[768, 1536]),
+        ("backbone.layers.0.mixer.x_proj.weight", &[80, 1536]),
+    ];
+
     #[cfg(feature = "mamba2")]
     const MAMBA2_MANIFEST: Manifest = &[
         ("backbone.embedding.weight", &[50288, 768]),
@@ -285,24 +867,45 @@ mod tests {
         #[cfg(feature = "mamba1")]
         Vendored {
             spec: &crate::hf::mamba1_130m::SPEC,
+            repo_id: crate::hf::mamba1_130m::SPEC.repo_id,
+            revision: crate::hf::mamba1_130m::SPEC.revision_path,
             manifest: MAMBA1_MANIFEST,
             n_layers: crate::hf::mamba1_130m::N_LAYER,
         },
+        #[cfg(feature = "mamba1")]
+        Vendored {
+            spec: &crate::hf::mamba1_130m::SPEC,
+            repo_id: "state-spaces/mamba-130m-hf",
+            revision: "main",
+            manifest: MAMBA1_HF_MANIFEST,
+            n_layers: crate::hf::mamba1_130m::N_LAYER,
+        },
+        // No Mamba-2 `-hf` manifest: `state-spaces` publishes `transformers`
+        // conversions of its Mamba-1 checkpoints only, and the third-party
+        // Mamba-2 ones could not be read here to vendor a header faithfully.
+        // `Mamba2ForCausalLM` renames nothing but the embedding, the one rule
+        // [Layout::Transformers] adds, which the Mamba-1 `-hf` manifest covers.
         #[cfg(feature = "mamba2")]
         Vendored {
             spec: &crate::hf::mamba2_130m::SPEC,
+            repo_id: crate::hf::mamba2_130m::SPEC.repo_id,
+            revision: crate::hf::mamba2_130m::SPEC.revision_path,
             manifest: MAMBA2_MANIFEST,
             n_layers: crate::hf::mamba2_130m::N_LAYER,
         },
         #[cfg(feature = "mamba3")]
         Vendored {
             spec: &crate::hf::mamba3_siso_187m::SPEC,
+            repo_id: crate::hf::mamba3_siso_187m::SPEC.repo_id,
+            revision: crate::hf::mamba3_siso_187m::SPEC.revision_path,
             manifest: SISO_MANIFEST,
             n_layers: crate::hf::mamba3_siso_187m::N_LAYER,
         },
         #[cfg(feature = "mamba3")]
         Vendored {
             spec: &crate::hf::mamba3_mimo_187m::SPEC,
+            repo_id: crate::hf::mamba3_mimo_187m::SPEC.repo_id,
+            revision: crate::hf::mamba3_mimo_187m::SPEC.revision_path,
             manifest: MIMO_MANIFEST,
             n_layers: crate::hf::mamba3_mimo_187m::N_LAYER,
         },
@@ -357,7 +960,8 @@ mod tests {
     /// Two names remapping onto one parameter is a panic: the second would
     /// overwrite the first, leaving whichever lost silently unloaded.
     fn remapped(manifest: Manifest, config: &MambaVocabNetConfig) -> Remapped {
-        let rules = key_remapping(config);
+        let layout = Layout::detect(manifest.iter().map(|(name, _)| *name));
+        let rules = key_remapping(config, layout);
         let patterns = burn::store::KeyRemapper::from_patterns(rules.clone())
             .expect("the remapping patterns must compile")
             .to_regex_pairs();
@@ -409,8 +1013,8 @@ mod tests {
     #[test]
     fn checkpoints_and_modules_hold_the_same_tensors() {
         for vendored in VENDORED {
-            let id = vendored.spec.id;
-            let full = (vendored.spec.config)();
+            let id = vendored.repo_id;
+            let full = vendored.spec.config();
             let config = one_layer(full.clone());
             let mut expected = remapped(vendored.manifest, &config).params;
 
@@ -453,8 +1057,8 @@ mod tests {
     #[test]
     fn remapping_rules_all_fire() {
         for vendored in VENDORED {
-            let id = vendored.spec.id;
-            let config = one_layer((vendored.spec.config)());
+            let id = vendored.repo_id;
+            let config = one_layer(vendored.spec.config());
             let unused: Vec<&str> = remapped(vendored.manifest, &config)
                 .rule_hits
                 .into_iter()
@@ -468,6 +1072,156 @@ mod tests {
         }
     }
 
+    /// The export names every parameter as the checkpoint it would have been
+    /// loaded from does, every rule rewriting something.
+    #[test]
+    fn export_remapping_inverts_the_import() {
+        for vendored in VENDORED {
+            let manifest = vendored.manifest;
+            if Layout::detect(manifest.iter().map(|(name, _)| *name)) != Layout::StateSpaces {
+                continue;
+            }
+            let id = vendored.repo_id;
+            let config = one_layer(vendored.spec.config());
+            let rules = export_key_remapping(&config);
+            let patterns = burn::store::KeyRemapper::from_patterns(rules.clone())
+                .expect("the remapping patterns must compile")
+                .to_regex_pairs();
+            let mut hits = vec![0; rules.len()];
+            let mut exported = BTreeSet::new();
+            for path in remapped(manifest, &config).params.into_keys() {
+                let mut name = path;
+                for (index, (regex, replacement)) in patterns.iter().enumerate() {
+                    if regex.is_match(&name) {
+                        hits[index] += 1;
+                        name = regex.replace_all(&name, replacement.as_str()).to_string();
+                    }
+                }
+                exported.insert(name);
+            }
+            let original: BTreeSet<String> =
+                manifest.iter().map(|(name, _)| name.to_string()).collect();
+            assert_eq!(exported, original, "{id}");
+            let unused: Vec<&str> = rules
+                .iter()
+                .zip(hits)
+                .filter(|(_, hits)| *hits == 0)
+                .map(|((pattern, _), _)| *pattern)
+                .collect();
+            assert!(
+                unused.is_empty(),
+                "{id}: export rule(s) that rewrite nothing: {unused:?}"
+            );
+        }
+    }
+
+    /// [export_checkpoint] and [load_mamba] are inverses: the reloaded model
+    /// holds every parameter of the exported one, bit for bit.
+    #[test]
+    fn exported_checkpoints_load_back_exactly() {
+        let device: Device = Default::default();
+        for (vendored, tied) in VENDORED.iter().flat_map(|v| [(v, true), (v, false)]) {
+            let id = vendored.repo_id;
+            let config = one_layer(vendored.spec.config());
+            let (mamba, bytes, config) = fixtures::exported(&config, tied, &device);
+            let reloaded = load_mamba(
+                Checkpoint::Bytes(bytes),
+                config,
+                RuntimePrecision::F32,
+                &device,
+            )
+            .unwrap_or_else(|e| panic!("{id}: {e}"));
+            fixtures::assert_same_params(&mamba, &reloaded, id);
+        }
+    }
+
+    /// The tensors of an f32 safetensors file, dealt alternately into two
+    /// shards; `drop` is left out of both.
+    fn two_shards(bytes: Vec<u8>, drop: Option<&str>) -> Checkpoint {
+        use burn::store::ModuleStore;
+        let tensors = SafetensorsStore::from_bytes(Some(bytes))
+            .get_all_snapshots()
+            .unwrap()
+            .clone();
+        let tensors: Vec<_> = tensors
+            .into_iter()
+            .filter(|(name, _)| Some(name.as_str()) != drop)
+            .collect();
+        let shard = |half: usize| {
+            let tensors = tensors.iter().skip(half).step_by(2);
+            let mut bytes = safetensors_header(
+                tensors
+                    .clone()
+                    .map(|(name, tensor)| (name.clone(), tensor.shape.clone(), FloatDType::F32)),
+            );
+            for (_, tensor) in tensors {
+                bytes.extend_from_slice(tensor.to_data().unwrap().as_bytes());
+            }
+            Checkpoint::Bytes(bytes)
+        };
+        Checkpoint::Sharded(vec![shard(0), shard(1)])
+    }
+
+    /// An exported checkpoint split into two shards loads as the single file
+    /// does; one tensor short, it is refused rather than left at its init.
+    #[test]
+    fn sharded_checkpoints_load_like_one_file() {
+        let device: Device = Default::default();
+        for vendored in VENDORED {
+            let id = vendored.repo_id;
+            let config = one_layer(vendored.spec.config());
+            let (mamba, bytes, config) = fixtures::exported(&config, true, &device);
+            let load =
+                |checkpoint| load_mamba(checkpoint, config.clone(), RuntimePrecision::F32, &device);
+
+            let reloaded =
+                load(two_shards(bytes.clone(), None)).unwrap_or_else(|e| panic!("{id}: {e}"));
+            fixtures::assert_same_params(&mamba, &reloaded, id);
+
+            let dropped = "backbone.layers.0.mixer.in_proj.weight";
+            let error = load(two_shards(bytes, Some(dropped)))
+                .map(|_| ())
+                .expect_err(id)
+                .to_string();
+            assert!(
+                error.contains("no shard of the mamba checkpoint fills"),
+                "{id}: {error}"
+            );
+            assert!(error.contains("in_proj"), "{id}: {error}");
+        }
+    }
+
+    /// A mixed precision keeps the recurrence parameters in f32 and casts the
+    /// rest down, each pass through a store of its own.
+    #[test]
+    fn mixed_precision_loads_each_parameter_at_its_dtype() {
+        let device: Device = Default::default();
+        let precision = RuntimePrecision::Bf16 { mixed: true };
+        for vendored in VENDORED {
+            let id = vendored.repo_id;
+            let config = one_layer(vendored.spec.config());
+            let (_, bytes, config) = fixtures::exported(&config, true, &device);
+            let reloaded = load_mamba(Checkpoint::Bytes(bytes), config, precision, &device)
+                .unwrap_or_else(|e| panic!("{id}: {e}"));
+
+            let dtypes: BTreeMap<String, burn::tensor::DType> = reloaded
+                .collect(None, None, true)
+                .into_iter()
+                .map(|snapshot| (snapshot.full_path(), snapshot.dtype))
+                .collect();
+            for (path, dtype) in &dtypes {
+                assert_eq!(*dtype, precision.param_dtype(path).into(), "{id}: {path}");
+            }
+            let block = "layers.real_layers.0.mamba_block";
+            let a_log = [format!("{block}.a_log_h"), format!("{block}.a_log")];
+            let a_log = a_log.iter().find(|path| dtypes.contains_key(*path));
+            let a_log = a_log.unwrap_or_else(|| panic!("{id}: no a_log"));
+            assert_eq!(dtypes[a_log], burn::tensor::DType::F32, "{id}");
+            let in_proj = format!("{block}.in_proj.weight");
+            assert_eq!(dtypes[&in_proj], burn::tensor::DType::BF16, "{id}");
+        }
+    }
+
     /// Confirms each vendored manifest really is its whole checkpoint header:
     /// the file must hold exactly the manifest's model-level tensors plus one
     /// copy of layer 0 per layer, at the same shapes. Without this the offline
@@ -482,43 +1236,26 @@ mod tests {
     #[ignore = "needs the real model.safetensors of every compiled-in checkpoint"]
     fn manifests_cover_the_whole_checkpoint() {
         use crate::hub::{FilePath, Repo, RepoId, RepoType, RevisionPath};
-        use std::io::Read;
+        use burn::store::ModuleStore;
 
         let api = crate::hub::sync::Api::new().expect("a hub client");
         for vendored in VENDORED {
-            let spec = vendored.spec;
+            let id = vendored.repo_id;
             let path = api
                 .repo(Repo::with_revision(
-                    RepoId(spec.repo_id.into()),
+                    RepoId(vendored.repo_id.into()),
                     RepoType::Model,
-                    RevisionPath(spec.revision_path.into()),
+                    RevisionPath(vendored.revision.into()),
                 ))
-                .get(&FilePath(spec.file_path_model_safetensors.into()))
-                .unwrap_or_else(|e| panic!("{}: {e}", spec.id));
-
-            // safetensors: an 8-byte little-endian header length, then that many
-            // bytes of JSON — `name → {dtype, shape, data_offsets}`.
-            let mut file = std::fs::File::open(&path).expect("the cached checkpoint");
-            let mut length = [0u8; 8];
-            file.read_exact(&mut length).expect("a header length");
-            let mut header = vec![0u8; u64::from_le_bytes(length) as usize];
-            file.read_exact(&mut header).expect("a header");
-            let header: BTreeMap<String, serde_json::Value> =
-                serde_json::from_slice(&header).expect("a safetensors header");
-
-            let mut found: BTreeMap<String, Vec<usize>> = BTreeMap::new();
-            for (name, entry) in header {
-                if name == "__metadata__" {
-                    continue;
-                }
-                let shape = entry["shape"]
-                    .as_array()
-                    .expect("a shape")
-                    .iter()
-                    .map(|dim| dim.as_u64().expect("a dimension") as usize)
-                    .collect();
-                found.insert(name, shape);
-            }
+                .get(&FilePath(vendored.spec.file_path_model_safetensors.into()))
+                .unwrap_or_else(|e| panic!("{id}: {e}"));
+
+            let found: BTreeMap<String, Vec<usize>> = SafetensorsStore::from_file(&path)
+                .get_all_snapshots()
+                .unwrap_or_else(|e| panic!("{id}: {e}"))
+                .iter()
+                .map(|(name, snapshot)| (name.clone(), snapshot.shape.clone()))
+                .collect();
 
             let mut expected: BTreeMap<String, Vec<usize>> = BTreeMap::new();
             for (name, shape) in vendored.manifest {
@@ -541,17 +1278,67 @@ mod tests {
             let extra: Vec<&str> = found_keys.difference(&expected_keys).copied().collect();
             assert!(
                 missing.is_empty(),
-                "{}: the manifest claims tensor(s) the checkpoint does not have: {missing:?}",
-                spec.id
+                "{id}: the manifest claims tensor(s) the checkpoint does not have: {missing:?}"
             );
             assert!(
                 extra.is_empty(),
-                "{}: the checkpoint holds tensor(s) the manifest does not cover: {extra:?}",
-                spec.id
+                "{id}: the checkpoint holds tensor(s) the manifest does not cover: {extra:?}"
             );
             for (name, want) in &expected {
-                assert_eq!(&found[name], want, "{}: shape mismatch at {name}", spec.id);
+                assert_eq!(&found[name], want, "{id}: shape mismatch at {name}");
             }
         }
     }
+
+    /// The layout is read off the header alone, which takes no tensor data.
+    #[test]
+    fn layout_is_read_off_the_header() {
+        let header = br#"{"__metadata__": {"format": "pt"},
+            "backbone.embeddings.weight": {"dtype": "F32", "shape": [0], "data_offsets": [0, 0]},
+            "lm_head.weight": {"dtype": "F32", "shape": [0], "data_offsets": [0, 0]}}"#;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header);
+        let names = Checkpoint::Bytes(bytes.clone()).tensor_names().unwrap();
+        assert_eq!(names, ["backbone.embeddings.weight", "lm_head.weight"]);
+        assert_eq!(
+            Layout::detect(names.iter().map(String::as_str)),
+            Layout::Transformers
+        );
+        assert_eq!(
+            Layout::detect(["backbone.embedding.weight", "backbone.norm_f.weight"]),
+            Layout::StateSpaces
+        );
+        assert!(
+            Checkpoint::Bytes(bytes[..12].to_vec())
+                .tensor_names()
+                .is_err()
+        );
+        // the header length is the file's word, not a size to allocate
+        let mut huge = u64::MAX.to_le_bytes().to_vec();
+        huge.extend_from_slice(header);
+        assert!(Checkpoint::Bytes(huge).tensor_names().is_err());
+    }
+
+    /// An index maps every tensor to its shard; each shard is loaded once, in
+    /// the `-0000x-of-` order.
+    #[test]
+    fn shard_index_lists_each_file_once() {
+        let index = br#"{
+            "metadata": {"total_size": 1024},
+            "weight_map": {
+                "backbone.norm_f.weight": "model-00002-of-00002.safetensors",
+                "backbone.embedding.weight": "model-00001-of-00002.safetensors",
+                "backbone.layers.0.norm.weight": "model-00001-of-00002.safetensors"
+            }
+        }"#;
+        assert_eq!(
+            shard_files(index).unwrap(),
+            [
+                "model-00001-of-00002.safetensors",
+                "model-00002-of-00002.safetensors"
+            ]
+        );
+        assert!(shard_files(br#"{"weight_map": {}}"#).is_err());
+        assert!(shard_files(b"[]").is_err());
+    }
 }
@@ -19,7 +19,7 @@
 //! pipelines is rejected at load time rather than silently ignored.
 
 mod bpe;
-mod byte_level;
+pub(crate) mod byte_level;
 
 use bpe::Bpe;
 use serde::Deserialize;
@@ -187,6 +187,12 @@ impl Tokenizer {
         self.vocab.len()
     }
 
+    /// One past the largest id, added tokens included: the smallest model
+    /// vocabulary this tokenizer fits in.
+    pub fn id_bound(&self) -> usize {
+        self.tokens.keys().max().map_or(0, |id| *id as usize + 1)
+    }
+
     /// Looks up a token's id, added tokens included.
     pub fn token_to_id(&self, token: &str) -> Option<u32> {
         self.vocab.get(token).copied().or_else(|| {
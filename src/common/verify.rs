@@ -0,0 +1,417 @@
+//! Numerical checks of the model against itself: the recurrent `step` against
+//! the chunkwise `forward` over the same tokens, and tracing non-finite logits
+//! back to the layer that produced them.
+//!
+//! Both compute the same function, by different kernels, so their logits may
+//! only differ by float rounding. Anything more is a bug in a kernel or in a
+//! backend — which is what these checks are for, before trusting a new one.
+//! They need no checkpoint: [random_model] builds any topology on random
+//! weights, and [token_ids] feeds it deterministic tokens. [tiny_specs] and
+//! [byte_tokenizer] go further, to a whole [crate::MambaWrapper] on random weights.
+
+use crate::tokenizer::Tokenizer;
+use crate::tokenizer::byte_level::bytes_char;
+use crate::{ModelSpec, PRECISION_FLOAT_D_TYPE, Precision, tie_lm_head};
+use burn::prelude::*;
+use burn::store::ModuleSnapshot;
+use burn_mamba::prelude::*;
+
+/// How far two logits may be apart: `|actual - expected| <= atol + rtol *
+/// |expected|`, as `numpy.allclose` has it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tolerance {
+    pub atol: f32,
+    pub rtol: f32,
+}
+
+impl Default for Tolerance {
+    /// Loose enough for f32 accumulating differently over a 24-layer model,
+    /// tight enough that any real kernel mismatch blows through it.
+    fn default() -> Self {
+        Self {
+            atol: 1e-3,
+            rtol: 1e-3,
+        }
+    }
+}
+
+/// How far apart two `[positions, vocab]` logits are; see [Divergence::between].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Divergence {
+    pub max_abs: f32,
+    /// The largest `|actual - expected| / |expected|`.
+    pub max_rel: f32,
+    /// The position whose worst logit is furthest past the tolerance (or the
+    /// least within it).
+    pub worst_position: usize,
+    /// Whether every logit is within the tolerance.
+    pub within: bool,
+}
+
+impl Divergence {
+    /// Compares `actual` to `expected`, both `[positions, vocab]` row-major. A
+    /// non-finite logit fails, unless it is the same infinity on both sides.
+    pub fn between(
+        expected: &[Precision],
+        actual: &[Precision],
+        vocab: usize,
+        tolerance: Tolerance,
+    ) -> Self {
+        assert_eq!(expected.len(), actual.len(), "the logits differ in shape");
+        let mut divergence = Self {
+            within: true,
+            ..Default::default()
+        };
+        // how far past its allowance the worst logit is, as a multiple of it
+        let mut worst_excess = f32::NEG_INFINITY;
+        for (position, (expected, actual)) in expected
+            .chunks_exact(vocab)
+            .zip(actual.chunks_exact(vocab))
+            .enumerate()
+        {
+            for (expected, actual) in expected.iter().zip(actual) {
+                let (abs, excess) = if expected == actual {
+                    (0., 0.)
+                } else if expected.is_finite() && actual.is_finite() {
+                    let abs = (actual - expected).abs();
+                    let allowance = tolerance.atol + tolerance.rtol * expected.abs();
+                    (abs, abs / allowance.max(f32::MIN_POSITIVE))
+                } else {
+                    (f32::INFINITY, f32::INFINITY)
+                };
+                divergence.max_abs = divergence.max_abs.max(abs);
+                let rel = abs / expected.abs().max(f32::MIN_POSITIVE);
+                divergence.max_rel = divergence.max_rel.max(rel);
+                divergence.within &= excess <= 1.;
+                if excess > worst_excess {
+                    worst_excess = excess;
+                    divergence.worst_position = position;
+                }
+            }
+        }
+        divergence
+    }
+}
+
+/// [Divergence] of `step` from `forward`, for the whole model and for each of
+/// its prefixes of layers; see [step_forward_parity].
+#[derive(Clone, Debug)]
+pub struct Parity {
+    pub logits: Divergence,
+    /// `layers[l]`: the model cut after layer `l`.
+    pub layers: Vec<Divergence>,
+}
+
+impl Parity {
+    /// The layer where `step` and `forward` part: the first whose prefix falls
+    /// out of tolerance, or — when all are within it — the one that adds the
+    /// most absolute error.
+    pub fn worst_layer(&self) -> Option<usize> {
+        if let Some(layer) = self.layers.iter().position(|d| !d.within) {
+            return Some(layer);
+        }
+        (0..self.layers.len()).max_by(|a, b| {
+            let added = |l: usize| {
+                self.layers[l].max_abs - l.checked_sub(1).map_or(0., |p| self.layers[p].max_abs)
+            };
+            added(*a).total_cmp(&added(*b))
+        })
+    }
+}
+
+/// Runs `tokens` through `mamba.step`, one at a time from empty caches, and
+/// through one `mamba.forward`, and compares their logits at every position —
+/// first for the whole model, then for the model cut after each layer, so a
+/// divergence can be traced to the layer it starts at.
+pub fn step_forward_parity(
+    mamba: &MambaVocabNet,
+    ssd_path: &MambaSsdPath,
+    tokens: &[usize],
+    tolerance: Tolerance,
+) -> anyhow::Result<Parity> {
+    let compare = |mamba: &MambaVocabNet| -> anyhow::Result<Divergence> {
+        let expected = forward_logits(mamba, tokens, ssd_path.clone())?;
+        let actual = step_logits(mamba, tokens)?;
+        let vocab = expected.len() / tokens.len();
+        Ok(Divergence::between(&expected, &actual, vocab, tolerance))
+    };
+    let logits = compare(mamba)?;
+    let layers = (1..=n_layers(mamba))
+        .map(|n| compare(&truncated(mamba, n)))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Parity { logits, layers })
+}
+
+/// NaN or infinite logits, and where in the model they started; see
+/// [locate_non_finite].
+#[derive(Clone, Debug, PartialEq)]
+pub struct NonFiniteLogits {
+    /// The call that produced them: `step` or `forward`.
+    pub path: &'static str,
+    /// How many logits are NaN or infinite.
+    pub count: usize,
+    /// The row (a batch row or a sequence position) of the first one.
+    pub position: usize,
+    /// Its token id.
+    pub id: usize,
+    pub value: Precision,
+    /// The first layer whose output is already non-finite; `None` when every
+    /// layer's output is finite, so the final norm or the head produced it.
+    pub layer: Option<usize>,
+    /// The module paths of the cache tensors holding a NaN or an infinity after
+    /// that layer ran.
+    pub cache_tensors: Vec<String>,
+}
+
+impl NonFiniteLogits {
+    /// Scans `logits`, `[rows, vocab]` row-major, as read back — before any
+    /// masking, which writes infinities of its own. Not localized yet.
+    pub fn find(logits: &[Precision], vocab: usize, path: &'static str) -> Option<Self> {
+        let first = logits.iter().position(|l| !l.is_finite())?;
+        Some(Self {
+            path,
+            count: logits.iter().filter(|l| !l.is_finite()).count(),
+            position: first / vocab,
+            id: first % vocab,
+            value: logits[first],
+            layer: None,
+            cache_tensors: Vec::new(),
+        })
+    }
+}
+
+impl std::fmt::Display for NonFiniteLogits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` produced {} non-finite logit(s), the first {} at row {}, id {}",
+            self.path, self.count, self.value, self.position, self.id
+        )?;
+        match self.layer {
+            Some(layer) => write!(f, "; layer {layer} is the first to output one")?,
+            None => write!(
+                f,
+                "; every layer's output is finite, so the final norm or the head produced it"
+            )?,
+        }
+        if !self.cache_tensors.is_empty() {
+            write!(f, " (non-finite cache tensors: {:?})", self.cache_tensors)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for NonFiniteLogits {}
+
+/// Fills in where `found` started: re-runs the same input through the model cut
+/// after each layer in turn, from the same `caches`, until one yields a
+/// non-finite logit, then names the cache tensors that run left non-finite.
+///
+/// `run` is the call that produced `found` — a `step` or a `forward` — made on
+/// the given model from the given caches, returning its logits as read back.
+pub fn locate_non_finite(
+    mut found: NonFiniteLogits,
+    mamba: &MambaVocabNet,
+    caches: Option<MambaCaches>,
+    run: impl Fn(&MambaVocabNet, Option<MambaCaches>) -> (Vec<Precision>, MambaCaches),
+) -> NonFiniteLogits {
+    for n in 1..=n_layers(mamba) {
+        let (logits, caches) = run(&truncated(mamba, n), caches.clone());
+        if logits.iter().any(|l| !l.is_finite()) {
+            found.layer = Some(n - 1);
+            found.cache_tensors = non_finite_tensors(&caches);
+            break;
+        }
+    }
+    found
+}
+
+/// The module paths of the tensors of `caches` holding a NaN or an infinity.
+fn non_finite_tensors(caches: &MambaCaches) -> Vec<String> {
+    caches
+        .collect(None, None, false)
+        .into_iter()
+        .filter(|snapshot| {
+            snapshot.to_data().is_ok_and(|data| {
+                data.convert::<Precision>()
+                    .into_vec::<Precision>()
+                    .is_ok_and(|values| values.iter().any(|v| !v.is_finite()))
+            })
+        })
+        .map(|snapshot| snapshot.full_path())
+        .collect()
+}
+
+/// The `[tokens, padded_vocab]` logits of a single `forward` over `tokens`.
+pub fn forward_logits(
+    mamba: &MambaVocabNet,
+    tokens: &[usize],
+    ssd_path: MambaSsdPath,
+) -> anyhow::Result<Vec<Precision>> {
+    anyhow::ensure!(!tokens.is_empty(), "no tokens to run");
+    let device = crate::device(mamba);
+    let input: Tensor<1, Int> = Tensor::from_data(tokens, &device);
+    let (logits, _caches) = mamba.forward(input.unsqueeze(), None, ssd_path, None);
+    read_back(logits.cast(PRECISION_FLOAT_D_TYPE).flatten(0, 2))
+}
+
+/// The `[tokens, padded_vocab]` logits of `step`ping through `tokens` one at a
+/// time, as [crate::MambaWrapper::step] does (without its masking).
+pub fn step_logits(mamba: &MambaVocabNet, tokens: &[usize]) -> anyhow::Result<Vec<Precision>> {
+    anyhow::ensure!(!tokens.is_empty(), "no tokens to run");
+    let device = crate::device(mamba);
+    let mut caches = None;
+    let mut rows = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let input = Tensor::from_data([*token], &device);
+        let (logits, new_caches) = mamba.step(input, caches, None);
+        caches = Some(new_caches);
+        rows.push(logits.cast(PRECISION_FLOAT_D_TYPE));
+    }
+    // one readback for the whole sequence
+    read_back(Tensor::cat(rows, 0).flatten(0, 1))
+}
+
+fn read_back(logits: Tensor<1>) -> anyhow::Result<Vec<Precision>> {
+    logits
+        .into_data()
+        .into_vec::<Precision>()
+        .map_err(|e| anyhow::anyhow!("failed to read the logits back: {e:?}"))
+}
+
+/// Builds `spec`'s topology with random weights and the head tied, as a loaded
+/// checkpoint would have it.
+pub fn random_model(spec: &ModelSpec, device: &Device) -> MambaVocabNet {
+    let mut mamba = spec.config().init(device);
+    tie_lm_head(&mut mamba, device);
+    mamba
+}
+
+/// Two-layer, 64-wide specs of every topology this binary builds, over
+/// [byte_tokenizer]'s 257 ids: small enough to run a [random_model] of each
+/// through a whole [crate::MambaWrapper]. Registered into a registry of their
+/// own, so each call leaks a fresh set.
+pub fn tiny_specs() -> Vec<&'static ModelSpec> {
+    let configs = [
+        #[cfg(feature = "mamba1")]
+        ("tiny-mamba1", serde_json::json!({"d_state": 8})),
+        #[cfg(feature = "mamba2")]
+        (
+            "tiny-mamba2",
+            serde_json::json!({"layer": "Mamba2", "d_state": 16, "headdim": 16}),
+        ),
+        #[cfg(feature = "mamba3")]
+        (
+            "tiny-mamba3",
+            serde_json::json!({"layer": "Mamba3", "d_state": 16, "headdim": 16}),
+        ),
+    ];
+    let entries: Vec<serde_json::Value> = configs
+        .into_iter()
+        .map(|(id, ssm_cfg)| {
+            serde_json::json!({
+                "id": id,
+                "repo_id": "random",
+                "config": {"d_model": 64, "n_layer": 2, "vocab_size": 257,
+                           "ssm_cfg": ssm_cfg, "pad_vocab_size_multiple": 16},
+            })
+        })
+        .collect();
+    crate::registry::Registry::empty()
+        .register_json(&serde_json::Value::from(entries).to_string())
+        .expect("the tiny specs build in every model feature")
+}
+
+/// A byte-level tokenizer without merges: a token per byte at ids `0..256` and
+/// `<|endoftext|>` at 256, the tokenizer of a [tiny_specs] model.
+pub fn byte_tokenizer() -> Tokenizer {
+    let vocab: serde_json::Map<String, serde_json::Value> = bytes_char()
+        .iter()
+        .enumerate()
+        .map(|(byte, c)| (c.to_string(), byte.into()))
+        .collect();
+    let json = serde_json::json!({
+        "added_tokens": [{"id": 256, "content": "<|endoftext|>", "special": true}],
+        "pre_tokenizer": {"type": "ByteLevel", "add_prefix_space": false},
+        "decoder": {"type": "ByteLevel"},
+        "model": {"type": "BPE", "vocab": vocab, "merges": []},
+    });
+    Tokenizer::from_bytes(json.to_string().as_bytes()).expect("a byte-level tokenizer.json")
+}
+
+/// `count` deterministic token ids under `vocab_size`: a plain LCG, so every run
+/// and every backend feeds the model the same ones.
+pub fn token_ids(count: usize, vocab_size: usize) -> Vec<usize> {
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    (0..count)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            ((state >> 33) % vocab_size as u64) as usize
+        })
+        .collect()
+}
+
+fn n_layers(mamba: &MambaVocabNet) -> usize {
+    match mamba {
+        #[cfg(feature = "mamba1")]
+        MambaVocabNet::Mamba1(m) => m.layers.real_layers.len(),
+        #[cfg(feature = "mamba2")]
+        MambaVocabNet::Mamba2(m) => m.layers.real_layers.len(),
+        #[cfg(feature = "mamba3")]
+        MambaVocabNet::Mamba3(m) => m.layers.real_layers.len(),
+    }
+}
+
+/// A copy of `mamba` keeping only its first `n` layers — the final norm and the
+/// head then read that layer's output.
+fn truncated(mamba: &MambaVocabNet, n: usize) -> MambaVocabNet {
+    let mut mamba = mamba.clone();
+    match &mut mamba {
+        #[cfg(feature = "mamba1")]
+        MambaVocabNet::Mamba1(m) => m.layers.real_layers.truncate(n),
+        #[cfg(feature = "mamba2")]
+        MambaVocabNet::Mamba2(m) => m.layers.real_layers.truncate(n),
+        #[cfg(feature = "mamba3")]
+        MambaVocabNet::Mamba3(m) => m.layers.real_layers.truncate(n),
+    }
+    mamba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divergence_finds_the_worst_position() {
+        let tolerance = Tolerance {
+            atol: 0.1,
+            rtol: 0.,
+        };
+        let expected = [1., 2., 3., 4., 5., 6.];
+        let actual = [1., 2.05, 3., 4., 5.5, 6.];
+        let divergence = Divergence::between(&expected, &actual, 2, tolerance);
+        assert_eq!(divergence.worst_position, 2);
+        assert!(!divergence.within);
+        assert!((divergence.max_abs - 0.5).abs() < 1e-6);
+        assert!((divergence.max_rel - 0.1).abs() < 1e-6);
+
+        let close = [1., 2.05, 3., 4., 5.05, 6.];
+        assert!(Divergence::between(&expected, &close, 2, tolerance).within);
+
+        let nan = [1., 2., f32::NAN, 4., 5., 6.];
+        assert!(!Divergence::between(&expected, &nan, 2, tolerance).within);
+    }
+
+    #[test]
+    fn non_finite_logits_are_found_by_row() {
+        let logits = [0., 1., 2., 3., f32::NAN, f32::INFINITY];
+        let found = NonFiniteLogits::find(&logits, 3, "step").unwrap();
+        assert_eq!((found.count, found.position, found.id), (2, 1, 1));
+        assert!(found.value.is_nan());
+        assert!(found.to_string().contains("final norm or the head"));
+
+        assert_eq!(NonFiniteLogits::find(&logits[..4], 2, "step"), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! The evaluation modes: `cargo run … -- eval-ppl <file> [window]` and
+//! `cargo run … -- eval-mc <file.jsonl>`.
+//!
+//! Each runs every compiled-in checkpoint (or the one `MAMBA_MODEL` names) over
+//! the same local data, so the checkpoints — and, with `BURN_DEVICE`, the
+//! backends — can be compared on a corpus of one's own.
+
+use super::{models, selected_models};
+use crate::eval::MultipleChoice;
+use log::info;
+
+/// Tokens per `forward` call, unless the second argument says otherwise.
+const DEFAULT_WINDOW: usize = 1024;
+
+/// `eval-ppl <file> [window]`: per-token negative log-likelihood, perplexity and
+/// bits-per-byte of a UTF-8 text file.
+pub fn ppl(args: &[String]) -> anyhow::Result<()> {
+    let Some(path) = args.first() else {
+        anyhow::bail!("usage: eval-ppl <file> [window]");
+    };
+    let window = match args.get(1) {
+        Some(window) => super::parse("window", window)?,
+        None => DEFAULT_WINDOW,
+    };
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("cannot read {path:?} as UTF-8 text: {e}"))?;
+
+    for spec in selected_models()? {
+        let mut models = models(spec)?;
+        let start = std::time::Instant::now();
+        let score = models.perplexity(&text, window)?;
+        info!(
+            "{} scored {} tokens in {:?}",
+            spec.display_name,
+            score.tokens,
+            start.elapsed()
+        );
+        println!(
+            "{}: nll/token {:.4}, perplexity {:.3}, bits/byte {:.4} ({} tokens, {} bytes)",
+            spec.id,
+            score.mean_nll(),
+            score.perplexity(),
+            score.bits_per_byte(),
+            score.tokens,
+            score.bytes
+        );
+    }
+    Ok(())
+}
+
+/// `eval-mc <file.jsonl>`: accuracy on multiple-choice questions, one per line
+/// in any layout [MultipleChoice::from_json] reads.
+///
+/// `acc` picks the choice of highest log-likelihood; `acc_norm`, of highest
+/// log-likelihood per UTF-8 byte, which doesn't favour short choices. A
+/// single-choice question (LAMBADA) is right, for both, when greedy decoding
+/// produces its answer.
+pub fn multiple_choice(args: &[String]) -> anyhow::Result<()> {
+    let Some(path) = args.first() else {
+        anyhow::bail!("usage: eval-mc <file.jsonl>");
+    };
+    let questions = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("cannot read {path:?} as UTF-8 text: {e}"))?
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            MultipleChoice::from_json(line).map_err(|e| anyhow::anyhow!("{path}:{}: {e}", i + 1))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(!questions.is_empty(), "no questions in {path:?}");
+
+    for spec in selected_models()? {
+        let mut models = models(spec)?;
+        let start = std::time::Instant::now();
+        let (mut acc, mut acc_norm) = (0usize, 0usize);
+        for question in &questions {
+            let pairs: Vec<(&str, &str)> = question
+                .choices
+                .iter()
+                .map(|choice| (question.context.as_str(), choice.as_str()))
+                .collect();
+            let scores = models.log_likelihoods(&pairs)?;
+            if let [score] = scores.as_slice() {
+                acc += score.is_greedy as usize;
+                acc_norm += score.is_greedy as usize;
+                continue;
+            }
+            let best = |key: &dyn Fn(usize) -> f64| {
+                (0..scores.len()).max_by(|a, b| key(*a).total_cmp(&key(*b)))
+            };
+            acc += (best(&|i| scores[i].log_prob) == Some(question.label)) as usize;
+            acc_norm += (best(&|i| scores[i].log_prob / question.choices[i].len() as f64)
+                == Some(question.label)) as usize;
+        }
+        info!(
+            "{} answered {} questions in {:?}",
+            spec.display_name,
+            questions.len(),
+            start.elapsed()
+        );
+        let total = questions.len() as f64;
+        println!(
+            "{}: acc {:.4}, acc_norm {:.4} ({} questions)",
+            spec.id,
+            acc as f64 / total,
+            acc_norm as f64 / total,
+            questions.len()
+        );
+    }
+    Ok(())
+}
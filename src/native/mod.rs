@@ -1,9 +1,21 @@
+pub mod eval;
+pub mod verify;
+
 #[allow(unused_imports)]
 use crate::Precision;
-use crate::hub::sync::Api;
+use crate::config_json::{self, ConfigJson};
+use crate::gguf::Gguf;
+use crate::hub::sync::{Api, ApiRepo};
 use crate::hub::{FilePath, Repo, RepoId, RepoType, RevisionPath};
+use crate::record_cache::RecordCache;
+use crate::sampling::device::DeviceSampler;
+use crate::sampling::{BatchLogitsProcessor, Sampling};
+use crate::stats::GenerationStats;
 use crate::tokenizer::Tokenizer;
-use crate::{Checkpoint, LogitsProcessorWrapper, MambaWrapper, ModelSpec, hf, load_mamba};
+use crate::{
+    Checkpoint, LogitsProcessorWrapper, MambaWrapper, ModelSpec, RuntimePrecision, SHARD_INDEX,
+    Topology, hf, load_mamba, registry, shard_files,
+};
 use burn::prelude::*;
 use log::info;
 
@@ -21,13 +33,13 @@ compile_error!(
      `mamba1`, `mamba2`, `mamba3-siso` and/or `mamba3-mimo`"
 );
 
-/// Picks the checkpoint to run out of everything compiled in: `MAMBA_MODEL`
-/// (a [ModelSpec::id]) when set, else the highest-priority one.
+/// Picks the checkpoint to run out of everything known: `MAMBA_MODEL` (a
+/// [ModelSpec::id]) when set, else the highest-priority one.
 pub fn select_model() -> anyhow::Result<&'static ModelSpec> {
     match std::env::var("MAMBA_MODEL") {
         Ok(id) => hf::by_id(id.trim()).ok_or_else(|| {
             anyhow::anyhow!(
-                "MAMBA_MODEL={id:?} is not compiled into this binary; available: {:?}",
+                "MAMBA_MODEL={id:?} is neither compiled in nor registered; available: {:?}",
                 hf::ids()
             )
         }),
@@ -36,15 +48,56 @@ pub fn select_model() -> anyhow::Result<&'static ModelSpec> {
     }
 }
 
+/// The checkpoints an evaluation mode runs: the one `MAMBA_MODEL` names, else
+/// every known one, so a single run compares them.
+pub fn selected_models() -> anyhow::Result<Vec<&'static ModelSpec>> {
+    if std::env::var("MAMBA_MODEL").is_ok() {
+        Ok(vec![select_model()?])
+    } else {
+        Ok(hf::models())
+    }
+}
+
 pub fn main() -> anyhow::Result<()> {
     let () = pretty_env_logger::formatted_timed_builder()
         .filter(Some("burn_mamba_example"), log::LevelFilter::Info)
         .init();
     info!("init");
 
+    // `MAMBA_REGISTRY=<file.json>` adds checkpoints to the compiled-in ones
+    if let Ok(path) = std::env::var("MAMBA_REGISTRY") {
+        let json = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow::anyhow!("MAMBA_REGISTRY={path:?}: {e}"))?;
+        let registered = registry::register_json(&json)?;
+        let ids: Vec<_> = registered.iter().map(|spec| spec.id).collect();
+        info!("registered {ids:?} from {path:?}");
+    }
+
+    // the first argument picks a mode; without one, the generation demo runs
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    match args.first().map(String::as_str) {
+        None => demo(),
+        Some("eval-ppl") => eval::ppl(&args[1..]),
+        Some("eval-mc") => eval::multiple_choice(&args[1..]),
+        Some("verify") => verify::step_forward(&args[1..]),
+        Some("verify-backends") => verify::backends(&args[1..]),
+        Some("verify-reference") => verify::reference(&args[1..]),
+        // one side of `verify-backends`, run in a child process
+        Some("backend-logits") => verify::backend_logits(&args[1..]),
+        Some(mode) => {
+            anyhow::bail!(
+                "unknown mode {mode:?}; available: eval-ppl, eval-mc, verify, verify-backends, \
+                 verify-reference"
+            )
+        }
+    }
+}
+
+/// Generates from "Mamba is the" in every run mode the environment enables.
+fn demo() -> anyhow::Result<()> {
     let model = select_model()?;
     info!(
-        "running {} (id {:?}); compiled-in models, by priority: {:?}",
+        "running {} (id {:?}); known models, by priority: {:?}",
         model.display_name,
         model.id,
         hf::ids()
@@ -53,70 +106,311 @@ pub fn main() -> anyhow::Result<()> {
 
     info!("running in sequential mode (inference-friendly)");
     let sample_len = 80;
-    let mut processor = LogitsProcessorWrapper::new(299792458, None, None, 1.1, 1024);
-    let (sample_len, start) = models.run_sequential("Mamba is the", sample_len, &mut processor)?;
+    let mut processor = logits_processor()?;
+    let stats = models.run_sequential("Mamba is the", sample_len, &mut processor)?;
     println!();
-    let elapsed = start.unwrap().elapsed().as_millis();
-    info!(
-        "mamba model generated {sample_len} tokens in {}ms ({} token/s)",
-        elapsed,
-        (sample_len * 1000) as f32 / elapsed as f32
-    );
+    report("sequential", &stats);
+
+    // `MAMBA_GUIDANCE_SCALE` opts into a third, guided, sequential run, steered
+    // away from `MAMBA_NEGATIVE_PROMPT` (empty: the unconditioned model).
+    if let Ok(scale) = std::env::var("MAMBA_GUIDANCE_SCALE") {
+        let scale: f32 = scale
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("MAMBA_GUIDANCE_SCALE={scale:?} is not a number: {e}"))?;
+        let negative_prompt = std::env::var("MAMBA_NEGATIVE_PROMPT").unwrap_or_default();
+        info!(
+            "running in sequential mode with classifier-free guidance \
+             (scale {scale}, negative prompt {negative_prompt:?})"
+        );
+        let sample_len = 80;
+        let mut processor = logits_processor()?;
+        let stats = models.run_sequential_cfg(
+            "Mamba is the",
+            &negative_prompt,
+            scale,
+            sample_len,
+            &mut processor,
+        )?;
+        println!();
+        report("guided", &stats);
+    }
+
+    // `MAMBA_N=n` opts into `n` sampled completions from a single prompt pass.
+    if let Ok(n) = std::env::var("MAMBA_N") {
+        let n: usize = parse("MAMBA_N", &n)?;
+        info!("sampling {n} completions from one prompt pass");
+        let sampling = Sampling::TopP {
+            p: 0.9,
+            temperature: 0.8,
+        };
+        let mut sampler = (0..n as u64).fold(BatchLogitsProcessor::default(), |sampler, row| {
+            sampler.with_row(299792458 + row, sampling.clone(), 1.1)
+        });
+        let start = std::time::Instant::now();
+        let completions = models.run_sequential_n("Mamba is the", 80, &mut sampler, 1024)?;
+        for (row, completion) in completions.iter().enumerate() {
+            println!("[{row}] Mamba is the{completion}");
+        }
+        info!(
+            "sampled {n} completions in {}ms",
+            start.elapsed().as_millis()
+        );
+    }
+
+    // `MAMBA_DEVICE_SAMPLING` opts into a sequential run sampled on the device.
+    if std::env::var("MAMBA_DEVICE_SAMPLING").is_ok() {
+        info!("running in sequential mode with device-side sampling");
+        let sample_len = 80;
+        let mut sampler =
+            DeviceSampler::new(299792458, Sampling::ArgMax).with_repeat_penalty(1.1, 1024);
+        let stats = models.run_sequential_on_device("Mamba is the", sample_len, &mut sampler)?;
+        println!();
+        report("device-sampled", &stats);
+    }
+
+    // `MAMBA_CONTRASTIVE=top_k,alpha` opts into a contrastive search run.
+    if let Ok(contrastive) = std::env::var("MAMBA_CONTRASTIVE") {
+        let (top_k, alpha) = contrastive.split_once(',').ok_or_else(|| {
+            anyhow::anyhow!("MAMBA_CONTRASTIVE={contrastive:?} is not `top_k,alpha`")
+        })?;
+        let top_k: usize = parse("MAMBA_CONTRASTIVE", top_k)?;
+        let alpha: f32 = parse("MAMBA_CONTRASTIVE", alpha)?;
+        info!("running in contrastive search mode (top_k {top_k}, alpha {alpha})");
+        let sample_len = 80;
+        let stats = models.run_contrastive("Mamba is the", sample_len, top_k, alpha)?;
+        println!();
+        report("contrastive", &stats);
+    }
 
     info!("running in parallel mode (training-friendly)");
     let sample_len = 20;
-    let mut processor = LogitsProcessorWrapper::new(299792458, None, None, 1.1, 1024);
-    let (sample_len, start) = models.run_parallel("Mamba is the", sample_len, &mut processor)?;
+    let mut processor = logits_processor()?;
+    let stats = models.run_parallel("Mamba is the", sample_len, &mut processor)?;
     println!();
-    let elapsed = start.unwrap().elapsed().as_millis();
-    let total_sample_len = (1 + sample_len) * sample_len / 2;
-    info!(
-        "mamba model generated {total_sample_len} total tokens in {}ms ({} token/s)",
-        elapsed,
-        (total_sample_len * 1000) as f32 / elapsed as f32
-    );
+    // each token is a `forward` over everything before it, so its latency grows
+    report("parallel", &stats);
 
     info!("finished (success)");
     Ok(())
 }
 
+/// Logs how a `run` went, and with `MAMBA_STATS_JSON` also prints it as one
+/// JSON line, tagged with the run's name.
+fn report(run: &str, stats: &GenerationStats) {
+    info!("{run} run: {stats}");
+    if std::env::var("MAMBA_STATS_JSON").is_ok() {
+        println!(r#"{{"run":"{run}","stats":{}}}"#, stats.to_json());
+    }
+}
+
+/// The sampler every run uses, plus the optional anti-repetition knobs:
+/// `MAMBA_NO_REPEAT_NGRAM` (an n-gram size), `MAMBA_DRY_MULTIPLIER` and
+/// `MAMBA_LOOP_STOP` (`max_period,min_repeats`, stopping a looping generation).
+pub fn logits_processor() -> anyhow::Result<LogitsProcessorWrapper> {
+    let mut processor = LogitsProcessorWrapper::new(299792458, None, None, 1.1, 1024);
+    if let Ok(n) = std::env::var("MAMBA_NO_REPEAT_NGRAM") {
+        processor = processor.with_no_repeat_ngram(parse("MAMBA_NO_REPEAT_NGRAM", &n)?);
+    }
+    if let Ok(multiplier) = std::env::var("MAMBA_DRY_MULTIPLIER") {
+        let multiplier = parse("MAMBA_DRY_MULTIPLIER", &multiplier)?;
+        processor = processor.with_dry(crate::sampling::Dry::new(multiplier));
+    }
+    if let Ok(loop_stop) = std::env::var("MAMBA_LOOP_STOP") {
+        let (max_period, min_repeats) = loop_stop.split_once(',').ok_or_else(|| {
+            anyhow::anyhow!("MAMBA_LOOP_STOP={loop_stop:?} is not `max_period,min_repeats`")
+        })?;
+        processor = processor.with_loop_stop(
+            parse("MAMBA_LOOP_STOP", max_period)?,
+            parse("MAMBA_LOOP_STOP", min_repeats)?,
+        );
+    }
+    Ok(processor)
+}
+
+/// Parses the `value` of the environment variable `name`.
+fn parse<T: std::str::FromStr>(name: &str, value: &str) -> anyhow::Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| anyhow::anyhow!("{name}={value:?} is not valid: {e}"))
+}
+
+/// A checkpoint file to read from disk rather than the hub: the path in the
+/// environment variable `var`, else the first of `file_names` that
+/// `MAMBA_LOCAL_DIR` has. A path given explicitly must exist.
+fn local_file(var: &str, file_names: &[&str]) -> anyhow::Result<Option<std::path::PathBuf>> {
+    if let Ok(path) = std::env::var(var) {
+        let path = std::path::PathBuf::from(path);
+        anyhow::ensure!(path.is_file(), "{var}={path:?} is not a file");
+        return Ok(Some(path));
+    }
+    let Ok(dir) = std::env::var("MAMBA_LOCAL_DIR") else {
+        return Ok(None);
+    };
+    let found = file_names
+        .iter()
+        .map(|file_name| std::path::Path::new(&dir).join(file_name))
+        .find(|path| path.is_file());
+    if found.is_none() {
+        info!("{file_names:?} not in MAMBA_LOCAL_DIR={dir:?}; looking it up on the hub");
+    }
+    Ok(found)
+}
+
+/// The checkpoint's weights, from disk (see [local_file]) or from `repo`:
+/// either one safetensors file, the shards a `model.safetensors.index.json`
+/// lists, or a llama.cpp `.gguf`. A repo published in shards has no
+/// `model.safetensors`, so asking for one falls back to the index.
+fn weights(model: &ModelSpec, repo: &ApiRepo) -> anyhow::Result<Checkpoint> {
+    let is_index = |file: &str| file.ends_with(".index.json");
+    let is_gguf = |path: &std::path::Path| path.extension().is_some_and(|ext| ext == "gguf");
+    let model_file = model.file_path_model_safetensors;
+    let fallback = (model_file == "model.safetensors").then_some(SHARD_INDEX);
+
+    let candidates: Vec<&str> = std::iter::once(model_file).chain(fallback).collect();
+    if let Some(path) = local_file("MAMBA_WEIGHTS", &candidates)? {
+        info!("mamba weights path: {path:?}");
+        return match is_index(&path.to_string_lossy()) {
+            true => Checkpoint::from_index(&path),
+            false if is_gguf(&path) => Ok(Checkpoint::Gguf(Gguf::from_file(&path)?)),
+            false => Ok(Checkpoint::File(path)),
+        };
+    }
+
+    let (file, path) = match (repo.get(&FilePath(model_file.into())), fallback) {
+        (Ok(path), _) => (model_file, path),
+        (Err(error), Some(index)) => {
+            info!("{model_file} is unavailable ({error}); trying {index}");
+            let index_path = repo.get(&FilePath(index.into())).map_err(|_| {
+                anyhow::anyhow!(
+                    "{error}; {} has neither {model_file} nor {index} at this revision, \
+                     and MAMBA_REVISION selects another (such as the refs/pr/N of a \
+                     safetensors conversion)",
+                    model.repo_id
+                )
+            })?;
+            (index, index_path)
+        }
+        (Err(error), None) => return Err(error.into()),
+    };
+    info!("mamba {file} path: {path:?}");
+    if is_gguf(std::path::Path::new(file)) {
+        return Ok(Checkpoint::Gguf(Gguf::from_file(&path)?));
+    }
+    if !is_index(file) {
+        return Ok(Checkpoint::File(path));
+    }
+    // the hub cache names blobs by etag, so each shard is fetched by name
+    // rather than looked up next to the index
+    let shards = shard_files(&std::fs::read(&path)?)?;
+    info!("{file} lists {} shards", shards.len());
+    let shards = shards
+        .into_iter()
+        .map(|shard| Ok(Checkpoint::File(repo.get(&FilePath(shard))?)))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Checkpoint::Sharded(shards))
+}
+
+/// `model`, but shaped by the GGUF's own metadata rather than by its spec: a
+/// GGUF conversion pads the vocabulary itself, and may not be the same size of
+/// model at all. Leaked, like a [registry]-registered spec.
+fn gguf_spec(model: &ModelSpec, gguf: &Gguf) -> anyhow::Result<&'static ModelSpec> {
+    let config = gguf.config_json()?;
+    info!(
+        "GGUF topology: {:?}, d_model {}, {} layers, vocabulary {}",
+        config.layer()?,
+        config.d_model,
+        config.n_layer,
+        config.vocab_size
+    );
+    let topology = Topology::ConfigJson(Box::leak(Box::new(config)));
+    Ok(Box::leak(Box::new(ModelSpec { topology, ..*model })))
+}
+
+/// The default device, with the float of the [RuntimePrecision]
+/// `MAMBA_PRECISION` picks and i32 installed as its defaults.
+///
+/// `configure` writes process-global per-device defaults and refuses a second
+/// call, so only the first model an evaluation mode builds may configure it;
+/// every later `Device::default()` still sees those defaults.
+pub fn device() -> Device {
+    use std::sync::Once;
+    static CONFIGURED: Once = Once::new();
+
+    let mut device: Device = Default::default();
+    CONFIGURED.call_once(|| {
+        let precision = RuntimePrecision::from_env().unwrap_or_else(|e| panic!("{e}"));
+        device
+            .configure(precision.device_dtypes())
+            .expect("Failed to install the float/i32 device defaults");
+    });
+    device
+}
+
 /// Downloads (or reuses) the tokenizer and the checkpoint, then builds the model.
 ///
 /// Takes the checkpoint to build, so a binary carrying several can build any of
-/// them (see [hf::MODELS]).
+/// them (see [hf::models]). Any of its three files may come from disk instead;
+/// see [local_file]. With `HF_HUB_OFFLINE=1` the rest only come from the hub
+/// cache, and a miss is an error rather than a download.
 pub fn models(model: &'static ModelSpec) -> anyhow::Result<MambaWrapper> {
     let start = std::time::Instant::now();
 
     let api = Api::new()?;
     let tokenizer_file = model.file_path_tokenizer_json;
-    let tokenizer_filename = api
-        .model(RepoId(model.tokenizer_repo_id.into()))
-        .get(&FilePath(tokenizer_file.into()))?;
+    let tokenizer_filename = match local_file("MAMBA_TOKENIZER", &[tokenizer_file])? {
+        Some(path) => path,
+        None => api
+            .model(RepoId(model.tokenizer_repo_id.into()))
+            .get(&FilePath(tokenizer_file.into()))?,
+    };
     info!("tokenizer {tokenizer_file} path: {tokenizer_filename:?}");
 
+    // `MAMBA_REVISION` points at another safetensors revision of the same repo
+    let revision = std::env::var("MAMBA_REVISION").unwrap_or(model.revision_path.into());
     let repo = api.repo(Repo::with_revision(
         RepoId(model.repo_id.into()),
         RepoType::Model,
-        RevisionPath(model.revision_path.into()),
+        RevisionPath(revision),
     ));
-    let model_file = model.file_path_model_safetensors;
-    let mamba_filename = repo.get(&FilePath(model_file.into()))?;
-    info!("mamba {model_file} path: {mamba_filename:?}");
+    let config_file = model.file_path_config_json;
+    let config_filename = match local_file("MAMBA_CONFIG", &[config_file])? {
+        Some(path) => path,
+        None => repo.get(&FilePath(config_file.into()))?,
+    };
+    info!("mamba {config_file} path: {config_filename:?}");
+    let checkpoint = weights(model, &repo)?;
     info!("retrieved the files in {:?}", start.elapsed());
 
     let tokenizer = Tokenizer::from_file(tokenizer_filename)?;
+    let config = ConfigJson::from_bytes(&std::fs::read(config_filename)?)?;
+    config_json::check_spec(model, &config)?;
+    let model = match &checkpoint {
+        Checkpoint::Gguf(gguf) => gguf_spec(model, gguf)?,
+        _ => model,
+    };
 
-    let mut device: Device = Default::default();
-    {
-        device
-            .configure((crate::PRECISION_FLOAT_D_TYPE, crate::PRECISION_INT_D_TYPE))
-            .expect("Failed to install fp32/i32 device defaults");
-    }
-
+    // `MAMBA_PRECISION` picks the float the model runs in
+    let precision = RuntimePrecision::from_env()?;
+    let device = device();
     let start = std::time::Instant::now();
     info!("started loading the model");
-    let mamba = load_mamba(Checkpoint::File(mamba_filename), (model.config)(), &device)?;
+    // `MAMBA_RECORD_CACHE` keeps the imported model in Burn's record format
+    let mamba = match RecordCache::from_env() {
+        Some(cache) => cache.load(checkpoint, model.config(), precision, &device)?,
+        None => load_mamba(checkpoint, model.config(), precision, &device)?,
+    };
     info!("loaded the model in {:?}", start.elapsed());
 
-    Ok(MambaWrapper::new(model, tokenizer, mamba))
+    // `MAMBA_CHECK_FINITE` opts into tracing NaN/Inf logits to their layer
+    let check_finite = std::env::var("MAMBA_CHECK_FINITE").is_ok();
+    // `MAMBA_MASK_UNRENDERABLE` opts into never sampling an id the tokenizer lacks
+    let mask_unrenderable = std::env::var("MAMBA_MASK_UNRENDERABLE").is_ok();
+    Ok(MambaWrapper::new(model, tokenizer, mamba)?
+        .with_finite_check(check_finite)
+        .with_unrenderable_masked(mask_unrenderable))
 }
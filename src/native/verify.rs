@@ -0,0 +1,412 @@
+//! The verification modes:
+//!
+//! - `cargo run … -- verify [random|pretrained] [tokens] [atol] [rtol]` checks
+//!   that `step` and `forward` agree, on the backend `BURN_DEVICE` picks — the
+//!   check the demo's two run modes only show;
+//! - `cargo run … -- verify-backends <device> <device> [random|pretrained]
+//!   [tokens]` checks that two backends agree on both paths;
+//! - `cargo run … -- verify-reference <logits> [tokens] [atol] [rtol]` checks
+//!   the loaded checkpoint against logits another implementation recorded.
+//!
+//! The first two run every compiled-in checkpoint (or the one `MAMBA_MODEL`
+//! names), the last the checkpoint the reference was recorded with; all fail
+//! the process when anything diverges beyond the tolerance.
+
+use super::{device, models, selected_models};
+use crate::ModelSpec;
+use crate::reference::{ReferenceLogits, parse_tokens};
+use crate::verify::{
+    Divergence, Tolerance, forward_logits, random_model, step_forward_parity, step_logits,
+    token_ids,
+};
+use log::info;
+use std::path::{Path, PathBuf};
+
+/// Tokens run through both paths, unless the second argument says otherwise:
+/// enough to span several SSD chunks' worth of state hand-offs.
+const DEFAULT_TOKENS: usize = 64;
+
+/// `verify [random|pretrained] [tokens] [atol] [rtol]`: compares the logits of
+/// `step` and `forward` position by position, on random weights unless told
+/// otherwise, so it runs without a download.
+pub fn step_forward(args: &[String]) -> anyhow::Result<()> {
+    let pretrained = match args.first().map(String::as_str) {
+        None | Some("random") => false,
+        Some("pretrained") => true,
+        Some(weights) => anyhow::bail!(
+            "usage: verify [random|pretrained] [tokens] [atol] [rtol]; \
+             unknown weights {weights:?}"
+        ),
+    };
+    let count = match args.get(1) {
+        Some(count) => super::parse("tokens", count)?,
+        None => DEFAULT_TOKENS,
+    };
+    let default = Tolerance::default();
+    let tolerance = Tolerance {
+        atol: args
+            .get(2)
+            .map_or(Ok(default.atol), |atol| super::parse("atol", atol))?,
+        rtol: args
+            .get(3)
+            .map_or(Ok(default.rtol), |rtol| super::parse("rtol", rtol))?,
+    };
+
+    let mut failed = Vec::new();
+    for spec in selected_models()? {
+        let mamba = if pretrained {
+            models(spec)?.mamba
+        } else {
+            random_model(spec, &device())
+        };
+        let tokens = token_ids(count, crate::vocab_size(&spec.config()));
+
+        let start = std::time::Instant::now();
+        let parity = step_forward_parity(&mamba, &spec.ssd_path(), &tokens, tolerance)?;
+        info!(
+            "{} compared {count} tokens over {} layer prefixes in {:?}",
+            spec.display_name,
+            parity.layers.len(),
+            start.elapsed()
+        );
+
+        for (layer, divergence) in parity.layers.iter().enumerate() {
+            info!(
+                "{}: through layer {layer}: max abs {:.3e}, max rel {:.3e}, worst position {}",
+                spec.id, divergence.max_abs, divergence.max_rel, divergence.worst_position
+            );
+        }
+        let logits = parity.logits;
+        println!(
+            "{}: {} (atol {:e}, rtol {:e}): max abs {:.3e}, max rel {:.3e}, \
+             worst position {}, worst layer {}",
+            spec.id,
+            if logits.within { "ok" } else { "DIVERGED" },
+            tolerance.atol,
+            tolerance.rtol,
+            logits.max_abs,
+            logits.max_rel,
+            logits.worst_position,
+            parity
+                .worst_layer()
+                .map_or_else(|| "-".into(), |layer| layer.to_string()),
+        );
+        if !logits.within {
+            failed.push(spec.id);
+        }
+    }
+    anyhow::ensure!(
+        failed.is_empty(),
+        "step and forward diverged beyond the tolerance on {failed:?}"
+    );
+    Ok(())
+}
+
+/// `verify-backends <device> <device> [random|pretrained] [tokens]`: runs the
+/// same weights and tokens on two backends, and compares their logits position
+/// by position, along with the token greedy decoding picks from each.
+///
+/// A backend is a `BURN_DEVICE` value, and `BURN_DEVICE` is read once per
+/// process, so each side runs in a child process of this binary (the hidden
+/// `backend-logits` mode). With random weights, the first side saves the ones it
+/// drew for the second to load.
+pub fn backends(args: &[String]) -> anyhow::Result<()> {
+    const USAGE: &str = "usage: verify-backends <device> <device> [random|pretrained] [tokens]";
+    let (Some(first), Some(second)) = (args.first(), args.get(1)) else {
+        anyhow::bail!(USAGE);
+    };
+    let weights = args.get(2).map_or("random", String::as_str);
+    anyhow::ensure!(
+        matches!(weights, "random" | "pretrained"),
+        "{USAGE}; unknown weights {weights:?}"
+    );
+    let count: usize = match args.get(3) {
+        Some(count) => super::parse("tokens", count)?,
+        None => DEFAULT_TOKENS,
+    };
+    let tolerance = Tolerance::default();
+
+    let dir = std::env::temp_dir().join(format!("burn-mamba-verify-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    let result = (|| {
+        let mut failed = Vec::new();
+        for spec in selected_models()? {
+            // random weights are drawn on the first side, and loaded on the second
+            let saved = dir.join(format!("{}.safetensors", spec.id));
+            let (first_weights, second_weights) = match weights {
+                "random" => (
+                    format!("save:{}", saved.display()),
+                    format!("load:{}", saved.display()),
+                ),
+                _ => (weights.to_string(), weights.to_string()),
+            };
+            let first_logits = dir.join(format!("{}-first", spec.id));
+            let second_logits = dir.join(format!("{}-second", spec.id));
+            run_child(spec, first, &first_weights, count, &first_logits)?;
+            run_child(spec, second, &second_weights, count, &second_logits)?;
+
+            let vocab = crate::vocab_size(&spec.config());
+            let padded_vocab = crate::padded_vocab_size(&spec.config());
+            let mut diverged = false;
+            for path in ["forward", "step"] {
+                let expected = read_logits(&logits_file(&first_logits, path))?;
+                let actual = read_logits(&logits_file(&second_logits, path))?;
+                anyhow::ensure!(
+                    expected.len() == count * padded_vocab && actual.len() == expected.len(),
+                    "{}: the {path} logits have unexpected lengths",
+                    spec.id
+                );
+                for (position, (expected, actual)) in expected
+                    .chunks_exact(padded_vocab)
+                    .zip(actual.chunks_exact(padded_vocab))
+                    .enumerate()
+                {
+                    let divergence = Divergence::between(expected, actual, padded_vocab, tolerance);
+                    let (expected, actual) = (greedy(&expected[..vocab]), greedy(&actual[..vocab]));
+                    info!(
+                        "{} {path} position {position}: max abs {:.3e}, max rel {:.3e}, \
+                         greedy {expected} vs {actual}",
+                        spec.id, divergence.max_abs, divergence.max_rel
+                    );
+                }
+                let agreeing = expected
+                    .chunks_exact(padded_vocab)
+                    .zip(actual.chunks_exact(padded_vocab))
+                    .filter(|(e, a)| greedy(&e[..vocab]) == greedy(&a[..vocab]))
+                    .count();
+                let divergence = Divergence::between(&expected, &actual, padded_vocab, tolerance);
+                println!(
+                    "{} {path}: {first} vs {second}: {} (atol {:e}, rtol {:e}): \
+                     max abs {:.3e}, max rel {:.3e}, worst position {}, \
+                     greedy agreement {agreeing}/{count}",
+                    spec.id,
+                    if divergence.within { "ok" } else { "DIVERGED" },
+                    tolerance.atol,
+                    tolerance.rtol,
+                    divergence.max_abs,
+                    divergence.max_rel,
+                    divergence.worst_position,
+                );
+                diverged |= !divergence.within;
+            }
+            if diverged {
+                failed.push(spec.id);
+            }
+        }
+        anyhow::ensure!(
+            failed.is_empty(),
+            "{first} and {second} diverged beyond the tolerance on {failed:?}"
+        );
+        Ok(())
+    })();
+    let _ = std::fs::remove_dir_all(&dir);
+    result
+}
+
+/// `backend-logits <random|pretrained|save:<path>|load:<path>> <tokens> <out>`:
+/// one side of [backends], on this process's `BURN_DEVICE`. Writes the
+/// `forward` and `step` logits, `[tokens, padded_vocab]` as little-endian f32,
+/// to `<out>.forward` and `<out>.step`.
+pub fn backend_logits(args: &[String]) -> anyhow::Result<()> {
+    let [weights, count, out] = args else {
+        anyhow::bail!("usage: backend-logits <weights> <tokens> <out>");
+    };
+    let spec = super::select_model()?;
+    let count: usize = super::parse("tokens", count)?;
+    let mamba = match weights.split_once(':') {
+        None if weights == "pretrained" => models(spec)?.mamba,
+        None if weights == "random" => random_model(spec, &device()),
+        Some(("save", path)) => {
+            let mamba = random_model(spec, &device());
+            crate::save_weights(&mamba, Path::new(path))?;
+            mamba
+        }
+        Some(("load", path)) => crate::load_weights(Path::new(path), spec.config(), &device())?,
+        _ => anyhow::bail!("unknown weights {weights:?}"),
+    };
+    let tokens = token_ids(count, crate::vocab_size(&spec.config()));
+
+    let out = PathBuf::from(out);
+    let forward = forward_logits(&mamba, &tokens, spec.ssd_path())?;
+    write_logits(&logits_file(&out, "forward"), &forward)?;
+    let step = step_logits(&mamba, &tokens)?;
+    write_logits(&logits_file(&out, "step"), &step)
+}
+
+/// `verify-reference <logits> [tokens] [atol] [rtol]`: runs the tokens a
+/// reference was recorded for through the loaded checkpoint — `MAMBA_MODEL`'s,
+/// or the preferred one — and compares both paths' logits to it, position by
+/// position.
+///
+/// `<logits>` is a safetensors file holding `input_ids` and `logits`, or a
+/// `.npy` of logits alone, whose `[tokens]` then come as a `.npy` file or a
+/// comma-separated list. Only the real vocabulary is compared: what a padding
+/// column holds is arbitrary on either side.
+pub fn reference(args: &[String]) -> anyhow::Result<()> {
+    const USAGE: &str =
+        "usage: verify-reference <logits.safetensors|logits.npy> [tokens] [atol] [rtol]";
+    let Some(path) = args.first() else {
+        anyhow::bail!(USAGE);
+    };
+    let bytes = std::fs::read(path).map_err(|e| anyhow::anyhow!("cannot read {path:?}: {e}"))?;
+    let reference = if path.ends_with(".npy") {
+        let tokens = args
+            .get(1)
+            .ok_or_else(|| anyhow::anyhow!("{USAGE}; a .npy reference needs its tokens"))?;
+        let tokens = match std::fs::read(tokens) {
+            Ok(bytes) => parse_tokens(&bytes)?,
+            Err(_) => parse_tokens(tokens.as_bytes())?,
+        };
+        ReferenceLogits::from_npy(&bytes, tokens)?
+    } else {
+        ReferenceLogits::from_safetensors(&bytes)?
+    };
+    // a safetensors reference carries its tokens, so the tolerances come first
+    let skipped = if path.ends_with(".npy") { 2 } else { 1 };
+    let tolerance_args = args.get(skipped..).unwrap_or_default();
+    let default = Tolerance::default();
+    let tolerance = Tolerance {
+        atol: tolerance_args
+            .first()
+            .map_or(Ok(default.atol), |atol| super::parse("atol", atol))?,
+        rtol: tolerance_args
+            .get(1)
+            .map_or(Ok(default.rtol), |rtol| super::parse("rtol", rtol))?,
+    };
+
+    let spec = super::select_model()?;
+    let vocab = crate::vocab_size(&spec.config());
+    let padded_vocab = crate::padded_vocab_size(&spec.config());
+    anyhow::ensure!(
+        reference.vocab >= vocab,
+        "the reference has {} logits per position, but {} has a vocabulary of {vocab}",
+        reference.vocab,
+        spec.id
+    );
+    anyhow::ensure!(
+        reference.tokens.iter().all(|t| *t < vocab),
+        "the reference tokens are not all in {}'s vocabulary",
+        spec.id
+    );
+    let expected = columns(&reference.logits, reference.vocab, vocab);
+    let mamba = models(spec)?.mamba;
+
+    let mut diverged = false;
+    for path in ["forward", "step"] {
+        let actual = match path {
+            "forward" => forward_logits(&mamba, &reference.tokens, spec.ssd_path())?,
+            _ => step_logits(&mamba, &reference.tokens)?,
+        };
+        let actual = columns(&actual, padded_vocab, vocab);
+        for (position, (expected, actual)) in expected
+            .chunks_exact(vocab)
+            .zip(actual.chunks_exact(vocab))
+            .enumerate()
+        {
+            let divergence = Divergence::between(expected, actual, vocab, tolerance);
+            info!(
+                "{} {path} position {position}: max abs {:.3e}, max rel {:.3e}{}",
+                spec.id,
+                divergence.max_abs,
+                divergence.max_rel,
+                if divergence.within { "" } else { " (diverged)" }
+            );
+        }
+        let divergence = Divergence::between(&expected, &actual, vocab, tolerance);
+        println!(
+            "{} {path} vs the reference: {} (atol {:e}, rtol {:e}): \
+             max abs {:.3e}, max rel {:.3e}, worst position {} of {}",
+            spec.id,
+            if divergence.within { "ok" } else { "DIVERGED" },
+            tolerance.atol,
+            tolerance.rtol,
+            divergence.max_abs,
+            divergence.max_rel,
+            divergence.worst_position,
+            reference.tokens.len()
+        );
+        diverged |= !divergence.within;
+    }
+    anyhow::ensure!(
+        !diverged,
+        "{} diverged from the reference beyond the tolerance",
+        spec.id
+    );
+    Ok(())
+}
+
+/// The first `keep` columns of the `[rows, width]` row-major `logits`.
+fn columns(logits: &[f32], width: usize, keep: usize) -> Vec<f32> {
+    logits
+        .chunks_exact(width)
+        .flat_map(|row| &row[..keep])
+        .copied()
+        .collect()
+}
+
+/// Runs [backend_logits] for `spec` on `device`, in a child process.
+fn run_child(
+    spec: &ModelSpec,
+    device: &str,
+    weights: &str,
+    count: usize,
+    out: &Path,
+) -> anyhow::Result<()> {
+    info!("computing {} logits on {device}", spec.id);
+    let status = std::process::Command::new(std::env::current_exe()?)
+        .args(["backend-logits", weights, &count.to_string()])
+        .arg(out)
+        .env("BURN_DEVICE", device)
+        .env("MAMBA_MODEL", spec.id)
+        .status()?;
+    anyhow::ensure!(
+        status.success(),
+        "computing the {} logits on {device} failed ({status})",
+        spec.id
+    );
+    Ok(())
+}
+
+/// `<out>.<path>`, named in full: `with_extension` would replace whatever
+/// follows the last dot of `out`, and model ids such as `mamba1-1.4b` have one.
+fn logits_file(out: &Path, path: &str) -> PathBuf {
+    let mut name = out.as_os_str().to_owned();
+    name.push(format!(".{path}"));
+    PathBuf::from(name)
+}
+
+fn write_logits(path: &Path, logits: &[f32]) -> anyhow::Result<()> {
+    let bytes: Vec<u8> = logits.iter().flat_map(|l| l.to_le_bytes()).collect();
+    std::fs::write(path, bytes).map_err(|e| anyhow::anyhow!("cannot write {path:?}: {e}"))
+}
+
+fn read_logits(path: &Path) -> anyhow::Result<Vec<f32>> {
+    let bytes = std::fs::read(path).map_err(|e| anyhow::anyhow!("cannot read {path:?}: {e}"))?;
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+/// The id of the largest of `logits`.
+fn greedy(logits: &[f32]) -> usize {
+    logits
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(0, |(id, _)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logits_files_keep_dotted_model_ids() {
+        let dir = Path::new("verify");
+        let first = logits_file(&dir.join("mamba1-1.4b-first"), "forward");
+        let second = logits_file(&dir.join("mamba1-1.4b-second"), "forward");
+        assert_eq!(first, dir.join("mamba1-1.4b-first.forward"));
+        assert_ne!(first, second);
+    }
+}
@@ -1,14 +1,19 @@
+use crate::config_json::{self, ConfigJson};
 use crate::hub::wasm::Api;
 use crate::hub::{FilePath, Repo, RepoId, RepoType, RevisionPath};
+use crate::stats::{FinishReason, GenerationTimer};
 use crate::tokenizer::Tokenizer;
-use crate::{Checkpoint, LogitsProcessorWrapper, MambaWrapper, ModelSpec, hf, load_mamba};
+use crate::{
+    Checkpoint, LogitsProcessorWrapper, MambaWrapper, ModelSpec, RuntimePrecision, hf, load_mamba,
+    shard_files,
+};
 use burn::prelude::*;
 
 pub async fn run() -> anyhow::Result<()> {
     let model = hf::preferred()
         .ok_or_else(|| anyhow::anyhow!("no checkpoint feature is enabled in this build"))?;
     log::info!(
-        "running {} (id {:?}); compiled-in models, by priority: {:?}",
+        "running {} (id {:?}); known models, by priority: {:?}",
         model.display_name,
         model.id,
         hf::ids()
@@ -20,14 +25,17 @@ pub async fn run() -> anyhow::Result<()> {
     let mut output = String::new();
 
     log::info!("Running mamba model");
-    let mut timing = web_time::Instant::now();
+    let timing = web_time::Instant::now();
     let mut last_elapsed = timing.elapsed().as_millis();
     let mut processor = LogitsProcessorWrapper::new(299792458, None, None, 1.1, 1024);
 
     // sequential run
     let mut i: usize = 0;
-    {
+    let stats = {
         let (mut tokens, eos_token) = models.reset_prompt(prompt)?;
+        let prompt_len = tokens.len();
+        let mut timer = GenerationTimer::start(prompt_len);
+        let mut finish_reason = FinishReason::Length;
 
         // gets first token (as if it were an implicit output)
         if let Some(t) = tokens.first() {
@@ -42,9 +50,8 @@ pub async fn run() -> anyhow::Result<()> {
         while i < sample_len {
             let (next_logits, next_caches) = models.step(tokens[i], Some(caches))?;
             caches = next_caches;
-            if i == 0 {
-                // reset after the first token gets generated to get a better approximation
-                timing = web_time::Instant::now();
+            if i + 1 >= prompt_len {
+                timer.prefilled();
             }
 
             let this_elapsed = timing.elapsed().as_millis();
@@ -55,8 +62,16 @@ pub async fn run() -> anyhow::Result<()> {
 
             let next_token = processor.add_logits(i, &mut tokens, next_logits)?;
             if next_token == eos_token {
+                finish_reason = FinishReason::Eos;
+                break;
+            }
+            if processor.looped().is_some() {
+                finish_reason = FinishReason::Loop;
                 break;
             }
+            if i + 1 >= prompt_len {
+                timer.token();
+            }
 
             // if the token has some valid representation, print it
             if let Some(t) = models.tokenizer.next_token(next_token as u32) {
@@ -68,14 +83,9 @@ pub async fn run() -> anyhow::Result<()> {
         if let Some(rest) = models.tokenizer.decode_rest() {
             output += &rest;
         }
-    }
-    let elapsed = timing.elapsed().as_millis();
-    log::info!(
-        "mamba model generated {} tokens in {}ms ({} token/s)",
-        i,
-        elapsed,
-        ((i - 1) * 1000) as f32 / elapsed as f32
-    );
+        timer.finish(finish_reason)
+    };
+    log::info!("sequential run: {stats}");
     log::info!("{output}");
 
     Ok(())
@@ -85,7 +95,7 @@ pub async fn run() -> anyhow::Result<()> {
 /// then builds the model.
 ///
 /// Takes the checkpoint to build, so a bundle carrying several can build any of
-/// them (see [hf::MODELS]).
+/// them (see [hf::models]).
 pub async fn models(model: &'static ModelSpec) -> anyhow::Result<MambaWrapper> {
     let api = Api::new().await?;
 
@@ -106,28 +116,45 @@ pub async fn models(model: &'static ModelSpec) -> anyhow::Result<MambaWrapper> {
         tokenizer
     };
 
+    // the page has no environment to pick another precision from
+    let precision = RuntimePrecision::default();
     let mut device: Device = Default::default();
     {
         device
-            .configure((crate::PRECISION_FLOAT_D_TYPE, crate::PRECISION_INT_D_TYPE))
+            .configure(precision.device_dtypes())
             .expect("Failed to install fp32/i32 device defaults");
     }
 
+    let repo = api.repo(Repo::with_revision(
+        RepoId(model.repo_id.into()),
+        RepoType::Model,
+        RevisionPath(model.revision_path.into()),
+    ));
+    {
+        let bytes = repo
+            .get_bytes(&FilePath(model.file_path_config_json.into()))
+            .await?;
+        config_json::check_spec(model, &ConfigJson::from_bytes(&bytes)?)?;
+    }
+
     let mamba = {
         let timing = web_time::Instant::now();
-        let bytes = api
-            .repo(Repo::with_revision(
-                RepoId(model.repo_id.into()),
-                RepoType::Model,
-                RevisionPath(model.revision_path.into()),
-            ))
-            .get_bytes(&FilePath(model.file_path_model_safetensors.into()))
-            .await?;
+        let model_file = model.file_path_model_safetensors;
+        let bytes = repo.get_bytes(&FilePath(model_file.into())).await?;
+        let checkpoint = if model_file.ends_with(".index.json") {
+            let mut shards = Vec::new();
+            for shard in shard_files(&bytes)? {
+                shards.push(Checkpoint::Bytes(repo.get_bytes(&FilePath(shard)).await?));
+            }
+            Checkpoint::Sharded(shards)
+        } else {
+            Checkpoint::Bytes(bytes)
+        };
         log::info!("mamba data loaded in {}ms", timing.elapsed().as_millis());
 
         let timing = web_time::Instant::now();
         log::info!("initializing and loading mamba model");
-        let mamba = load_mamba(Checkpoint::Bytes(bytes), (model.config)(), &device)?;
+        let mamba = load_mamba(checkpoint, model.config(), precision, &device)?;
         log::info!(
             "mamba initialized and loaded in {}ms",
             timing.elapsed().as_millis()
@@ -135,5 +162,5 @@ pub async fn models(model: &'static ModelSpec) -> anyhow::Result<MambaWrapper> {
         mamba
     };
 
-    Ok(MambaWrapper::new(model, tokenizer, mamba))
+    MambaWrapper::new(model, tokenizer, mamba)
 }
@@ -63,7 +63,9 @@ pub enum Msg {
     StartModelBuild(ModelSelection),
     /// Concludes building a model from the model data.
     FinishModelBuild(ModelSelection),
-    FailModelBuild,
+    /// The data did not build into a model, or the models into a
+    /// [MambaWrapper](crate::MambaWrapper); the error is shown in the output.
+    FailModelBuild(String),
     /// If all required models are built, we move to the next step of being to use the models
     /// for inference (etc).
     TryFinilizeModelsBuilding,
@@ -3,8 +3,11 @@ use crate::hub::{
     Endpoint, FilePath, FileUrl, HubError, Metadata, Repo, RepoId, RepoType, RevisionPath,
     UrlTemplate,
 };
+use crate::stats::{GenerationStats, GenerationTimer};
 use crate::tokenizer::Tokenizer;
-use crate::{Checkpoint, LogitsProcessorWrapper, MambaWrapper, ModelSpec, hf, load_mamba};
+use crate::{
+    Checkpoint, LogitsProcessorWrapper, MambaWrapper, ModelSpec, RuntimePrecision, hf, load_mamba,
+};
 use burn::prelude::*;
 use burn_mamba::prelude::*;
 
@@ -54,6 +57,10 @@ pub struct Model {
     pub output: String,
     /// The token the model uses to signal the end of the generation.
     pub eos_token: usize,
+    /// Times the ongoing generation.
+    pub timer: Option<GenerationTimer>,
+    /// How the last finished generation went.
+    pub stats: Option<GenerationStats>,
 }
 
 impl Model {
@@ -77,7 +84,7 @@ impl Default for Model {
         let mut device = Device::default();
         {
             device
-                .configure((crate::PRECISION_FLOAT_D_TYPE, crate::PRECISION_INT_D_TYPE))
+                .configure(RuntimePrecision::default().device_dtypes())
                 .expect("Failed to install fp32/i32 device defaults");
         }
 
@@ -126,6 +133,8 @@ impl Default for Model {
             tokens: vec![],
             output: "".into(),
             eos_token: 0,
+            timer: None,
+            stats: None,
         }
     }
 }
@@ -150,13 +159,24 @@ impl MambaWrapperBuilder {
     pub fn is_ready(&self) -> bool {
         self.tokenizer.is_some() && self.mamba.is_some()
     }
-    pub fn build(self) -> Wrapper {
-        self.into()
+    /// Errors if the parts do not fit together, see [MambaWrapper::new].
+    pub fn build(self) -> anyhow::Result<Wrapper> {
+        match (self.spec, self.tokenizer, self.mamba) {
+            (Some(s), Some(t), Some(m)) => Ok(Wrapper::new(MambaWrapper::new(s, t, m)?)),
+            (None, _, _) => panic!("missing model spec"),
+            (_, None, _) => panic!("missing tokenizer"),
+            (_, _, None) => panic!("missing mamba"),
+        }
     }
-    pub fn with(&mut self, selection: &ModelSelection, data: Vec<u8>, device: &Device) {
+    pub fn with(
+        &mut self,
+        selection: &ModelSelection,
+        data: Vec<u8>,
+        device: &Device,
+    ) -> anyhow::Result<()> {
         match selection {
             ModelSelection::Tokenizer => {
-                let tokenizer = Tokenizer::from_bytes(&data).unwrap();
+                let tokenizer = Tokenizer::from_bytes(&data)?;
                 self.tokenizer = Some(tokenizer);
             }
             ModelSelection::Mamba => {
@@ -165,8 +185,12 @@ impl MambaWrapperBuilder {
                     log::info!("initializing and loading mamba model");
 
                     let spec = self.spec.expect("missing model spec");
-                    let mamba =
-                        load_mamba(Checkpoint::Bytes(data), (spec.config)(), device).unwrap();
+                    let mamba = load_mamba(
+                        Checkpoint::Bytes(data),
+                        spec.config(),
+                        RuntimePrecision::default(),
+                        device,
+                    )?;
                     log::info!(
                         "mamba initialized and loaded in {}ms",
                         timing.elapsed().as_millis()
@@ -177,6 +201,7 @@ impl MambaWrapperBuilder {
                 self.mamba = Some(mamba);
             }
         }
+        Ok(())
     }
     pub fn merge(self, other: Self) -> Self {
         Self {
@@ -187,20 +212,6 @@ impl MambaWrapperBuilder {
     }
 }
 
-impl From<MambaWrapperBuilder> for Wrapper {
-    fn from(value: MambaWrapperBuilder) -> Self {
-        match (value.spec, value.tokenizer, value.mamba) {
-            (Some(s), Some(t), Some(m)) => {
-                let models = MambaWrapper::new(s, t, m);
-                Wrapper::new(models)
-            }
-            (None, _, _) => panic!("missing model spec"),
-            (_, None, _) => panic!("missing tokenizer"),
-            (_, _, None) => panic!("missing mamba"),
-        }
-    }
-}
-
 pub enum Connection<T> {
     Disconnected,
     Connecting,
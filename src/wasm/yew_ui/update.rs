@@ -1,7 +1,10 @@
 use super::Msg;
 use super::model::ModelSelection;
 pub use super::model::{self, Connection, Model};
+use crate::config_json::{self, ConfigJson};
+use crate::hub::FilePath;
 use crate::hub::wasm::{Api, ChunkKey};
+use crate::stats::GenerationTimer;
 use yew::prelude::*;
 
 const TICK_MILLIS: u32 = 1;
@@ -162,7 +165,30 @@ impl model::Model {
             Msg::FinishModelDataLoad(selection, data) => {
                 let model_data = self.select_mut(&selection);
                 model_data.load.data = data;
-                ctx.link().send_message(Msg::StartModelBuild(selection));
+                if selection != ModelSelection::Mamba {
+                    ctx.link().send_message(Msg::StartModelBuild(selection));
+                    return false;
+                }
+                // the weights only build into the spec's config, so check that the
+                // repo's config.json still describes it before building
+                let api = self.cache_api.as_connected().unwrap().clone();
+                let repo = self.mamba.config.api_repo(&api);
+                let spec = self.spec;
+                ctx.link().send_future(async move {
+                    let checked = async {
+                        let bytes = repo
+                            .get_bytes(&FilePath(spec.file_path_config_json.into()))
+                            .await?;
+                        config_json::check_spec(spec, &ConfigJson::from_bytes(&bytes)?)
+                    };
+                    match checked.await {
+                        Ok(()) => Msg::StartModelBuild(selection),
+                        Err(err) => Msg::FailModelBuild(format!(
+                            "{} does not match its config.json: {err:#}",
+                            spec.display_name
+                        )),
+                    }
+                });
                 false
             }
             Msg::FailModelDataLoad(selection, err) => {
@@ -262,9 +288,15 @@ impl model::Model {
             Msg::StartModelBuild(selection) => {
                 let model_data = self.select_mut(&selection);
                 let data = std::mem::take(&mut model_data.load.data);
-                self.models_wrapper_builder
-                    .with(&selection, data, &self.device);
-                ctx.link().send_message(Msg::FinishModelBuild(selection));
+                match self
+                    .models_wrapper_builder
+                    .with(&selection, data, &self.device)
+                {
+                    Ok(()) => ctx.link().send_message(Msg::FinishModelBuild(selection)),
+                    Err(err) => ctx.link().send_message(Msg::FailModelBuild(format!(
+                        "failed to build {selection:?}: {err:#}"
+                    ))),
+                }
                 false
             }
             Msg::FinishModelBuild(selection) => {
@@ -274,15 +306,21 @@ impl model::Model {
                 ctx.link().send_message(Msg::TryFinilizeModelsBuilding);
                 true
             }
-            Msg::FailModelBuild => {
-                todo!()
+            Msg::FailModelBuild(err) => {
+                log::error!("{err}");
+                self.output = err;
+                true
             }
             Msg::TryFinilizeModelsBuilding => {
                 // consume the built models if they are all ready
                 if self.models_wrapper_builder.is_ready() {
                     let builder = std::mem::take(&mut self.models_wrapper_builder);
-                    let wrapper = builder.build();
-                    self.models_wrapper = Some(wrapper);
+                    match builder.build() {
+                        Ok(wrapper) => self.models_wrapper = Some(wrapper),
+                        Err(err) => ctx.link().send_message(Msg::FailModelBuild(format!(
+                            "failed to build the models: {err:#}"
+                        ))),
+                    }
                 }
                 true
             }
@@ -312,6 +350,8 @@ impl model::Model {
                 let models_wrapper = self.models_wrapper.as_mut().unwrap();
                 let (tokens, eos_token) = models_wrapper.models.reset_prompt(&self.input).unwrap();
                 // TODO: reset preprocessor
+                self.timer = Some(GenerationTimer::start(tokens.len()));
+                self.stats = None;
                 self.tokens = tokens;
                 self.eos_token = eos_token;
 
@@ -344,10 +384,24 @@ impl model::Model {
                     )
                     .unwrap();
                 models_wrapper.caches = next_caches;
+                let prompt_len = self.timer.as_ref().map_or(0, |t| t.prompt_tokens());
+                let generating = self.step + 1 >= prompt_len;
                 let next_token = models_wrapper
                     .processor
                     .add_logits(self.step, &mut self.tokens, next_logits)
                     .unwrap();
+                let finish_reason = crate::common::finish_reason_of(
+                    next_token,
+                    self.eos_token,
+                    &models_wrapper.processor,
+                );
+                if let Some(timer) = self.timer.as_mut().filter(|_| generating) {
+                    if finish_reason.is_none() {
+                        timer.token();
+                    } else {
+                        timer.prefilled();
+                    }
+                }
 
                 // if the token has some valid representation, print it
                 if let Some(t) = models.tokenizer.next_token(next_token as u32) {
@@ -355,12 +409,17 @@ impl model::Model {
                 }
                 self.step += 1;
 
-                if next_token == self.eos_token {
+                if let Some(finish_reason) = finish_reason {
                     self.is_generating = false;
 
                     if let Some(rest) = models.tokenizer.decode_rest() {
                         self.output += &rest;
                     }
+                    if let Some(timer) = self.timer.take() {
+                        let stats = timer.finish(finish_reason);
+                        log::info!("generation: {stats}");
+                        self.stats = Some(stats);
+                    }
 
                     true
                 } else {
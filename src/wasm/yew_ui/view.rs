@@ -60,7 +60,9 @@ impl model::Model {
                 placeholder="The continuation prediction will appear in here.."
                 value={self.output.clone()}
             />
-            <label class="help">{"TODO"}</label>
+            <label class="help">
+                {self.stats.as_ref().map(ToString::to_string).unwrap_or_default()}
+            </label>
             </div>
         };
         let controls = html_nested! {
@@ -0,0 +1,125 @@
+//! Tests that need the device configured to a half-precision default.
+//!
+//! `Device::configure` is process-global and refuses a second call, so these
+//! live in a test binary of their own rather than next to the unit tests, which
+//! all run at the f32 default.
+#![cfg(any(feature = "mamba1", feature = "mamba2", feature = "mamba3"))]
+
+use burn::prelude::*;
+use burn::tensor::FloatDType;
+use burn_mamba::prelude::*;
+use burn_mamba_example::sampling::device::DeviceSampler;
+use burn_mamba_example::sampling::{BatchLogitsProcessor, Sampling};
+use burn_mamba_example::verify::{
+    byte_tokenizer, forward_logits, step_logits, tiny_specs, token_ids,
+};
+use burn_mamba_example::{
+    LogitsProcessorWrapper, MambaWrapper, ModelSpec, RuntimePrecision, cast_mamba, hf, tie_lm_head,
+};
+
+const PRECISION: RuntimePrecision = RuntimePrecision::Bf16 { mixed: true };
+
+/// The default device, configured to bf16 by whichever test gets here first.
+fn device() -> Device {
+    static CONFIGURE: std::sync::Once = std::sync::Once::new();
+    let mut device: Device = Default::default();
+    CONFIGURE.call_once(|| device.configure(PRECISION.device_dtypes()).unwrap());
+    device
+}
+
+#[test]
+fn device_sampling_reads_large_ids_back_exactly() {
+    let device = device();
+
+    // past f16's largest finite value, and nowhere near what bf16 holds exactly
+    let (vocab, id) = (70_016, 69_999);
+    let mut logits = vec![0f32; vocab];
+    logits[id] = 8.;
+    let logits = Tensor::<1>::from_data(logits.as_slice(), &device)
+        .cast(FloatDType::BF16)
+        .unsqueeze();
+
+    let (token, log_prob) = DeviceSampler::new(0, Sampling::ArgMax)
+        .sample(logits, &[])
+        .unwrap();
+    assert_eq!(token, id as u32);
+    assert!(log_prob.is_finite() && log_prob < 0., "{log_prob}");
+}
+
+/// A bf16-mixed model — bf16 activations and weights, f32 decay, skip and
+/// norms — runs both its forward and its step to finite logits.
+#[test]
+fn mixed_precision_runs_forward_and_step() {
+    let device = device();
+    for spec in hf::MODELS {
+        let mut config = spec.config();
+        let n_real_layers = match &mut config {
+            #[cfg(feature = "mamba1")]
+            MambaVocabNetConfig::Mamba1 { n_real_layers, .. } => n_real_layers,
+            #[cfg(feature = "mamba2")]
+            MambaVocabNetConfig::Mamba2 { n_real_layers, .. } => n_real_layers,
+            #[cfg(feature = "mamba3")]
+            MambaVocabNetConfig::Mamba3 { n_real_layers, .. } => n_real_layers,
+        };
+        *n_real_layers = 2;
+        let mut mamba = config.init(&device);
+        cast_mamba(&mut mamba, PRECISION).unwrap();
+        tie_lm_head(&mut mamba, &device);
+
+        let tokens = token_ids(8, 1000);
+        let forward = forward_logits(&mamba, &tokens, spec.ssd_path()).unwrap();
+        let step = step_logits(&mamba, &tokens).unwrap();
+        assert_eq!(forward.len(), step.len(), "{}", spec.id);
+        for (path, logits) in [("forward", &forward), ("step", &step)] {
+            let non_finite = logits.iter().filter(|v| !v.is_finite()).count();
+            assert_eq!(non_finite, 0, "{}: {path}", spec.id);
+        }
+    }
+}
+
+/// A bf16-mixed [MambaWrapper] over a random `spec` model, whose every run
+/// path reads half-precision logits back to the host.
+fn mixed_wrapper(spec: &'static ModelSpec) -> MambaWrapper {
+    let device = device();
+    let mut mamba = spec.config().init(&device);
+    cast_mamba(&mut mamba, PRECISION).unwrap();
+    tie_lm_head(&mut mamba, &device);
+    MambaWrapper::new(spec, byte_tokenizer(), mamba).unwrap()
+}
+
+#[test]
+fn parallel_run_reads_half_precision_logits_back() {
+    for spec in tiny_specs() {
+        let mut processor = LogitsProcessorWrapper::new(0, None, None, 1., 0);
+        let stats = mixed_wrapper(spec)
+            .run_parallel("the cat", 4, &mut processor)
+            .unwrap_or_else(|e| panic!("{}: {e:#}", spec.id));
+        assert_eq!(stats.prompt_tokens, 7, "{}", spec.id);
+    }
+}
+
+#[test]
+fn batched_run_reads_half_precision_logits_back() {
+    for spec in tiny_specs() {
+        let mut sampler = BatchLogitsProcessor::default()
+            .with_row(0, Sampling::ArgMax, 1.)
+            .with_row(1, Sampling::All { temperature: 1. }, 1.);
+        let completions = mixed_wrapper(spec)
+            .run_sequential_n("the cat", 4, &mut sampler, 0)
+            .unwrap_or_else(|e| panic!("{}: {e:#}", spec.id));
+        assert_eq!(completions.len(), 2, "{}", spec.id);
+    }
+}
+
+#[test]
+fn log_likelihoods_read_half_precision_log_probs_back() {
+    for spec in tiny_specs() {
+        let scores = mixed_wrapper(spec)
+            .log_likelihoods(&[("the cat", " sat"), ("the cat", " ran")])
+            .unwrap_or_else(|e| panic!("{}: {e:#}", spec.id));
+        for score in scores {
+            assert_eq!(score.tokens, 4, "{}", spec.id);
+            assert!(score.log_prob.is_finite(), "{}: {score:?}", spec.id);
+        }
+    }
+}